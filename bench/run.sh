@@ -0,0 +1,36 @@
+#!/usr/bin/env bash
+# Times every script in this directory on a release build.
+#
+#   bench/run.sh            time the working tree
+#   bench/run.sh <rev>      also time git revision <rev>, to compare against it
+#
+# Flags in $LOXER_FLAGS are passed to every binary, e.g. LOXER_FLAGS=--vm bench/run.sh
+set -euo pipefail
+
+root="$(cd "$(dirname "$0")/.." && pwd)"
+cd "$root"
+
+cargo build --release --quiet
+binaries=("$root/target/release/loxer")
+names=("working tree")
+read -ra flags <<< "${LOXER_FLAGS:-}"
+
+if [ $# -gt 0 ]; then
+    baseline="$(mktemp -d)"
+    trap 'git worktree remove --force "$baseline"' EXIT
+    git worktree add --detach --quiet "$baseline" "$1"
+    cargo build --release --quiet --manifest-path "$baseline/Cargo.toml"
+    binaries+=("$baseline/target/release/loxer")
+    names+=("$1")
+fi
+
+for script in "$root"/bench/*.lox; do
+    echo "$(basename "$script")"
+    for i in "${!binaries[@]}"; do
+        start=$(date +%s.%N)
+        "${binaries[$i]}" ${flags[@]+"${flags[@]}"} "$script" > /dev/null
+        end=$(date +%s.%N)
+        awk -v name="${names[$i]}" -v s="$start" -v e="$end" \
+            'BEGIN { printf "  %-14s %6.3fs\n", name, e - s }'
+    done
+done
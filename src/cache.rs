@@ -0,0 +1,334 @@
+use std::rc::Rc;
+
+use crate::{
+    chunk::Chunk,
+    diagnostic::{Diagnostic, Label},
+    error_code::ErrorCode,
+    token::Span,
+    value::{Function, Value},
+};
+
+/// First bytes of every `.loxc` file
+const MAGIC: &[u8; 4] = b"LOXC";
+/// Bump whenever `OpCode` or the layout below changes, so older caches are recompiled.
+const FORMAT_VERSION: u32 = 6;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+
+/// Why a cache file could not be used. In every case the script is compiled again.
+#[derive(Debug, PartialEq)]
+pub enum CacheError {
+    /// The file doesn't start with `MAGIC`
+    NotACache,
+    /// Written by a build with a different `FORMAT_VERSION`
+    WrongVersion,
+    /// Compiled from a different version of the source, or with different options
+    Stale,
+    /// Truncated, or otherwise not something `encode` wrote
+    Corrupt,
+}
+
+/// Serialises the compiled `script` and the `warnings` reported while compiling it, so that a
+/// cached run reports them too. Tagged with a hash of the `source` it was compiled from and the
+/// command-line `options` that change the result, such as `--optimize` and `--allow`.
+///
+/// Layout: magic, version (u32) and hash (u64), then the script function and the warnings. A
+/// function is its name, arity, upvalue count, code, constants and span runs. A warning is its
+/// code, message, span and labels, and a span its start, end, line and column. Numbers are
+/// little-endian, lengths and counts u32, strings length-prefixed UTF-8.
+pub fn encode(script: &Function, warnings: &[Diagnostic], source: &str, options: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    out.extend_from_slice(&hash(source, options).to_le_bytes());
+    write_function(&mut out, script);
+    write_u32(&mut out, warnings.len());
+    for warning in warnings {
+        write_warning(&mut out, warning);
+    }
+    out
+}
+
+/// Loads a script and its warnings `encode` wrote, provided it was compiled from this `source`
+/// with these `options`.
+pub fn decode(
+    bytes: &[u8],
+    source: &str,
+    options: &str,
+) -> Result<(Rc<Function>, Vec<Diagnostic>), CacheError> {
+    let mut reader = Reader { bytes, position: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(CacheError::NotACache);
+    }
+    if reader.u32()? != FORMAT_VERSION {
+        return Err(CacheError::WrongVersion);
+    }
+    if reader.u64()? != hash(source, options) {
+        return Err(CacheError::Stale);
+    }
+    let script = reader.function()?;
+    let count = reader.len()?;
+    let warnings = (0..count)
+        .map(|_| reader.warning())
+        .collect::<Result<_, _>>()?;
+    if reader.position != bytes.len() {
+        return Err(CacheError::Corrupt);
+    }
+    Ok((Rc::new(script), warnings))
+}
+
+/// 64-bit FNV-1a of the source, a separator and the options. `DefaultHasher` isn't guaranteed
+/// to be stable between Rust releases.
+fn hash(source: &str, options: &str) -> u64 {
+    let bytes = source.bytes().chain([0]).chain(options.bytes());
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+    let value = u32::try_from(value).expect("Chunk too large to cache.");
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u32(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+    write_str(out, &function.name);
+    write_u32(out, function.arity);
+    write_u32(out, function.upvalue_count);
+
+    let chunk = &function.chunk;
+    write_u32(out, chunk.code.len());
+    out.extend_from_slice(&chunk.code);
+    write_u32(out, chunk.constants.len());
+    for constant in &chunk.constants {
+        match constant {
+            Value::Number(n) => {
+                out.push(TAG_NUMBER);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Value::String(s) => {
+                out.push(TAG_STRING);
+                write_str(out, s);
+            }
+            Value::Function(function) => {
+                out.push(TAG_FUNCTION);
+                write_function(out, function);
+            }
+            // The compiler only puts the three kinds above in constant pools
+            _ => unreachable!("Runtime value {constant} in a constant pool."),
+        }
+    }
+    write_u32(out, chunk.span_runs().len());
+    for &(offset, span) in chunk.span_runs() {
+        write_u32(out, offset);
+        write_span(out, span);
+    }
+}
+
+fn write_span(out: &mut Vec<u8>, span: Span) {
+    for value in [span.start, span.end, span.line, span.column] {
+        write_u32(out, value);
+    }
+}
+
+fn write_warning(out: &mut Vec<u8>, warning: &Diagnostic) {
+    write_str(out, warning.code.code());
+    write_str(out, &warning.message);
+    // Warnings are always about a place in the source
+    write_span(out, warning.span.unwrap_or_default());
+    write_u32(out, warning.labels.len());
+    for label in &warning.labels {
+        write_span(out, label.span);
+        write_str(out, &label.message);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CacheError> {
+        let end = self.position.checked_add(len).ok_or(CacheError::Corrupt)?;
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or(CacheError::Corrupt)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CacheError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, CacheError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, CacheError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, CacheError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, CacheError> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CacheError::Corrupt)
+    }
+
+    fn span(&mut self) -> Result<Span, CacheError> {
+        Ok(Span {
+            start: self.len()?,
+            end: self.len()?,
+            line: self.len()?,
+            column: self.len()?,
+        })
+    }
+
+    fn warning(&mut self) -> Result<Diagnostic, CacheError> {
+        let code = ErrorCode::from_code(&self.string()?).ok_or(CacheError::Corrupt)?;
+        let message = self.string()?;
+        let mut warning = Diagnostic::warning(code, message, self.span()?);
+        let count = self.len()?;
+        for _ in 0..count {
+            let span = self.span()?;
+            warning.labels.push(Label::new(span, &self.string()?));
+        }
+        Ok(warning)
+    }
+
+    fn function(&mut self) -> Result<Function, CacheError> {
+        let name = self.string()?;
+        let arity = self.len()?;
+        let upvalue_count = self.len()?;
+
+        let len = self.len()?;
+        let code = self.take(len)?.to_vec();
+        let count = self.len()?;
+        let mut constants = Vec::new();
+        for _ in 0..count {
+            let [tag] = self.array()?;
+            constants.push(match tag {
+                TAG_NUMBER => Value::Number(f64::from_le_bytes(self.array()?)),
+                TAG_STRING => Value::String(self.string()?.into()),
+                TAG_FUNCTION => Value::Function(Rc::new(self.function()?)),
+                _ => return Err(CacheError::Corrupt),
+            });
+        }
+        let count = self.len()?;
+        let mut spans = Vec::new();
+        for _ in 0..count {
+            spans.push((self.len()?, self.span()?));
+        }
+
+        Ok(Function {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk::from_parts(code, constants, spans),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, encode, CacheError};
+    use crate::{
+        compiler::Compiler,
+        diagnostic::{Diagnostic, Label},
+        disassembler::disassemble,
+        error_code::ErrorCode,
+        interpreter::Interpreter,
+        parser::Parser,
+        resolver::Resolver,
+        scanner::Scanner,
+        token::Span,
+    };
+
+    const SOURCE: &str = r#"
+        class Greeter {
+            init(name) { this.name = name; }
+            greet() { return "Hello, " + this.name + "!"; }
+        }
+        fun twice(f) {
+            fun apply(x) { return f(f(x)); }
+            return apply;
+        }
+        fun grow(n) { return n * 1.5; }
+        print Greeter("cache").greet();
+        print twice(grow)(2);
+    "#;
+
+    fn compile(source: &str) -> std::rc::Rc<crate::value::Function> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        Resolver::new(&mut Interpreter::new())
+            .resolve_stmts(&statements)
+            .unwrap();
+        Compiler::new().compile(&statements).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let script = compile(SOURCE);
+        let span = |line, column| Span {
+            start: 10,
+            end: 12,
+            line,
+            column,
+        };
+        let mut warning = Diagnostic::warning(
+            ErrorCode::Shadowing,
+            "'x' shadows a \"variable\".".to_string(),
+            span(3, 5),
+        );
+        warning
+            .labels
+            .push(Label::new(span(1, 9), "shadowed declaration"));
+        let warnings = vec![warning];
+
+        let bytes = encode(&script, &warnings, SOURCE, "");
+        let (loaded, loaded_warnings) = decode(&bytes, SOURCE, "").unwrap();
+        assert_eq!(disassemble(&loaded), disassemble(&script));
+        assert_eq!(loaded.chunk.span_runs(), script.chunk.span_runs());
+        assert_eq!(loaded_warnings, warnings);
+    }
+
+    #[test]
+    fn test_rejected_caches() {
+        let bytes = encode(&compile(SOURCE), &[], SOURCE, "");
+        let stale = Some(CacheError::Stale);
+        assert_eq!(decode(&bytes, "print 1;", "").err(), stale);
+        assert_eq!(decode(&bytes, SOURCE, "--optimize").err(), stale);
+        assert_eq!(
+            decode(b"#!/usr/bin/env lox", SOURCE, "").err(),
+            Some(CacheError::NotACache)
+        );
+
+        let mut old = bytes.clone();
+        old[4] = old[4].wrapping_add(1);
+        let wrong_version = Some(CacheError::WrongVersion);
+        assert_eq!(decode(&old, SOURCE, "").err(), wrong_version);
+
+        for len in [10, bytes.len() / 2, bytes.len() - 1] {
+            let corrupt = decode(&bytes[..len], SOURCE, "");
+            assert_eq!(corrupt.err(), Some(CacheError::Corrupt));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        let corrupt = decode(&trailing, SOURCE, "");
+        assert_eq!(corrupt.err(), Some(CacheError::Corrupt));
+    }
+}
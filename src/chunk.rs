@@ -0,0 +1,235 @@
+use crate::{token::Span, value::Value};
+
+/// Instructions of the bytecode VM. Operands follow the opcode byte: constant and name indices
+/// and jump offsets take two bytes (big-endian), slots and argument counts one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// constant
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// slot
+    GetLocal,
+    /// slot
+    SetLocal,
+    /// name
+    GetGlobal,
+    /// name
+    DefineGlobal,
+    /// name
+    SetGlobal,
+    /// index
+    GetUpvalue,
+    /// index
+    SetUpvalue,
+    /// name
+    GetProperty,
+    /// name
+    SetProperty,
+    /// name: reads a private member of the instance below the class whose body accesses it
+    GetPrivate,
+    /// name: like `GetPrivate`, with the value to store between the instance and the class
+    SetPrivate,
+    /// name: binds a method of the superclass on top of the stack to the instance below it
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// offset
+    Jump,
+    /// offset
+    JumpIfFalse,
+    /// offset, backwards
+    Loop,
+    /// argument count
+    Call,
+    /// argument count: like `Call`, but reuses the caller's frame when calling a Lox function
+    TailCall,
+    /// name, argument count: calls a property without binding it first
+    Invoke,
+    /// function constant, then a pair of bytes for each upvalue: whether it captures a local of
+    /// the enclosing function (or one of its upvalues), and the slot or index
+    Closure,
+    CloseUpvalue,
+    Return,
+    /// name
+    Class,
+    /// Makes the class on top of the stack inherit from the value below it
+    Inherit,
+    /// name: adds the closure on top of the stack to the class below it
+    Method,
+    /// name: declares a method a subclass has to implement
+    AbstractMethod,
+    /// Registers the closure on top of the stack to run when its block exits
+    Defer,
+    /// Calls the closure registered last by `Defer`, which leaves its result on the stack
+    RunDeferred,
+    /// name, variant count, then for each variant its name, field count and field names
+    Enum,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 46] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetPrivate,
+        OpCode::SetPrivate,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::TailCall,
+        OpCode::Invoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::AbstractMethod,
+        OpCode::Defer,
+        OpCode::RunDeferred,
+        OpCode::Enum,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+}
+
+/// Compiled code of one function, with the values it uses and the source span of every byte
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Runs of bytes compiled from the same span, as (offset of the first byte, span)
+    spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    /// Rebuilds a chunk from the parts `span_runs` and the public fields expose
+    pub fn from_parts(code: Vec<u8>, constants: Vec<Value>, spans: Vec<(usize, Span)>) -> Self {
+        Self {
+            code,
+            constants,
+            spans,
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        if self.spans.last().is_none_or(|&(_, last)| last != span) {
+            self.spans.push((self.code.len(), span));
+        }
+        self.code.push(byte);
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        let [high, low] = value.to_be_bytes();
+        self.write(high, span);
+        self.write(low, span);
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Adds a value to the constant pool and returns its index, or `None` once the pool is full.
+    pub fn add_constant(&mut self, value: Value) -> Option<u16> {
+        let index = u16::try_from(self.constants.len()).ok()?;
+        self.constants.push(value);
+        Some(index)
+    }
+
+    pub fn span_runs(&self) -> &[(usize, Span)] {
+        &self.spans
+    }
+
+    pub fn span_at(&self, offset: usize) -> Span {
+        let run = self.spans.partition_point(|&(start, _)| start <= offset);
+        self.spans[run.saturating_sub(1)].1
+    }
+
+    pub fn line_at(&self, offset: usize) -> usize {
+        self.span_at(offset).line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, OpCode};
+    use crate::token::Span;
+
+    #[test]
+    fn test_opcode_bytes() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as u8 as usize, byte);
+            assert_eq!(OpCode::from_byte(byte as u8), Some(*op));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::ALL.len() as u8), None);
+    }
+
+    #[test]
+    fn test_span_table() {
+        let span = |column| Span {
+            start: column - 1,
+            end: column,
+            line: 4,
+            column,
+        };
+        let mut chunk = Chunk::default();
+        chunk.write_op(OpCode::Nil, Span::line(1));
+        chunk.write_op(OpCode::Pop, Span::line(1));
+        chunk.write_u16(300, span(3));
+        chunk.write_op(OpCode::Add, span(9));
+        chunk.write_op(OpCode::Return, Span::line(7));
+        let lines: Vec<_> = (0..chunk.code.len()).map(|i| chunk.line_at(i)).collect();
+        assert_eq!(lines, vec![1, 1, 4, 4, 4, 7]);
+        assert_eq!(chunk.span_at(3), span(3));
+        assert_eq!(chunk.span_at(4), span(9));
+        assert_eq!(chunk.span_runs().len(), 4);
+        assert_eq!(chunk.read_u16(2), 300);
+    }
+}
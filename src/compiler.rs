@@ -0,0 +1,805 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    error_code::ErrorCode,
+    expr::{Expr, Literal},
+    lox_class::CLASS_BINDING,
+    lox_result::{LoxResult, ParseErrorCause},
+    stmt::{ClassStmt, EnumStmt, FunctionStmt, Stmt},
+    token::{Span, Token, TokenType},
+    value::{Function, Value},
+};
+
+/// Locals and upvalues are addressed with a single byte
+const MAX_SLOTS: usize = u8::MAX as usize + 1;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct LocalVariable {
+    name: String,
+    depth: usize,
+    /// Captured variables are moved off the stack when they go out of scope
+    captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct UpvalueRef {
+    index: u8,
+    is_local: bool,
+}
+
+struct Loop {
+    label: Option<String>,
+    /// Number of locals alive when the loop started, which a jump out of it leaves behind
+    locals: usize,
+    /// Number of deferred expressions registered when the loop started, which a jump out of it
+    /// runs the rest of
+    deferred: usize,
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+struct FunctionState {
+    function: Function,
+    kind: FunctionKind,
+    locals: Vec<LocalVariable>,
+    upvalues: Vec<UpvalueRef>,
+    scope_depth: usize,
+    /// Scope depth of each `defer` in the scopes being compiled, which run when they exit
+    deferred: Vec<usize>,
+    loops: Vec<Loop>,
+    /// Constant pool index of every name used, so that each is stored once
+    names: HashMap<String, u16>,
+}
+
+impl FunctionState {
+    fn new(name: &str, arity: usize, kind: FunctionKind) -> Self {
+        // Slot 0 holds the receiver in methods and the called function otherwise
+        let receiver = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        Self {
+            function: Function {
+                name: name.to_string(),
+                arity,
+                ..Function::default()
+            },
+            kind,
+            locals: vec![LocalVariable {
+                name: receiver.to_string(),
+                depth: 0,
+                captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            deferred: Vec::new(),
+            loops: Vec::new(),
+            names: HashMap::new(),
+        }
+    }
+}
+
+/// Compiles a resolved program to bytecode for the `Vm`. Programs are expected to have passed
+/// the `Resolver`, which reports misplaced `return`, `this` and the like.
+pub struct Compiler {
+    functions: Vec<FunctionState>,
+    /// Names of the classes whose bodies enclose the code being compiled
+    classes: Vec<String>,
+    /// Span of the code being compiled, recorded for every byte emitted
+    span: Span,
+    errors: Vec<ParseErrorCause>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![FunctionState::new("", 0, FunctionKind::Script)],
+            classes: Vec::new(),
+            span: Span::line(1),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Compiles the statements into the function run for the whole script
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Rc<Function>, LoxResult> {
+        for s in statements {
+            self.statement(s);
+        }
+        self.emit_return();
+
+        if self.errors.is_empty() {
+            let state = self.functions.pop().unwrap();
+            Ok(Rc::new(state.function))
+        } else {
+            Err(LoxResult::ParseError {
+                causes: self.errors,
+            })
+        }
+    }
+
+    fn statement(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Block(s) => {
+                self.begin_scope();
+                for s in &s.statements {
+                    self.statement(s);
+                }
+                self.end_scope();
+            }
+            Stmt::Break(s) => self.jump_out_of_loop(&s.keyword, &s.label, true),
+            Stmt::Class(s) => self.class(s),
+            Stmt::Continue(s) => self.jump_out_of_loop(&s.keyword, &s.label, false),
+            Stmt::Defer(s) => {
+                // The expression becomes a closure, so that it can run later in the same scope
+                self.span = s.keyword.span;
+                let mut state = FunctionState::new("defer", 0, FunctionKind::Function);
+                state.scope_depth = 1;
+                self.functions.push(state);
+                self.expression(&s.expression);
+                self.emit(OpCode::Return);
+                let state = self.functions.pop().unwrap();
+                self.span = s.keyword.span;
+                self.emit_closure(state);
+                self.emit(OpCode::Defer);
+                let depth = self.current().scope_depth;
+                self.current().deferred.push(depth);
+            }
+            Stmt::DoWhile(s) => {
+                let start = self.chunk().code.len();
+                self.begin_loop(&s.label);
+                self.statement(&s.body);
+                let finished = self.current().loops.pop().unwrap();
+                finished
+                    .continues
+                    .into_iter()
+                    .for_each(|j| self.patch_jump(j));
+
+                self.expression(&s.condition);
+                let exit = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.emit_loop(start);
+                self.patch_jump(exit);
+                self.emit(OpCode::Pop);
+                finished.breaks.into_iter().for_each(|j| self.patch_jump(j));
+            }
+            Stmt::Enum(s) => self.enumeration(s),
+            Stmt::Expression(s) => {
+                self.expression(&s.expression);
+                self.emit(OpCode::Pop);
+            }
+            Stmt::Function(s) => {
+                self.span = s.name.span;
+                if self.current().scope_depth > 0 {
+                    // Declared before the body is compiled, so that the function can call itself
+                    self.add_local(&s.name);
+                    self.function(s, FunctionKind::Function);
+                } else {
+                    self.function(s, FunctionKind::Function);
+                    let name = self.name_constant(&s.name.lexeme);
+                    self.emit_with_u16(OpCode::DefineGlobal, name);
+                }
+            }
+            Stmt::If(s) => {
+                self.expression(&s.condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.statement(&s.then_branch);
+                let else_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(then_jump);
+                self.emit(OpCode::Pop);
+                if let Some(else_branch) = &s.else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump);
+            }
+            Stmt::Print(s) => {
+                self.expression(&s.expression);
+                self.emit(OpCode::Print);
+            }
+            Stmt::Return(s) => {
+                self.span = s.keyword.span;
+                match &s.value {
+                    Some(Expr::Call(call)) if s.tail_call => {
+                        self.expression(&call.callee);
+                        for argument in &call.arguments {
+                            self.expression(argument);
+                        }
+                        self.span = call.paren.span;
+                        self.emit_with_byte(OpCode::TailCall, call.arguments.len() as u8);
+                        // Only reached when the callee isn't a Lox function and was called as usual
+                        self.emit(OpCode::Return);
+                    }
+                    Some(value) => {
+                        self.expression(value);
+                        self.run_deferred(0);
+                        self.emit(OpCode::Return);
+                    }
+                    None => self.emit_return(),
+                }
+            }
+            Stmt::Var(s) => {
+                self.span = s.name.span;
+                match &s.initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(OpCode::Nil),
+                }
+                self.span = s.name.span;
+                if self.current().scope_depth > 0 {
+                    self.add_local(&s.name);
+                } else {
+                    let name = self.name_constant(&s.name.lexeme);
+                    self.emit_with_u16(OpCode::DefineGlobal, name);
+                }
+            }
+            Stmt::While(s) => {
+                let start = self.chunk().code.len();
+                self.expression(&s.condition);
+                let exit = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.begin_loop(&s.label);
+                self.statement(&s.body);
+                let finished = self.current().loops.pop().unwrap();
+                finished
+                    .continues
+                    .into_iter()
+                    .for_each(|j| self.patch_jump(j));
+
+                if let Some(increment) = &s.increment {
+                    self.expression(increment);
+                    self.emit(OpCode::Pop);
+                }
+                self.emit_loop(start);
+                self.patch_jump(exit);
+                self.emit(OpCode::Pop);
+                finished.breaks.into_iter().for_each(|j| self.patch_jump(j));
+            }
+        }
+    }
+
+    fn class(&mut self, s: &ClassStmt) {
+        self.span = s.name.span;
+        let name = self.name_constant(&s.name.lexeme);
+        self.emit_with_u16(OpCode::Class, name);
+        if self.current().scope_depth > 0 {
+            self.add_local(&s.name);
+        } else {
+            self.emit_with_u16(OpCode::DefineGlobal, name);
+        }
+        self.classes.push(s.name.lexeme.clone());
+
+        // Methods capture the class, for private access, and `super` from a scope of their own,
+        // like in the tree-walker
+        self.begin_scope();
+        self.variable(&s.name.lexeme);
+        self.add_local(&Token::new(
+            TokenType::Identifier(CLASS_BINDING.to_string()),
+            CLASS_BINDING.to_string(),
+            s.name.line,
+        ));
+        if let Some(superclass) = &s.superclass {
+            self.span = superclass.name.span;
+            self.variable(&superclass.name.lexeme);
+            self.add_local(&Token::new(
+                TokenType::Super,
+                "super".to_string(),
+                superclass.name.line,
+            ));
+            self.variable(&s.name.lexeme);
+            self.emit(OpCode::Inherit);
+        }
+
+        self.variable(&s.name.lexeme);
+        for method in &s.abstract_methods {
+            let name = self.name_constant(&method.name.lexeme);
+            self.emit_with_u16(OpCode::AbstractMethod, name);
+        }
+        for method in &s.methods {
+            if let Stmt::Function(f) = method {
+                let kind = if f.name.lexeme == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                self.function(f, kind);
+                let name = self.name_constant(&f.name.lexeme);
+                self.emit_with_u16(OpCode::Method, name);
+            }
+        }
+        self.emit(OpCode::Pop);
+
+        self.end_scope();
+        self.classes.pop();
+    }
+
+    /// Compiles the function's body on its own and leaves a closure over it on the stack
+    fn function(&mut self, f: &FunctionStmt, kind: FunctionKind) {
+        let mut state = FunctionState::new(&f.name.lexeme, f.params.len(), kind);
+        state.scope_depth = 1;
+        self.functions.push(state);
+        for param in &f.params {
+            self.add_local(param);
+        }
+        for s in &f.body {
+            self.statement(s);
+        }
+        self.emit_return();
+
+        let state = self.functions.pop().unwrap();
+        self.span = f.name.span;
+        self.emit_closure(state);
+    }
+
+    /// Leaves a closure over the function compiled with `state` on the stack
+    fn emit_closure(&mut self, state: FunctionState) {
+        let mut function = state.function;
+        function.upvalue_count = state.upvalues.len();
+        let constant = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit_with_u16(OpCode::Closure, constant);
+        for upvalue in state.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    /// Enums are built by a single instruction, and defined like classes
+    fn enumeration(&mut self, s: &EnumStmt) {
+        self.span = s.name.span;
+        let name = self.name_constant(&s.name.lexeme);
+        if s.variants.len() > u8::MAX as usize {
+            self.error(
+                &s.name,
+                ErrorCode::CompilerLimit,
+                "Too many variants in one enum.",
+            );
+            return;
+        }
+        let mut operands = vec![s.variants.len() as u8];
+        for variant in &s.variants {
+            if variant.fields.len() > u8::MAX as usize {
+                let message = "Too many fields in one variant.";
+                self.error(&variant.name, ErrorCode::CompilerLimit, message);
+                return;
+            }
+            operands.extend(self.name_constant(&variant.name.lexeme).to_be_bytes());
+            operands.push(variant.fields.len() as u8);
+            for field in &variant.fields {
+                operands.extend(self.name_constant(&field.lexeme).to_be_bytes());
+            }
+        }
+        self.span = s.name.span;
+        self.emit_with_u16(OpCode::Enum, name);
+        operands.into_iter().for_each(|byte| self.emit_byte(byte));
+        if self.current().scope_depth > 0 {
+            self.add_local(&s.name);
+        } else {
+            self.emit_with_u16(OpCode::DefineGlobal, name);
+        }
+    }
+
+    fn expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign(e) => {
+                self.expression(&e.value);
+                self.span = e.name.span;
+                self.assign_variable(&e.name.lexeme);
+            }
+            Expr::Binary(e) => {
+                self.expression(&e.left);
+                self.expression(&e.right);
+                self.span = e.operator.span;
+                let op = match e.operator.token_type {
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    _ => unreachable!("Invalid operator?"),
+                };
+                self.emit(op);
+            }
+            Expr::Call(e) => match &e.callee {
+                // Method calls skip creating a bound method
+                Expr::Get(get) if !is_private(&get.name) => {
+                    self.expression(&get.object);
+                    for argument in &e.arguments {
+                        self.expression(argument);
+                    }
+                    self.span = e.paren.span;
+                    let name = self.name_constant(&get.name.lexeme);
+                    self.emit_with_u16(OpCode::Invoke, name);
+                    self.emit_byte(e.arguments.len() as u8);
+                }
+                callee => {
+                    self.expression(callee);
+                    for argument in &e.arguments {
+                        self.expression(argument);
+                    }
+                    self.span = e.paren.span;
+                    self.emit_with_byte(OpCode::Call, e.arguments.len() as u8);
+                }
+            },
+            Expr::Conditional(e) => {
+                self.expression(&e.condition);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                self.emit(OpCode::Pop);
+                self.expression(&e.left);
+                let end_jump = self.emit_jump(OpCode::Jump);
+                self.patch_jump(else_jump);
+                self.emit(OpCode::Pop);
+                self.expression(&e.right);
+                self.patch_jump(end_jump);
+            }
+            Expr::Get(e) => {
+                self.expression(&e.object);
+                self.span = e.name.span;
+                self.property(OpCode::GetProperty, OpCode::GetPrivate, &e.name);
+            }
+            Expr::Grouping(e) => self.expression(&e.expression),
+            Expr::Literal(e) => match &e.value {
+                Literal::Nil => self.emit(OpCode::Nil),
+                Literal::Boolean(true) => self.emit(OpCode::True),
+                Literal::Boolean(false) => self.emit(OpCode::False),
+                Literal::Number(n) => self.emit_constant(Value::Number(*n)),
+                Literal::String(s) => self.emit_constant(Value::String(s.as_str().into())),
+                value => unreachable!("The parser doesn't produce {value:?} literals"),
+            },
+            Expr::Logical(e) => {
+                self.expression(&e.left);
+                self.span = e.operator.span;
+                if e.operator.token_type == TokenType::Or {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    let end_jump = self.emit_jump(OpCode::Jump);
+                    self.patch_jump(else_jump);
+                    self.emit(OpCode::Pop);
+                    self.expression(&e.right);
+                    self.patch_jump(end_jump);
+                } else {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+                    self.emit(OpCode::Pop);
+                    self.expression(&e.right);
+                    self.patch_jump(end_jump);
+                }
+            }
+            Expr::Set(e) => {
+                self.expression(&e.object);
+                self.expression(&e.value);
+                self.span = e.name.span;
+                self.property(OpCode::SetProperty, OpCode::SetPrivate, &e.name);
+            }
+            Expr::Super(e) => {
+                self.span = e.keyword.span;
+                self.variable("this");
+                self.variable("super");
+                self.span = e.method.span;
+                let name = self.name_constant(&e.method.lexeme);
+                self.emit_with_u16(OpCode::GetSuper, name);
+            }
+            Expr::This(e) => {
+                self.span = e.keyword.span;
+                self.variable("this");
+            }
+            Expr::Unary(e) => {
+                self.expression(&e.right);
+                self.span = e.operator.span;
+                match e.operator.token_type {
+                    TokenType::Minus => self.emit(OpCode::Negate),
+                    TokenType::Bang => self.emit(OpCode::Not),
+                    _ => unreachable!("Invalid operator?"),
+                }
+            }
+            Expr::Variable(e) => {
+                self.span = e.name.span;
+                self.variable(&e.name.lexeme);
+            }
+        }
+    }
+
+    /// Emits a property access, which for private members also pushes the class accessing them
+    fn property(&mut self, public: OpCode, private: OpCode, name: &Token) {
+        let constant = self.name_constant(&name.lexeme);
+        if !is_private(name) {
+            self.emit_with_u16(public, constant);
+            return;
+        }
+        match self.classes.last() {
+            Some(_) => {
+                self.variable(CLASS_BINDING);
+                self.span = name.span;
+                self.emit_with_u16(private, constant);
+            }
+            None => self.error(
+                name,
+                ErrorCode::PrivateAccess,
+                &format!(
+                    "Can't access private property '{}' outside of its class.",
+                    name.lexeme
+                ),
+            ),
+        }
+    }
+
+    fn variable(&mut self, name: &str) {
+        let last = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(last, name) {
+            self.emit_with_byte(OpCode::GetLocal, slot);
+        } else if let Some(index) = self.resolve_upvalue(last, name) {
+            self.emit_with_byte(OpCode::GetUpvalue, index);
+        } else {
+            let name = self.name_constant(name);
+            self.emit_with_u16(OpCode::GetGlobal, name);
+        }
+    }
+
+    fn assign_variable(&mut self, name: &str) {
+        let last = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(last, name) {
+            self.emit_with_byte(OpCode::SetLocal, slot);
+        } else if let Some(index) = self.resolve_upvalue(last, name) {
+            self.emit_with_byte(OpCode::SetUpvalue, index);
+        } else {
+            let name = self.name_constant(name);
+            self.emit_with_u16(OpCode::SetGlobal, name);
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        let slot = locals.iter().rposition(|local| local.name == name)?;
+        Some(slot as u8)
+    }
+
+    /// Finds a variable of an enclosing function and captures it through every function in
+    /// between.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+        if let Some(slot) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return self.add_upvalue(function, slot, true);
+        }
+        let index = self.resolve_upvalue(function - 1, name)?;
+        self.add_upvalue(function, index, false)
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> Option<u8> {
+        let upvalue = UpvalueRef { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return Some(existing as u8);
+        }
+        if upvalues.len() == MAX_SLOTS {
+            self.error_at_line(
+                ErrorCode::CompilerLimit,
+                "Too many closure variables in function.",
+            );
+            return None;
+        }
+        upvalues.push(upvalue);
+        Some((upvalues.len() - 1) as u8)
+    }
+
+    /// Declares a local in the current scope, taking the stack slot of the value on top
+    fn add_local(&mut self, name: &Token) {
+        if self.current().locals.len() == MAX_SLOTS {
+            self.error(
+                name,
+                ErrorCode::CompilerLimit,
+                "Too many local variables in function.",
+            );
+            return;
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(LocalVariable {
+            name: name.lexeme.clone(),
+            depth,
+            captured: false,
+        });
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        let deferred = &self.current().deferred;
+        let remaining = deferred.partition_point(|&d| d <= depth);
+        self.run_deferred(remaining);
+        self.current().deferred.truncate(remaining);
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(op);
+            self.current().locals.pop();
+        }
+    }
+
+    fn begin_loop(&mut self, label: &Option<Token>) {
+        let locals = self.current().locals.len();
+        let deferred = self.current().deferred.len();
+        self.current().loops.push(Loop {
+            label: label.as_ref().map(|l| l.lexeme.clone()),
+            locals,
+            deferred,
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+    }
+
+    /// Runs the deferred expressions of the scopes being left, discards their locals and jumps to the end of the targeted loop,
+    /// or to its next iteration.
+    fn jump_out_of_loop(&mut self, keyword: &Token, label: &Option<Token>, is_break: bool) {
+        self.span = keyword.span;
+        let loops = &self.functions.last().unwrap().loops;
+        let target = match label {
+            None => loops.len().checked_sub(1),
+            Some(label) => loops
+                .iter()
+                .rposition(|l| l.label.as_ref() == Some(&label.lexeme)),
+        };
+        let Some(target) = target else {
+            let message = format!("Can't use '{}' outside of a loop.", keyword.lexeme);
+            self.error(keyword, ErrorCode::OutsideLoop, &message);
+            return;
+        };
+
+        let deferred = self.current().loops[target].deferred;
+        self.run_deferred(deferred);
+        let state = self.current();
+        let captured: Vec<bool> = state.locals[state.loops[target].locals..]
+            .iter()
+            .rev()
+            .map(|local| local.captured)
+            .collect();
+        for captured in captured {
+            self.emit(if captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            });
+        }
+        let jump = self.emit_jump(OpCode::Jump);
+        let target = &mut self.current().loops[target];
+        if is_break {
+            target.breaks.push(jump);
+        } else {
+            target.continues.push(jump);
+        }
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn emit(&mut self, op: OpCode) {
+        let span = self.span;
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let span = self.span;
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16) {
+        let span = self.span;
+        self.chunk().write_u16(value, span);
+    }
+
+    fn emit_with_byte(&mut self, op: OpCode, operand: u8) {
+        self.emit(op);
+        self.emit_byte(operand);
+    }
+
+    fn emit_with_u16(&mut self, op: OpCode, operand: u16) {
+        self.emit(op);
+        self.emit_u16(operand);
+    }
+
+    fn emit_return(&mut self) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit_with_byte(OpCode::GetLocal, 0);
+        } else {
+            self.emit(OpCode::Nil);
+        }
+        self.run_deferred(0);
+        self.emit(OpCode::Return);
+    }
+
+    /// Runs the deferred expressions registered after the first `keep` ones, last first, and
+    /// discards their results
+    fn run_deferred(&mut self, keep: usize) {
+        for _ in keep..self.current().deferred.len() {
+            self.emit(OpCode::RunDeferred);
+            self.emit(OpCode::Pop);
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_with_u16(OpCode::Constant, constant);
+    }
+
+    fn make_constant(&mut self, value: Value) -> u16 {
+        match self.chunk().add_constant(value) {
+            Some(index) => index,
+            None => {
+                self.error_at_line(ErrorCode::CompilerLimit, "Too many constants in one chunk.");
+                0
+            }
+        }
+    }
+
+    fn name_constant(&mut self, name: &str) -> u16 {
+        if let Some(index) = self.current().names.get(name) {
+            return *index;
+        }
+        let index = self.make_constant(Value::String(name.into()));
+        self.current().names.insert(name.to_string(), index);
+        index
+    }
+
+    /// Emits a forward jump to be patched once its target is known, and returns its operand's offset
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_with_u16(op, u16::MAX);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let distance = self.chunk().code.len() - offset - 2;
+        let Ok(distance) = u16::try_from(distance) else {
+            self.error_at_line(ErrorCode::CompilerLimit, "Too much code to jump over.");
+            return;
+        };
+        let [high, low] = distance.to_be_bytes();
+        self.chunk().code[offset] = high;
+        self.chunk().code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, start: usize) {
+        self.emit(OpCode::Loop);
+        let distance = self.chunk().code.len() - start + 2;
+        match u16::try_from(distance) {
+            Ok(distance) => self.emit_u16(distance),
+            Err(_) => self.error_at_line(ErrorCode::CompilerLimit, "Loop body too large."),
+        }
+    }
+
+    fn error(&mut self, token: &Token, code: ErrorCode, message: &str) {
+        self.errors.push(ParseErrorCause::at(token, code, message));
+    }
+
+    fn error_at_line(&mut self, code: ErrorCode, message: &str) {
+        self.errors
+            .push(ParseErrorCause::new(self.span.line, None, code, message));
+    }
+}
+
+fn is_private(name: &Token) -> bool {
+    matches!(name.token_type, TokenType::PrivateIdentifier(_))
+}
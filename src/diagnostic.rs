@@ -0,0 +1,402 @@
+use std::{collections::BTreeMap, env, fmt::Write, io::IsTerminal};
+
+use crate::{error_code::ErrorCode, lox_result::TraceFrame, token::Span};
+
+/// Points at another place in the source that helps explain a diagnostic
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: &str) -> Self {
+        Self {
+            span,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Whether a diagnostic stops the script from running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Reported by the linter, the script still runs
+    Warning,
+}
+
+impl Severity {
+    fn name(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    fn style(self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+/// An error or warning as shown to the user, whichever stage reported it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: ErrorCode,
+    pub message: String,
+    /// `None` for errors that aren't about a particular place, like running out of time
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    /// Calls a runtime error happened in, innermost first
+    pub trace: Vec<TraceFrame>,
+}
+
+/// Whether to colour diagnostics: only on a terminal, and never when `NO_COLOR` is set
+pub fn use_colour() -> bool {
+    std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none()
+}
+
+const RED: &str = "1;31";
+const YELLOW: &str = "1;33";
+const BLUE: &str = "1;34";
+const BOLD: &str = "1";
+
+impl Diagnostic {
+    /// A warning at `span`, without labels or trace
+    pub fn warning(code: ErrorCode, message: String, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code,
+            message,
+            span: Some(span),
+            labels: Vec::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    /// Renders the diagnostic with the lines of `source` it points at, compiler-style:
+    ///
+    /// ```text
+    /// error[L0202]: Already a variable with this name in this scope.
+    ///  --> script.lox:3:9
+    ///   |
+    /// 2 |     var a = 1;
+    ///   |         - first declared here
+    /// 3 |     var a = 2;
+    ///   |         ^
+    /// ```
+    pub fn render(&self, file: &str, source: &str, colour: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if colour {
+                format!("\x1b[{style}m{text}\x1b[0m")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{}{}",
+            paint(
+                self.severity.style(),
+                &format!("{}[{}]", self.severity.name(), self.code)
+            ),
+            paint(BOLD, &format!(": {}", self.message))
+        )
+        .unwrap();
+        let Some(span) = self.span else {
+            return out;
+        };
+        self.render_snippet(&mut out, span, file, source, &paint);
+        self.render_trace(&mut out);
+        out
+    }
+
+    /// The diagnostic as a single line of JSON, for editors and CI:
+    ///
+    /// ```text
+    /// {"severity":"error","code":"L0202","message":"...","file":"a.lox","line":3,"column":9,
+    ///  "span":{"start":20,"end":21},"notes":[{"message":"first declared here","line":2,...}]}
+    /// ```
+    ///
+    /// Notes are the labels, then the stack trace.
+    pub fn to_json(&self, file: &str) -> String {
+        let mut out = String::new();
+        write!(
+            out,
+            r#"{{"severity":"{}","code":"{}","message":{},"file":{},"#,
+            self.severity.name(),
+            self.code,
+            json_string(&self.message),
+            json_string(file),
+        )
+        .unwrap();
+        json_location(&mut out, self.span);
+        out.push_str(r#","notes":["#);
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| (label.message.clone(), Some(label.span)));
+        let frames = self
+            .trace
+            .iter()
+            .map(|frame| (frame.to_string(), frame.line.map(Span::line)));
+        for (i, (message, span)) in labels.chain(frames).enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            write!(out, r#"{{"message":{},"#, json_string(&message)).unwrap();
+            json_location(&mut out, span);
+            out.push('}');
+        }
+        out.push_str("]}");
+        out
+    }
+
+    /// One line per frame, collapsing runs of the same frame left by deep recursion
+    fn render_trace(&self, out: &mut String) {
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            writeln!(out, "    {frame}").unwrap();
+            let mut repeats = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeats += 1;
+            }
+            if repeats > 0 {
+                writeln!(out, "    ... repeated {repeats} more times").unwrap();
+            }
+        }
+    }
+
+    fn render_snippet(
+        &self,
+        out: &mut String,
+        span: Span,
+        file: &str,
+        source: &str,
+        paint: &dyn Fn(&str, &str) -> String,
+    ) {
+        // Underlines by line, the primary span first
+        let mut lines: BTreeMap<usize, Vec<(Span, Option<&str>)>> = BTreeMap::new();
+        lines.entry(span.line).or_default().push((span, None));
+        for label in &self.labels {
+            let marks = lines.entry(label.span.line).or_default();
+            marks.push((label.span, Some(label.message.as_str())));
+        }
+        let width = lines.keys().last().unwrap().to_string().len();
+        let gutter = paint(BLUE, &format!("{:width$} |", ""));
+
+        let location = if span.is_known() {
+            format!("{file}:{}:{}", span.line, span.column)
+        } else {
+            format!("{file}:{}", span.line)
+        };
+        writeln!(out, "{:width$}{} {location}", "", paint(BLUE, "-->")).unwrap();
+        writeln!(out, "{gutter}").unwrap();
+
+        let mut previous = None;
+        for (line, marks) in lines {
+            if previous.is_some_and(|previous| line > previous + 1) {
+                writeln!(out, "{}", paint(BLUE, "...")).unwrap();
+            }
+            previous = Some(line);
+
+            let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+            let number = paint(BLUE, &format!("{line:>width$} |"));
+            writeln!(out, "{number} {text}").unwrap();
+            for (span, message) in marks.into_iter().filter(|(span, _)| span.is_known()) {
+                let (mark, style) = match message {
+                    None => ('^', self.severity.style()),
+                    Some(_) => ('-', BLUE),
+                };
+                let underline = mark.to_string().repeat(underline_width(source, span));
+                let text = match message {
+                    Some(message) => format!("{underline} {message}"),
+                    None => underline,
+                };
+                let indent = " ".repeat(span.column - 1);
+                writeln!(out, "{gutter} {indent}{}", paint(style, &text)).unwrap();
+            }
+        }
+    }
+}
+
+/// The place fields of a diagnostic or note in JSON: its line, column and byte range, each
+/// `null` when unknown
+fn json_location(out: &mut String, span: Option<Span>) {
+    let line = span.map(|span| span.line);
+    let known = span.filter(|span| span.is_known());
+    write!(
+        out,
+        r#""line":{},"column":{},"span":"#,
+        json_or_null(line),
+        json_or_null(known.map(|span| span.column)),
+    )
+    .unwrap();
+    match known {
+        Some(span) => write!(out, r#"{{"start":{},"end":{}}}"#, span.start, span.end).unwrap(),
+        None => out.push_str("null"),
+    }
+}
+
+fn json_or_null(value: Option<usize>) -> String {
+    value.map_or("null".to_string(), |value| value.to_string())
+}
+
+/// `s` as a quoted JSON string
+fn json_string(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Characters to underline for `span`. Spans covering several lines are underlined to the end of
+/// the first one.
+fn underline_width(source: &str, span: Span) -> usize {
+    let text = source.get(span.start..span.end).unwrap_or("");
+    let first_line = text.split('\n').next().unwrap_or("");
+    first_line.chars().count().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Label, Severity};
+    use crate::{error_code::ErrorCode, lox_result::TraceFrame, token::Span};
+
+    fn span(source: &str, text: &str, line: usize) -> Span {
+        let start = source.find(text).unwrap();
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            start,
+            end: start + text.len(),
+            line,
+            column: source[line_start..start].chars().count() + 1,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "{\n    var a = 1;\n    var a = 2;\n}\n";
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: ErrorCode::DuplicateVariable,
+            message: "Already a variable with this name in this scope.".to_string(),
+            span: Some(span(source, "a = 2", 3)),
+            labels: vec![Label::new(span(source, "a = 1", 2), "first declared here")],
+            trace: vec![],
+        };
+        let expected = "\
+error[L0202]: Already a variable with this name in this scope.
+ --> test.lox:3:9
+  |
+2 |     var a = 1;
+  |         ----- first declared here
+3 |     var a = 2;
+  |         ^^^^^
+";
+        assert_eq!(diagnostic.render("test.lox", source, false), expected);
+    }
+
+    #[test]
+    fn test_render_without_column() {
+        let source = "print 1;\n\nprint 2;";
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: ErrorCode::DivisionByZero,
+            message: "Division by zero".to_string(),
+            span: Some(Span::line(3)),
+            labels: vec![Label::new(span(source, "1", 1), "numerator")],
+            trace: vec![
+                TraceFrame {
+                    function: "f".into(),
+                    line: Some(3),
+                    tail_calls: 0,
+                },
+                TraceFrame {
+                    function: "f".into(),
+                    line: Some(3),
+                    tail_calls: 0,
+                },
+                TraceFrame {
+                    function: "f".into(),
+                    line: Some(3),
+                    tail_calls: 0,
+                },
+                TraceFrame {
+                    function: "<script>".into(),
+                    line: Some(1),
+                    tail_calls: 0,
+                },
+            ],
+        };
+        let rendered = diagnostic.render("<stdin>", source, true);
+        assert!(rendered.contains("\x1b[1;31merror[L0306]"));
+        let plain = diagnostic.render("<stdin>", source, false);
+        let expected = "\
+error[L0306]: Division by zero
+ --> <stdin>:3
+  |
+1 | print 1;
+  |       - numerator
+...
+3 | print 2;
+    at f (line 3)
+    ... repeated 2 more times
+    at <script> (line 1)
+";
+        assert_eq!(plain, expected);
+
+        let nowhere = Diagnostic {
+            severity: Severity::Error,
+            code: ErrorCode::StepBudget,
+            message: "Interrupted: out of steps".to_string(),
+            span: None,
+            labels: vec![],
+            trace: vec![],
+        };
+        assert_eq!(
+            nowhere.render("x", source, false),
+            "error[L0314]: Interrupted: out of steps\n"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let source = "{\n    var a = 1;\n    var a = \"\\\"\";\n}\n";
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: ErrorCode::DuplicateVariable,
+            message: "Already a \"variable\".".to_string(),
+            span: Some(span(source, "a = \"", 3)),
+            labels: vec![Label::new(span(source, "a = 1", 2), "first declared here")],
+            trace: vec![TraceFrame {
+                function: "<script>".into(),
+                line: Some(3),
+                tail_calls: 0,
+            }],
+        };
+        let expected = concat!(
+            r#"{"severity":"error","code":"L0202","message":"Already a \"variable\".","#,
+            r#""file":"dir\\a.lox","line":3,"column":9,"span":{"start":25,"end":30},"notes":["#,
+            r#"{"message":"first declared here","line":2,"column":9,"span":{"start":10,"end":15}},"#,
+            r#"{"message":"at <script> (line 3)","line":3,"column":null,"span":null}]}"#,
+        );
+        assert_eq!(diagnostic.to_json("dir\\a.lox"), expected);
+    }
+}
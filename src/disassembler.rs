@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    value::{Function, Value},
+};
+
+/// Lists the instructions of a compiled function, followed by those of the functions it defines.
+pub fn disassemble(function: &Function) -> String {
+    let mut out = String::new();
+    writeln!(out, "== {function} ==").unwrap();
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = instruction(chunk, offset, &mut out);
+    }
+
+    for constant in &chunk.constants {
+        if let Value::Function(function) = constant {
+            out.push('\n');
+            out.push_str(&disassemble(function));
+        }
+    }
+    out
+}
+
+/// Writes the instruction at `offset` as a line of `out` and returns the offset of the next one.
+fn instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    write!(out, "{offset:04} ").unwrap();
+    let line = chunk.line_at(offset);
+    if offset > 0 && line == chunk.line_at(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        write!(out, "{line:4} ").unwrap();
+    }
+
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        writeln!(out, "Unknown opcode {}", chunk.code[offset]).unwrap();
+        return offset + 1;
+    };
+    let name = format!("{op:?}");
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetPrivate
+        | OpCode::SetPrivate
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::AbstractMethod => {
+            let constant = chunk.read_u16(offset + 1);
+            writeln!(
+                out,
+                "{name:<16} {constant:4} {}",
+                constant_at(chunk, constant)
+            )
+            .unwrap();
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::TailCall => {
+            writeln!(out, "{name:<16} {:4}", chunk.code[offset + 1]).unwrap();
+            offset + 2
+        }
+        OpCode::Invoke => {
+            let constant = chunk.read_u16(offset + 1);
+            let count = chunk.code[offset + 3];
+            writeln!(
+                out,
+                "{name:<16} {constant:4} {} ({count} args)",
+                constant_at(chunk, constant)
+            )
+            .unwrap();
+            offset + 4
+        }
+        OpCode::Enum => {
+            let constant = chunk.read_u16(offset + 1);
+            writeln!(
+                out,
+                "{name:<16} {constant:4} {}",
+                constant_at(chunk, constant)
+            )
+            .unwrap();
+            let mut offset = offset + 4;
+            for _ in 0..chunk.code[offset - 1] {
+                let variant = constant_at(chunk, chunk.read_u16(offset));
+                let fields: Vec<_> = (0..chunk.code[offset + 2] as usize)
+                    .map(|i| constant_at(chunk, chunk.read_u16(offset + 3 + 2 * i)))
+                    .collect();
+                writeln!(
+                    out,
+                    "{offset:04}    |                     {variant} ({})",
+                    fields.join(", ")
+                )
+                .unwrap();
+                offset += 3 + 2 * fields.len();
+            }
+            offset
+        }
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let target = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            writeln!(out, "{name:<16} {offset:4} -> {target}").unwrap();
+            offset + 3
+        }
+        OpCode::Loop => {
+            let target = offset + 3 - chunk.read_u16(offset + 1) as usize;
+            writeln!(out, "{name:<16} {offset:4} -> {target}").unwrap();
+            offset + 3
+        }
+        OpCode::Closure => {
+            let constant = chunk.read_u16(offset + 1);
+            let function = &chunk.constants[constant as usize];
+            writeln!(out, "{name:<16} {constant:4} {function}").unwrap();
+            let upvalues = match function {
+                Value::Function(function) => function.upvalue_count,
+                _ => 0,
+            };
+            let mut offset = offset + 3;
+            for _ in 0..upvalues {
+                let kind = if chunk.code[offset] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                let index = chunk.code[offset + 1];
+                writeln!(out, "{offset:04}    |                     {kind} {index}").unwrap();
+                offset += 2;
+            }
+            offset
+        }
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return
+        | OpCode::Inherit
+        | OpCode::Defer
+        | OpCode::RunDeferred => {
+            writeln!(out, "{name}").unwrap();
+            offset + 1
+        }
+    }
+}
+
+fn constant_at(chunk: &Chunk, index: u16) -> String {
+    format!("'{}'", chunk.constants[index as usize])
+}
@@ -1,29 +1,54 @@
-use crate::{expr::Literal, lox_result::LoxResult, token::Token};
-use std::{
-    cell::RefCell,
-    collections::{hash_map::Entry::Occupied, HashMap},
-    rc::Rc,
+use crate::{
+    error_code::ErrorCode,
+    expr::Literal,
+    gc::{Node, Trace},
+    lox_result::LoxResult,
+    memory::{entry_size, Allocation, Heap},
+    suggest,
+    token::Token,
 };
+use std::{cell::RefCell, collections::HashMap, mem::size_of, rc::Rc};
 
-#[derive(Clone, Default, Debug)]
+/// Globals live in `values` by name. Every other scope keeps its variables in `slots`, in the
+/// order the resolver numbered their declarations, and only resolved code ever reads them.
+#[derive(Debug)]
 pub struct Environment {
     values: HashMap<String, Literal>,
+    slots: Vec<Literal>,
     pub enclosing: Option<Rc<RefCell<Environment>>>,
+    allocation: Allocation,
 }
 
 impl Environment {
-    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
+    pub fn new(enclosing: Option<Rc<RefCell<Environment>>>, heap: &Rc<Heap>) -> Self {
         Self {
             values: HashMap::new(),
+            slots: Vec::new(),
             enclosing,
+            allocation: Allocation::new(heap, size_of::<Self>()),
         }
     }
 
+    /// Creates a scope nested in `enclosing`, charged to the same heap
     pub fn wrap(enclosing: Rc<RefCell<Environment>>) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(Self {
-            enclosing: Some(enclosing),
-            values: HashMap::new(),
-        }))
+        let heap = Rc::clone(enclosing.borrow().allocation.heap());
+        let environment = Rc::new(RefCell::new(Self::new(Some(enclosing), &heap)));
+        heap.collector().register_environment(&environment);
+        environment
+    }
+
+    /// Drops all variables and the link to the enclosing scope. Only used by the collector.
+    pub fn clear(&mut self) {
+        let held = self
+            .values
+            .iter()
+            .map(|(n, v)| entry_size(n, v))
+            .sum::<usize>()
+            + self.slots.iter().map(|v| entry_size("", v)).sum::<usize>();
+        self.values.clear();
+        self.slots.clear();
+        self.enclosing = None;
+        self.allocation.replace(held, 0);
     }
 
     // NOTE: Shadowing is legal Lox
@@ -33,68 +58,75 @@ impl Environment {
     var a = "after";
     print a; // "after".
     */
+    /// Declarations run in the order the resolver saw them, so a local takes the next slot.
     pub fn define(&mut self, name: &str, value: Literal) {
-        self.values.insert(name.to_string(), value);
+        if self.enclosing.is_some() {
+            self.allocation.replace(0, entry_size("", &value));
+            self.slots.push(value);
+        } else {
+            self.insert(name, value);
+        }
+    }
+
+    fn insert(&mut self, name: &str, value: Literal) {
+        let new = entry_size(name, &value);
+        let old = self.values.insert(name.to_string(), value);
+        let old = old.map_or(0, |old| entry_size(name, &old));
+        self.allocation.replace(old, new);
     }
 
     pub fn get(&self, name: &Token) -> Result<Literal, LoxResult> {
         match self.values.get(&name.lexeme) {
             Some(v) => Ok(v.clone()),
-            None => {
-                if let Some(e) = &self.enclosing {
-                    return e.borrow().get(name);
-                }
-
-                Err(LoxResult::runtime_error(
-                    name,
-                    &format!("Undefined variable '{}'.", name.lexeme),
-                ))
-            }
+            None => Err(self.undefined(name, None)),
         }
     }
 
     pub fn assign(&mut self, name: &Token, value: Literal) -> Result<(), LoxResult> {
-        if let Occupied(mut e) = self.values.entry(name.lexeme.clone()) {
-            e.insert(value);
+        if self.values.contains_key(&name.lexeme) {
+            self.insert(&name.lexeme, value);
             Ok(())
         } else {
-            if let Some(e) = &mut self.enclosing {
-                return e.borrow_mut().assign(name, value);
-            }
-            Err(LoxResult::runtime_error(
-                name,
-                &format!("Undefined variable '{}'.", name.lexeme),
-            ))
+            Err(self.undefined(name, None))
         }
     }
 
-    pub fn get_at(&self, distance: &usize, name: &str) -> Result<Literal, LoxResult> {
-        if distance == &0 {
-            Ok(self.values.get(name).unwrap().clone()) // ERROR:  This should be able to unwrap
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow()
-                .get_at(&(distance - 1), name)
+    /// The error for a variable that isn't defined here, suggesting a similar name from this
+    /// scope or `local`, a similar local the resolver found in scope where `name` is used
+    pub fn undefined(&self, name: &Token, local: Option<&str>) -> LoxResult {
+        let names = self.values.keys().map(String::as_str).chain(local);
+        let message = format!("Undefined variable '{}'.", name.lexeme);
+        let message = suggest::did_you_mean(message, &name.lexeme, names);
+        LoxResult::runtime_error(name, ErrorCode::UndefinedVariable, &message)
+    }
+
+    pub fn get_at(&self, distance: usize, slot: usize) -> Literal {
+        match &self.enclosing {
+            _ if distance == 0 => self.slots[slot].clone(),
+            Some(enclosing) => enclosing.borrow().get_at(distance - 1, slot),
+            None => unreachable!("Locals are resolved to scopes inside the global one"),
         }
     }
 
-    pub fn assign_at(
-        &mut self,
-        distance: &usize,
-        name: &str,
-        value: Literal,
-    ) -> Result<(), LoxResult> {
-        if distance == &0 {
-            self.values.insert(name.to_string(), value);
-            Ok(())
-        } else {
-            self.enclosing
-                .as_ref()
-                .unwrap()
-                .borrow_mut()
-                .assign_at(&(distance - 1), name, value)
+    pub fn assign_at(&mut self, distance: usize, slot: usize, value: Literal) {
+        match &self.enclosing {
+            _ if distance == 0 => {
+                let old = std::mem::replace(&mut self.slots[slot], value);
+                let new = entry_size("", &self.slots[slot]);
+                self.allocation.replace(entry_size("", &old), new);
+            }
+            Some(enclosing) => enclosing.borrow_mut().assign_at(distance - 1, slot, value),
+            None => unreachable!("Locals are resolved to scopes inside the global one"),
+        }
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        self.values.values().for_each(|v| v.trace(edges));
+        self.slots.iter().for_each(|v| v.trace(edges));
+        if let Some(enclosing) = &self.enclosing {
+            edges.push(Node::Environment(Rc::clone(enclosing)));
         }
     }
 }
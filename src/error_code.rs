@@ -0,0 +1,604 @@
+/// Stable identifier for each kind of diagnostic, shown as `error[L0201]` and explained by
+/// `--explain L0201`. Messages can be reworded freely, but a code is never reused for something
+/// else once released.
+///
+/// Codes are numbered by the stage that reports them: `L00xx` scanner, `L01xx` parser, `L02xx`
+/// resolver, `L03xx` runtime, `L04xx` bytecode compiler and `L05xx` lint warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    UnexpectedCharacter,
+    UnterminatedString,
+    UnterminatedComment,
+    MissingPrivateName,
+
+    ExpectedToken,
+    ExpectedName,
+    ExpectedExpression,
+    InvalidAssignmentTarget,
+    TooManyParameters,
+    TooManyArguments,
+    TooMuchNesting,
+
+    OwnInitializer,
+    DuplicateVariable,
+    DuplicateEnumMember,
+    ReservedEnumName,
+    TopLevelReturn,
+    InitializerReturn,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    InheritsFromItself,
+    AbstractInitializer,
+    AbstractAndImplemented,
+    DeferOutsideBlock,
+    OutsideLoop,
+    UndefinedLabel,
+    DuplicateLabel,
+    PrivateAccess,
+
+    UndefinedVariable,
+    UndefinedProperty,
+    UndefinedVariant,
+    InvalidOrdinal,
+    InvalidOperand,
+    DivisionByZero,
+    NotCallable,
+    WrongArity,
+    NotAnInstance,
+    SuperclassNotClass,
+    AbstractInstantiation,
+    StackOverflow,
+    MemoryLimit,
+    StepBudget,
+    Deadline,
+
+    CompilerLimit,
+
+    UnusedVariable,
+    UnusedParameter,
+    Shadowing,
+    UnreachableCode,
+    MissingReturn,
+}
+
+use ErrorCode::*;
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 49] = [
+        UnexpectedCharacter,
+        UnterminatedString,
+        UnterminatedComment,
+        MissingPrivateName,
+        ExpectedToken,
+        ExpectedName,
+        ExpectedExpression,
+        InvalidAssignmentTarget,
+        TooManyParameters,
+        TooManyArguments,
+        TooMuchNesting,
+        OwnInitializer,
+        DuplicateVariable,
+        DuplicateEnumMember,
+        ReservedEnumName,
+        TopLevelReturn,
+        InitializerReturn,
+        ThisOutsideClass,
+        SuperOutsideClass,
+        SuperWithoutSuperclass,
+        InheritsFromItself,
+        AbstractInitializer,
+        AbstractAndImplemented,
+        DeferOutsideBlock,
+        OutsideLoop,
+        UndefinedLabel,
+        DuplicateLabel,
+        PrivateAccess,
+        UndefinedVariable,
+        UndefinedProperty,
+        UndefinedVariant,
+        InvalidOrdinal,
+        InvalidOperand,
+        DivisionByZero,
+        NotCallable,
+        WrongArity,
+        NotAnInstance,
+        SuperclassNotClass,
+        AbstractInstantiation,
+        StackOverflow,
+        MemoryLimit,
+        StepBudget,
+        Deadline,
+        CompilerLimit,
+        UnusedVariable,
+        UnusedParameter,
+        Shadowing,
+        UnreachableCode,
+        MissingReturn,
+    ];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            UnexpectedCharacter => "L0001",
+            UnterminatedString => "L0002",
+            UnterminatedComment => "L0003",
+            MissingPrivateName => "L0004",
+
+            ExpectedToken => "L0101",
+            ExpectedName => "L0102",
+            ExpectedExpression => "L0103",
+            InvalidAssignmentTarget => "L0104",
+            TooManyParameters => "L0105",
+            TooManyArguments => "L0106",
+            TooMuchNesting => "L0107",
+
+            OwnInitializer => "L0201",
+            DuplicateVariable => "L0202",
+            DuplicateEnumMember => "L0203",
+            ReservedEnumName => "L0204",
+            TopLevelReturn => "L0205",
+            InitializerReturn => "L0206",
+            ThisOutsideClass => "L0207",
+            SuperOutsideClass => "L0208",
+            SuperWithoutSuperclass => "L0209",
+            InheritsFromItself => "L0210",
+            AbstractInitializer => "L0211",
+            AbstractAndImplemented => "L0212",
+            DeferOutsideBlock => "L0213",
+            OutsideLoop => "L0214",
+            UndefinedLabel => "L0215",
+            DuplicateLabel => "L0216",
+            PrivateAccess => "L0217",
+
+            UndefinedVariable => "L0301",
+            UndefinedProperty => "L0302",
+            UndefinedVariant => "L0303",
+            InvalidOrdinal => "L0304",
+            InvalidOperand => "L0305",
+            DivisionByZero => "L0306",
+            NotCallable => "L0307",
+            WrongArity => "L0308",
+            NotAnInstance => "L0309",
+            SuperclassNotClass => "L0310",
+            AbstractInstantiation => "L0311",
+            StackOverflow => "L0312",
+            MemoryLimit => "L0313",
+            StepBudget => "L0314",
+            Deadline => "L0315",
+
+            CompilerLimit => "L0402",
+
+            UnusedVariable => "L0501",
+            UnusedParameter => "L0502",
+            Shadowing => "L0503",
+            UnreachableCode => "L0504",
+            MissingReturn => "L0505",
+        }
+    }
+
+    /// Looks a code up, ignoring case
+    pub fn from_code(code: &str) -> Option<ErrorCode> {
+        ErrorCode::ALL
+            .into_iter()
+            .find(|c| c.code().eq_ignore_ascii_case(code))
+    }
+
+    /// What `--explain` prints: what the error means, an example that causes it and how to fix it
+    pub fn explanation(self) -> &'static str {
+        match self {
+            UnexpectedCharacter => {
+                "A character that isn't part of any Lox token, outside of a string or comment.
+
+    var price = 5 @ 2;  // error: '@' means nothing in Lox
+
+Remove the character, or put it in a string if it's meant to be text."
+            }
+            UnterminatedString => {
+                "A string literal is missing its closing quote, so it runs to the end of the file.
+
+    print \"hello;  // error: the string never ends
+
+Add the closing `\"`. Strings may span several lines, so the error can be reported far from
+where the quote is missing."
+            }
+            UnterminatedComment => {
+                "A `/*` block comment has no matching `*/`.
+
+    /* commented out
+    print 1;  // error: still inside the comment at the end of the file
+
+Block comments nest, so every `/*` inside the comment needs its own `*/` too."
+            }
+            MissingPrivateName => {
+                "`#` must be followed by the name of a private field or method.
+
+    this.# = 1;      // error
+    this.#count = 1; // ok
+
+Private names are only visible inside the class that declares them."
+            }
+            ExpectedToken => {
+                "The parser needed a particular token, such as a `;` or a closing bracket, and
+found something else.
+
+    print 1    // error: expect ';' after value
+    if (x { }  // error: expect ')' after if condition
+
+The error points at the token that was found instead. The missing token usually belongs just
+before it, or at the end of the previous line."
+            }
+            ExpectedName => {
+                "A declaration or property access is missing its name.
+
+    var = 1;       // error: expect variable name
+    class { }      // error: expect class name
+    object.;       // error: expect property name after '.'
+
+Names start with a letter or `_`, followed by letters, digits and `_`. Keywords like `class` or
+`var` can't be used as names."
+            }
+            ExpectedExpression => {
+                "The parser needed a value and found something that can't start an expression.
+
+    var total = ;  // error
+    print 1 + ;    // error
+
+Add the missing operand, or remove the operator."
+            }
+            InvalidAssignmentTarget => {
+                "Only variables, properties and private fields can be assigned to.
+
+    1 = x;        // error
+    a + b = c;    // error
+    point.x = 1;  // ok"
+            }
+            TooManyParameters => {
+                "A function can have at most 255 parameters.
+
+Pass related values together in an instance instead of as separate parameters."
+            }
+            TooManyArguments => {
+                "A call can pass at most 255 arguments, the most any function can take."
+            }
+            TooMuchNesting => {
+                "Expressions and statements are nested too deeply for the parser.
+
+This is almost always generated code. Split the expression with intermediate variables, or
+move nested blocks into functions."
+            }
+            OwnInitializer => {
+                "A local variable is read in its own initializer, before it has a value.
+
+    var a = 1;
+    {
+      var a = a + 1;  // error: the inner `a` shadows the outer one from here on
+    }
+
+Give the new variable a different name to use the outer one."
+            }
+            DuplicateVariable => {
+                "Two local variables with the same name are declared in the same scope.
+
+    fun f() {
+      var a = 1;
+      var a = 2;  // error
+    }
+
+Assign to the existing variable instead, or use another name. Globals can be redeclared."
+            }
+            DuplicateEnumMember => {
+                "An enum has two variants with the same name, or a variant has two fields with the
+same name.
+
+    enum Colour { Red, Green, Red }  // error"
+            }
+            ReservedEnumName => {
+                "The name is already used by every enum or variant, so can't be declared again.
+
+    enum Size { count }  // error: `Size.count` is the number of variants
+
+Rename the variant or field."
+            }
+            TopLevelReturn => {
+                "`return` can only be used inside a function or method.
+
+    return 1;  // error at the top level of a script
+
+To stop a script early, put its body in a function and return from that."
+            }
+            InitializerReturn => {
+                "`init` always returns the new instance, so it can't return another value.
+
+    class Point {
+      init(x) {
+        return x;  // error
+      }
+    }
+
+A bare `return;` is allowed to leave the initializer early."
+            }
+            ThisOutsideClass => {
+                "`this` only means something inside a method.
+
+    fun f() { print this; }  // error
+
+Pass the instance as a parameter instead."
+            }
+            SuperOutsideClass => {
+                "`super` only means something inside a method.
+
+    fun f() { super.g(); }  // error"
+            }
+            SuperWithoutSuperclass => {
+                "`super` is used in a class that doesn't inherit from another.
+
+    class A {
+      f() { super.f(); }  // error: A has no superclass
+    }
+
+Add a superclass with `class A < Base`, or call the method on `this`."
+            }
+            InheritsFromItself => {
+                "A class names itself as its superclass.
+
+    class A < A {}  // error"
+            }
+            AbstractInitializer => {
+                "`init` can't be declared `abstract`: every class needs a way to create instances.
+
+    class Shape {
+      abstract init();  // error
+    }"
+            }
+            AbstractAndImplemented => {
+                "A class declares a method abstract and also implements it.
+
+    class Shape {
+      abstract area();
+      area() { return 0; }  // error
+    }
+
+Remove one of the two declarations. Subclasses implement abstract methods, not the class
+declaring them."
+            }
+            DeferOutsideBlock => {
+                "`defer` runs its expression when the enclosing block ends, so it needs one. The
+body of a loop or `if` has to be a block of its own, or the expression would run once the block
+around the loop ends, for every iteration.
+
+    defer close(file);      // error at the top level
+    { defer close(file); }  // ok
+    while (more()) defer close(next());     // error
+    while (more()) { defer close(next()); } // ok"
+            }
+            OutsideLoop => {
+                "`break` or `continue` is used outside of any loop.
+
+    fun f() {
+      break;  // error
+    }
+
+Loops don't extend into functions declared inside them. Use `return` to leave a function."
+            }
+            UndefinedLabel => {
+                "`break` or `continue` names a label that no enclosing loop has.
+
+    outer: while (true) {
+      break inner;  // error
+    }"
+            }
+            DuplicateLabel => {
+                "A loop reuses the label of a loop it's nested in, so `break` with that label
+would be ambiguous.
+
+    a: while (true) {
+      a: while (true) {}  // error
+    }"
+            }
+            PrivateAccess => {
+                "A private field or method, one whose name starts with `#`, is used outside of the
+class that declares it.
+
+    class Counter {
+      init() { this.#count = 0; }
+    }
+    print Counter().#count;  // error
+
+Add a method to the class that returns the value."
+            }
+            UndefinedVariable => {
+                "A variable is used that hasn't been declared, or a global is read before the
+statement declaring it has run.
+
+    print total;  // error
+    var total = 1;
+
+Check the spelling, or move the declaration earlier."
+            }
+            UndefinedProperty => {
+                "An instance has no field or method with this name.
+
+    class Point { init() { this.x = 1; } }
+    print Point().y;  // error
+
+Fields only exist once assigned, usually in `init`."
+            }
+            UndefinedVariant => {
+                "An enum has no variant with this name.
+
+    enum Colour { Red, Green }
+    print Colour.Blue;  // error"
+            }
+            InvalidOrdinal => {
+                "An enum is called with a number that isn't the ordinal of one of its variants.
+
+    enum Colour { Red, Green }
+    print Colour(2);  // error: ordinals are 0 and 1
+
+Check the number against `Colour.count` first."
+            }
+            InvalidOperand => {
+                "An operator is used on values of the wrong type.
+
+    print -\"a\";       // error: only numbers can be negated
+    print 1 < nil;    // error: only numbers can be compared
+    print \"a\" + nil;  // error: `+` adds numbers or joins strings and numbers"
+            }
+            DivisionByZero => {
+                "A number is divided by zero.
+
+    print 1 / 0;  // error
+
+Check the divisor before dividing."
+            }
+            NotCallable => {
+                "Something that isn't a function or class is called.
+
+    var a = 1;
+    a();  // error"
+            }
+            WrongArity => {
+                "A function is called with a different number of arguments than it has
+parameters.
+
+    fun add(a, b) { return a + b; }
+    add(1);  // error: expected 2 arguments but got 1
+
+Classes take the same arguments as their `init` method."
+            }
+            NotAnInstance => {
+                "A property is read or set on something that isn't an instance.
+
+    var n = 1;
+    print n.size;  // error"
+            }
+            SuperclassNotClass => {
+                "A class inherits from something that isn't a class.
+
+    var Base = 1;
+    class A < Base {}  // error"
+            }
+            AbstractInstantiation => {
+                "A class is instantiated while some of its abstract methods have no
+implementation.
+
+    class Shape { abstract area(); }
+    Shape();  // error
+
+Instantiate a subclass that implements every abstract method instead."
+            }
+            StackOverflow => {
+                "Calls are nested deeper than the interpreter allows, usually because of
+recursion that never stops.
+
+    fun f() { return 1 + f(); }
+    f();  // error
+
+Check the recursion has a base case. Calls in tail position (`return f();`) reuse the
+caller's frame, so they don't count towards the limit."
+            }
+            MemoryLimit => "The script allocated more memory than the interpreter's limit allows.",
+            StepBudget => {
+                "The script ran for more steps than the interpreter's budget allows, and was
+stopped. This usually means a loop that never ends."
+            }
+            Deadline => {
+                "The script ran for longer than the interpreter's time limit, and was stopped."
+            }
+            CompilerLimit => {
+                "A function or enum is too big for the bytecode format, which allows 256 local
+variables and closure variables, 65536 constants, jumps of 65535 bytes, and 255 variants of
+up to 255 fields in an enum.
+
+Split the function or enum into smaller ones."
+            }
+            UnusedVariable => {
+                "A local variable, function or class is declared but never read. Assigning to a
+variable doesn't count as using it.
+
+    fun area(width, height) {
+      var perimeter = 2 * (width + height);  // warning: never read
+      return width * height;
+    }
+
+Remove the declaration, or start its name with `_` if it's unused on purpose. Disable the
+warning with `--allow=unused-variable`."
+            }
+            UnusedParameter => {
+                "A function or method never reads one of its parameters.
+
+    fun greet(name, greeting) {  // warning: `greeting` is never read
+      print \"Hello, \" + name;
+    }
+
+Remove the parameter, or start its name with `_` when it has to stay, for example to match
+the method it overrides. Disable the warning with `--allow=unused-parameter`."
+            }
+            Shadowing => {
+                "A local variable has the same name as one in an enclosing scope, which it hides
+until the end of its own scope.
+
+    {
+      var count = 1;
+      {
+        var count = 2;  // warning: hides the `count` above
+      }
+    }
+
+Rename one of them, or disable the warning with `--allow=shadowing`. Only locals are checked:
+a local with the same name as a global isn't reported."
+            }
+            UnreachableCode => {
+                "A statement can never run: it follows a `return`, `break` or `continue` in the
+same block, or a loop that never ends, or it's the branch of an `if` or the body of a `while`
+whose condition is a literal that always goes the other way.
+
+    fun sign(n) {
+      return n < 0 ? -1 : 1;
+      print \"done\";  // warning: never runs
+    }
+
+Remove the code, or disable the warning with `--allow=unreachable-code`."
+            }
+            MissingReturn => {
+                "A function returns a value on some paths but can also reach the end of its body,
+where it returns `nil` without saying so.
+
+    fun describe(n) {
+      if (n > 0) return \"positive\";
+      if (n < 0) return \"negative\";
+    }  // warning: `describe(0)` is nil
+
+Add a `return` at the end, or disable the warning with `--allow=missing-return`."
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorCode;
+
+    #[test]
+    fn test_codes_are_unique() {
+        for (i, code) in ErrorCode::ALL.iter().enumerate() {
+            assert_eq!(ErrorCode::from_code(code.code()), Some(*code));
+            assert!(code.code().starts_with('L') && code.code().len() == 5);
+            assert!(!code.explanation().is_empty());
+            for other in &ErrorCode::ALL[..i] {
+                assert_ne!(code.code(), other.code());
+            }
+        }
+        assert_eq!(
+            ErrorCode::from_code("l0201"),
+            Some(ErrorCode::OwnInitializer)
+        );
+        assert_eq!(ErrorCode::from_code("L9999"), None);
+    }
+}
@@ -1,6 +1,11 @@
 use crate::functions::LoxFunction;
 use crate::lox_class::{LoxClass, LoxInstance};
-use crate::{interpreter::Interpreter, lox_result::LoxResult, token::Token};
+use crate::lox_enum::{LoxEnum, LoxEnumValue};
+use crate::{
+    interpreter::Interpreter,
+    lox_result::LoxResult,
+    token::{Span, Token},
+};
 use std::any::TypeId;
 use std::cell::RefCell;
 use std::fmt::{self, Debug, Display, Formatter};
@@ -18,8 +23,10 @@ pub enum Literal {
     Function(Rc<LoxFunction>),
     // TODO: Is typeId needed?
     NativeFunction(TypeId, Rc<dyn LoxCallable>),
-    Class(LoxClass),
+    Class(Rc<LoxClass>),
     Instance(Rc<RefCell<LoxInstance>>),
+    Enum(Rc<LoxEnum>),
+    EnumValue(Rc<LoxEnumValue>),
 }
 
 impl Literal {
@@ -39,15 +46,15 @@ impl Hash for Literal {
             Literal::Function(v) => ptr_hash(v, state),
             Literal::NativeFunction(ty, _) => ty.hash(state),
             Literal::Class(v) => v.hash(state),
-            Literal::Instance(v) => v.borrow().hash(state),
+            Literal::Instance(v) => Rc::as_ptr(v).hash(state),
+            Literal::Enum(v) => v.name.hash(state),
+            Literal::EnumValue(v) => v.hash(state),
         }
     }
 }
 
 // TODO: Verify
-impl Eq for Literal {
-    fn assert_receiver_is_total_eq(&self) {}
-}
+impl Eq for Literal {}
 
 impl core::fmt::Debug for Literal {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
@@ -64,6 +71,8 @@ impl core::fmt::Debug for Literal {
             Self::Function(arg0) => f.debug_tuple("Function").field(&arg0.to_string()).finish(),
             Self::Class(arg0) => f.debug_tuple("Class").field(arg0).finish(),
             Self::Instance(arg0) => f.debug_tuple("Instance").field(arg0).finish(),
+            Self::Enum(arg0) => f.debug_tuple("Enum").field(&arg0.name).finish(),
+            Self::EnumValue(arg0) => f.debug_tuple("EnumValue").field(&arg0.to_string()).finish(),
         }
     }
 }
@@ -78,8 +87,10 @@ impl Display for Literal {
             Literal::String(s) => s.to_string(),
             Literal::Number(n) => n.to_string(),
             Literal::Function(f) => f.to_string(),
-            Literal::Class(c) => LoxCallable::to_string(c),
+            Literal::Class(c) => LoxCallable::to_string(c.as_ref()),
             Literal::Instance(i) => i.borrow().to_string(),
+            Literal::Enum(e) => e.to_string(),
+            Literal::EnumValue(v) => v.to_string(),
         };
         write!(f, "{v}")
     }
@@ -95,8 +106,11 @@ impl PartialEq for Literal {
             (Self::Function(l0), Self::Function(r0)) => std::ptr::eq(l0.as_ref(), r0.as_ref()),
             (Self::Nil, Self::Nil) => true,
             (Self::NativeFunction(ty0, _), Self::NativeFunction(ty1, _)) => ty0 == ty1,
-            (Self::Class(l0), Self::Class(r0)) => l0.name == r0.name,
-            (Self::Instance(l0), Self::Instance(r0)) => l0 == r0,
+            (Self::Class(l0), Self::Class(r0)) => Rc::ptr_eq(l0, r0),
+            // Instances are equal only to themselves, which also keeps self-references from recursing
+            (Self::Instance(l0), Self::Instance(r0)) => Rc::ptr_eq(l0, r0),
+            (Self::Enum(l0), Self::Enum(r0)) => l0.name == r0.name,
+            (Self::EnumValue(l0), Self::EnumValue(r0)) => l0 == r0,
             _ => false,
         }
     }
@@ -111,6 +125,8 @@ pub trait LoxCallable {
     ) -> Result<Literal, LoxResult>;
     fn get_arity(&self) -> usize;
     fn to_string(&self) -> String;
+    /// How the callable is shown in stack traces, e.g. `Foo.bar`
+    fn name(&self) -> Rc<str>;
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -121,7 +137,7 @@ pub enum Expr {
     Conditional(Box<ConditionalExpr>), // Ternary
     Get(Box<GetExpr>),
     Grouping(Box<GroupingExpr>),
-    Literal(Literal),
+    Literal(Box<LiteralExpr>),
     Logical(Box<LogicalExpr>),
     Set(Box<SetExpr>),
     Super(Box<SuperExpr>),
@@ -130,29 +146,58 @@ pub enum Expr {
     Variable(Box<VariableExpr>),
 }
 
+/// Identifies an expression node. The parser hands out a new one for every node it creates,
+/// so it survives the tree being cloned or stored, unlike the node's address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(pub usize);
+
+impl Expr {
+    pub fn id(&self) -> ExprId {
+        match self {
+            Expr::Assign(e) => e.id,
+            Expr::Binary(e) => e.id,
+            Expr::Call(e) => e.id,
+            Expr::Conditional(e) => e.id,
+            Expr::Get(e) => e.id,
+            Expr::Grouping(e) => e.id,
+            Expr::Literal(e) => e.id,
+            Expr::Logical(e) => e.id,
+            Expr::Set(e) => e.id,
+            Expr::Super(e) => e.id,
+            Expr::This(e) => e.id,
+            Expr::Unary(e) => e.id,
+            Expr::Variable(e) => e.id,
+        }
+    }
+
+    /// Source covered by the expression, from its first token to its last
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign(e) => e.name.span.to(e.value.span()),
+            Expr::Binary(e) => e.left.span().to(e.right.span()),
+            Expr::Call(e) => e.callee.span().to(e.paren.span),
+            Expr::Conditional(e) => e.condition.span().to(e.right.span()),
+            Expr::Get(e) => e.object.span().to(e.name.span),
+            Expr::Grouping(e) => e.span,
+            Expr::Literal(e) => e.span,
+            Expr::Logical(e) => e.left.span().to(e.right.span()),
+            Expr::Set(e) => e.object.span().to(e.value.span()),
+            Expr::Super(e) => e.keyword.span.to(e.method.span),
+            Expr::This(e) => e.keyword.span,
+            Expr::Unary(e) => e.operator.span.to(e.right.span()),
+            Expr::Variable(e) => e.name.span,
+        }
+    }
+}
+
 fn ptr_hash<T: Debug, H: std::hash::Hasher>(v: &T, state: &mut H) {
     let ptr = v as *const _ as usize;
-    // println!("{v:?} {ptr:?}");
     ptr.hash(state)
 }
 
 impl Hash for Expr {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        match self {
-            Expr::Assign(v) => ptr_hash(v, state),
-            Expr::Binary(v) => ptr_hash(v, state),
-            Expr::Call(v) => ptr_hash(v, state),
-            Expr::Conditional(v) => ptr_hash(v, state),
-            Expr::Get(v) => ptr_hash(v, state),
-            Expr::Grouping(v) => ptr_hash(v, state),
-            Expr::Literal(v) => v.hash(state),
-            Expr::Logical(v) => ptr_hash(v, state),
-            Expr::Set(v) => ptr_hash(v, state),
-            Expr::Super(v) => v.hash(state),
-            Expr::This(v) => ptr_hash(v, state),
-            Expr::Unary(v) => ptr_hash(v, state),
-            Expr::Variable(v) => ptr_hash(v, state),
-        }
+        self.id().hash(state)
     }
 }
 
@@ -181,16 +226,37 @@ impl Display for Expr {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LiteralExpr {
+    pub id: ExprId,
+    pub value: Literal,
+    pub span: Span,
+}
+
+impl LiteralExpr {
+    pub fn new(id: ExprId, value: Literal, span: Span) -> Self {
+        Self { id, value, span }
+    }
+}
+
+impl Display for LiteralExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BinaryExpr {
+    pub id: ExprId,
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
 }
 
 impl BinaryExpr {
-    pub fn new(left: Expr, operator: Token, right: Expr) -> Self {
+    pub fn new(id: ExprId, left: Expr, operator: Token, right: Expr) -> Self {
         Self {
+            id,
             left,
             operator,
             right,
@@ -215,14 +281,16 @@ impl Display for BinaryExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CallExpr {
+    pub id: ExprId,
     pub callee: Expr,
     pub paren: Token,
     pub arguments: Vec<Expr>,
 }
 
 impl CallExpr {
-    pub fn new(callee: Expr, paren: Token, arguments: Vec<Expr>) -> Self {
+    pub fn new(id: ExprId, callee: Expr, paren: Token, arguments: Vec<Expr>) -> Self {
         Self {
+            id,
             callee,
             paren,
             arguments,
@@ -246,14 +314,16 @@ impl CallExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ConditionalExpr {
+    pub id: ExprId,
     pub condition: Expr,
     pub left: Expr,
     pub right: Expr,
 }
 
 impl ConditionalExpr {
-    pub fn new(condition: Expr, left: Expr, right: Expr) -> Self {
+    pub fn new(id: ExprId, condition: Expr, left: Expr, right: Expr) -> Self {
         Self {
+            id,
             condition,
             left,
             right,
@@ -277,12 +347,19 @@ impl Display for ConditionalExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GroupingExpr {
+    pub id: ExprId,
     pub expression: Expr,
+    /// Including the parentheses
+    pub span: Span,
 }
 
 impl GroupingExpr {
-    pub fn new(expression: Expr) -> Self {
-        Self { expression }
+    pub fn new(id: ExprId, expression: Expr, span: Span) -> Self {
+        Self {
+            id,
+            expression,
+            span,
+        }
     }
 
     #[allow(unused)]
@@ -299,14 +376,16 @@ impl Display for GroupingExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LogicalExpr {
+    pub id: ExprId,
     pub left: Expr,
     pub operator: Token,
     pub right: Expr,
 }
 
 impl LogicalExpr {
-    pub fn new(left: Expr, operator: Token, right: Expr) -> Self {
+    pub fn new(id: ExprId, left: Expr, operator: Token, right: Expr) -> Self {
         Self {
+            id,
             left,
             operator,
             right,
@@ -331,13 +410,18 @@ impl Display for LogicalExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct UnaryExpr {
+    pub id: ExprId,
     pub operator: Token,
     pub right: Expr,
 }
 
 impl UnaryExpr {
-    pub fn new(operator: Token, right: Expr) -> Self {
-        Self { operator, right }
+    pub fn new(id: ExprId, operator: Token, right: Expr) -> Self {
+        Self {
+            id,
+            operator,
+            right,
+        }
     }
 
     #[allow(unused)]
@@ -354,12 +438,13 @@ impl Display for UnaryExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct VariableExpr {
+    pub id: ExprId,
     pub name: Token,
 }
 
 impl VariableExpr {
-    pub fn new(name: Token) -> Self {
-        Self { name }
+    pub fn new(id: ExprId, name: Token) -> Self {
+        Self { id, name }
     }
 }
 
@@ -371,13 +456,14 @@ impl Display for VariableExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AssignExpr {
+    pub id: ExprId,
     pub name: Token,
     pub value: Expr,
 }
 
 impl AssignExpr {
-    pub fn new(name: Token, value: Expr) -> Self {
-        Self { name, value }
+    pub fn new(id: ExprId, name: Token, value: Expr) -> Self {
+        Self { id, name, value }
     }
 }
 
@@ -389,13 +475,14 @@ impl Display for AssignExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct GetExpr {
+    pub id: ExprId,
     pub object: Expr,
     pub name: Token,
 }
 
 impl GetExpr {
-    pub fn new(name: Token, object: Expr) -> Self {
-        Self { name, object }
+    pub fn new(id: ExprId, name: Token, object: Expr) -> Self {
+        Self { id, name, object }
     }
 }
 
@@ -407,14 +494,16 @@ impl Display for GetExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SetExpr {
+    pub id: ExprId,
     pub object: Expr,
     pub name: Token,
     pub value: Expr,
 }
 
 impl SetExpr {
-    pub fn new(object: Expr, name: Token, value: Expr) -> Self {
+    pub fn new(id: ExprId, object: Expr, name: Token, value: Expr) -> Self {
         Self {
+            id,
             name,
             object,
             value,
@@ -430,12 +519,13 @@ impl Display for SetExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ThisExpr {
+    pub id: ExprId,
     pub keyword: Token,
 }
 
 impl ThisExpr {
-    pub fn new(keyword: Token) -> Self {
-        Self { keyword }
+    pub fn new(id: ExprId, keyword: Token) -> Self {
+        Self { id, keyword }
     }
 }
 
@@ -447,13 +537,18 @@ impl Display for ThisExpr {
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct SuperExpr {
+    pub id: ExprId,
     pub keyword: Token,
     pub method: Token,
 }
 
 impl SuperExpr {
-    pub fn new(keyword: Token, method: Token) -> Self {
-        Self { keyword, method }
+    pub fn new(id: ExprId, keyword: Token, method: Token) -> Self {
+        Self {
+            id,
+            keyword,
+            method,
+        }
     }
 }
 
@@ -527,54 +622,83 @@ fn walk_rpn(name: &str, exprs: &[&Expr]) -> String {
 #[cfg(test)]
 mod tests {
     use crate::{
-        expr::{BinaryExpr, ConditionalExpr, Expr, GroupingExpr, Literal, UnaryExpr},
-        token::{Token, TokenType},
+        expr::{
+            BinaryExpr, ConditionalExpr, Expr, ExprId, GroupingExpr, Literal, LiteralExpr,
+            UnaryExpr,
+        },
+        token::{Span, Token, TokenType},
     };
 
+    fn literal(value: Literal) -> Expr {
+        Expr::Literal(Box::new(LiteralExpr::new(
+            ExprId(0),
+            value,
+            Span::default(),
+        )))
+    }
+
     fn build_e1() -> BinaryExpr {
         let left = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(1.0)),
+            ExprId(0),
+            literal(Literal::Number(1.0)),
             Token::new(TokenType::Plus, "+".to_owned(), 1),
-            Expr::Literal(Literal::Number(2.0)),
+            literal(Literal::Number(2.0)),
         )));
         let right = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(4.0)),
+            ExprId(0),
+            literal(Literal::Number(4.0)),
             Token::new(TokenType::Minus, "-".to_owned(), 1),
-            Expr::Literal(Literal::Number(3.0)),
+            literal(Literal::Number(3.0)),
         )));
 
-        BinaryExpr::new(left, Token::new(TokenType::Star, "*".to_owned(), 1), right)
+        BinaryExpr::new(
+            ExprId(0),
+            left,
+            Token::new(TokenType::Star, "*".to_owned(), 1),
+            right,
+        )
     }
 
     fn build_e2() -> BinaryExpr {
         let left = Expr::Unary(Box::new(UnaryExpr::new(
+            ExprId(0),
             Token::new(TokenType::Minus, "-".to_owned(), 1),
-            Expr::Literal(Literal::Number(123.0)),
+            literal(Literal::Number(123.0)),
+        )));
+        let right = Expr::Grouping(Box::new(GroupingExpr::new(
+            ExprId(0),
+            literal(Literal::Number(45.76)),
+            Span::default(),
         )));
-        let right = Expr::Grouping(Box::new(GroupingExpr::new(Expr::Literal(Literal::Number(
-            45.76,
-        )))));
 
-        BinaryExpr::new(left, Token::new(TokenType::Star, "*".to_owned(), 1), right)
+        BinaryExpr::new(
+            ExprId(0),
+            left,
+            Token::new(TokenType::Star, "*".to_owned(), 1),
+            right,
+        )
     }
 
     fn build_e3() -> ConditionalExpr {
         let condition = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(5.0)),
+            ExprId(0),
+            literal(Literal::Number(5.0)),
             Token::new(TokenType::Greater, ">".to_owned(), 1),
-            Expr::Literal(Literal::Number(6.0)),
+            literal(Literal::Number(6.0)),
         )));
         let left = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(1.0)),
+            ExprId(0),
+            literal(Literal::Number(1.0)),
             Token::new(TokenType::Plus, "+".to_owned(), 1),
-            Expr::Literal(Literal::Number(2.0)),
+            literal(Literal::Number(2.0)),
         )));
         let right = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(4.0)),
+            ExprId(0),
+            literal(Literal::Number(4.0)),
             Token::new(TokenType::Minus, "-".to_owned(), 1),
-            Expr::Literal(Literal::Number(3.0)),
+            literal(Literal::Number(3.0)),
         )));
-        ConditionalExpr::new(condition, left, right)
+        ConditionalExpr::new(ExprId(0), condition, left, right)
     }
 
     #[test]
@@ -583,24 +707,24 @@ mod tests {
         assert_eq!(e.to_string(), "(* (- 123) (group 45.76))");
 
         let left = Expr::Unary(Box::new(UnaryExpr::new(
+            ExprId(0),
             Token::new(TokenType::Bang, "!".to_owned(), 1),
-            Expr::Literal(Literal::Boolean(true)),
+            literal(Literal::Boolean(true)),
         )));
         let right = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::String("Hello".to_owned())),
+            ExprId(0),
+            literal(Literal::String("Hello".to_owned())),
             Token::new(TokenType::BangEqual, "!=".to_owned(), 1),
-            Expr::Literal(Literal::String("World".to_owned())),
+            literal(Literal::String("World".to_owned())),
         )));
         let expression = BinaryExpr::new(
+            ExprId(0),
             left,
             Token::new(TokenType::EqualEqual, "==".to_owned(), 1),
             right,
         );
 
-        assert_eq!(
-            expression.to_string(),
-            "(== (! true) (!= Hello World))"
-        );
+        assert_eq!(expression.to_string(), "(== (! true) (!= Hello World))");
 
         let e = build_e3();
         assert_eq!(e.to_string(), "(?: (> 5 6) (+ 1 2) (- 4 3))");
@@ -612,8 +736,9 @@ mod tests {
         assert_eq!(e.to_rpn(), "1 2 + 4 3 - *");
 
         let e = UnaryExpr::new(
+            ExprId(0),
             Token::new(TokenType::Bang, "!".to_owned(), 1),
-            Expr::Literal(Literal::Boolean(true)),
+            literal(Literal::Boolean(true)),
         );
         assert_eq!(e.to_rpn(), "true !");
     }
@@ -0,0 +1,280 @@
+use crate::{
+    diagnostic::{Diagnostic, Label},
+    expr::{Expr, Literal},
+    lint::{Lint, Lints},
+    stmt::{FunctionStmt, Stmt},
+    token::{Span, Token},
+};
+
+/// Finds statements that can never run, and functions that return a value on some paths but
+/// reach the end of their body on others. Runs on the statements as parsed, before the optimizer
+/// removes the dead branches it would warn about.
+///
+/// Only literal conditions count as constant, and calls are assumed to return, so some dead code
+/// goes unreported but nothing that can run is.
+pub fn analyse(statements: &[Stmt], lints: &Lints) -> Vec<Diagnostic> {
+    let mut flow = Flow {
+        lints,
+        warnings: vec![],
+        loops: vec![],
+        returns: vec![],
+    };
+    flow.block(statements);
+    flow.warnings
+}
+
+/// A loop being analysed, and whether anything jumps out of it or on to its next iteration
+struct Loop<'a> {
+    label: Option<&'a str>,
+    broken: bool,
+    continued: bool,
+}
+
+struct Flow<'a> {
+    lints: &'a Lints,
+    warnings: Vec<Diagnostic>,
+    loops: Vec<Loop<'a>>,
+    /// `return` keywords with a value in the function being analysed
+    returns: Vec<Span>,
+}
+
+impl<'a> Flow<'a> {
+    /// Whether running the statements can go on to whatever follows them. Only the first
+    /// statement that can't run is reported, and nothing after it is analysed.
+    fn block(&mut self, statements: &'a [Stmt]) -> bool {
+        for (i, s) in statements.iter().enumerate() {
+            if !self.statement(s) {
+                if let (Some(next), Some(exit)) = (statements.get(i + 1), s.span()) {
+                    self.unreachable(next, exit, "any code after this is unreachable");
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn statement(&mut self, s: &'a Stmt) -> bool {
+        match s {
+            Stmt::Block(b) => self.block(&b.statements),
+            Stmt::Break(b) => {
+                if let Some(target) = self.target(&b.label) {
+                    target.broken = true;
+                }
+                false
+            }
+            Stmt::Continue(c) => {
+                if let Some(target) = self.target(&c.label) {
+                    target.continued = true;
+                }
+                false
+            }
+            Stmt::Class(c) => {
+                for method in &c.methods {
+                    if let Stmt::Function(f) = method {
+                        self.function(f);
+                    }
+                }
+                true
+            }
+            Stmt::DoWhile(d) => {
+                let (body, target) = self.loop_body(&d.label, &d.body);
+                let checked = body || target.continued;
+                target.broken || (checked && constant(&d.condition) != Some(true))
+            }
+            Stmt::Function(f) => {
+                self.function(f);
+                true
+            }
+            Stmt::If(i) => match constant(&i.condition) {
+                Some(true) => {
+                    if let Some(else_branch) = &i.else_branch {
+                        let why = "this condition is always true";
+                        self.unreachable(else_branch, i.condition.span(), why);
+                    }
+                    self.statement(&i.then_branch)
+                }
+                Some(false) => {
+                    let why = "this condition is always false";
+                    self.unreachable(&i.then_branch, i.condition.span(), why);
+                    i.else_branch.as_ref().is_none_or(|s| self.statement(s))
+                }
+                None => {
+                    let then_branch = self.statement(&i.then_branch);
+                    let else_branch = i.else_branch.as_ref().is_none_or(|s| self.statement(s));
+                    then_branch || else_branch
+                }
+            },
+            Stmt::Return(r) => {
+                if r.value.is_some() {
+                    self.returns.push(r.keyword.span);
+                }
+                false
+            }
+            Stmt::While(w) => match constant(&w.condition) {
+                Some(false) => {
+                    let why = "this condition is always false";
+                    self.unreachable(&w.body, w.condition.span(), why);
+                    true
+                }
+                condition => {
+                    let (_, target) = self.loop_body(&w.label, &w.body);
+                    target.broken || condition != Some(true)
+                }
+            },
+            Stmt::Defer(_)
+            | Stmt::Enum(_)
+            | Stmt::Expression(_)
+            | Stmt::Print(_)
+            | Stmt::Var(_) => true,
+        }
+    }
+
+    /// Analyses the body of a loop, returning whether it can reach its end and what jumps
+    /// out of it
+    fn loop_body(&mut self, label: &'a Option<Token>, body: &'a Stmt) -> (bool, Loop<'a>) {
+        self.loops.push(Loop {
+            label: label.as_ref().map(|label| label.lexeme.as_str()),
+            broken: false,
+            continued: false,
+        });
+        let completes = self.statement(body);
+        (completes, self.loops.pop().unwrap())
+    }
+
+    /// The loop a `break` or `continue` jumps out of, if the resolver won't reject it
+    fn target(&mut self, label: &Option<Token>) -> Option<&mut Loop<'a>> {
+        match label {
+            None => self.loops.last_mut(),
+            Some(label) => self
+                .loops
+                .iter_mut()
+                .rev()
+                .find(|l| l.label == Some(label.lexeme.as_str())),
+        }
+    }
+
+    fn function(&mut self, f: &'a FunctionStmt) {
+        let loops = std::mem::take(&mut self.loops);
+        let returns = std::mem::take(&mut self.returns);
+        let completes = self.block(&f.body);
+        if let (true, Some(&first)) = (completes, self.returns.first()) {
+            let message = format!(
+                "'{}' can reach the end of its body without returning a value.",
+                f.name.lexeme
+            );
+            let label = Label::new(first, "but returns a value here");
+            self.warn(Lint::MissingReturn, message, f.name.span, label);
+        }
+        self.loops = loops;
+        self.returns = returns;
+    }
+
+    /// Reports `s`, which can never run because of the code at `cause`
+    fn unreachable(&mut self, s: &Stmt, cause: Span, why: &str) {
+        if let Some(span) = s.span() {
+            let label = Label::new(cause, why);
+            self.warn(
+                Lint::UnreachableCode,
+                "Unreachable code.".to_string(),
+                span,
+                label,
+            );
+        }
+    }
+
+    fn warn(&mut self, lint: Lint, message: String, span: Span, label: Label) {
+        if self.lints.is_enabled(lint) && span.is_known() {
+            let mut warning = Diagnostic::warning(lint.code(), message, span);
+            warning.labels.push(label);
+            self.warnings.push(warning);
+        }
+    }
+}
+
+/// Truthiness of a condition that is a literal
+fn constant(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Grouping(e) => constant(&e.expression),
+        Expr::Literal(e) => match e.value {
+            Literal::Boolean(b) => Some(b),
+            Literal::Nil => Some(false),
+            Literal::Number(_) | Literal::String(_) => Some(true),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::analyse;
+    use crate::{
+        lint::{Lint, Lints},
+        parser::Parser,
+        scanner::Scanner,
+    };
+
+    fn warnings(source: &str, lints: Lints) -> Vec<(String, usize)> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        analyse(&statements, &lints)
+            .into_iter()
+            .map(|w| (w.message, w.span.unwrap().line))
+            .collect()
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let source = "fun f(n) {
+            while (true) {
+                if (n) break;
+                continue;
+                print 1;
+            }
+            if (false) print 2;
+            if (true) return; else print 3;
+            print 4;
+        }
+        fun g() {
+            outer: while (true) { loop { break outer; } }
+            while (false) print 5;
+            do { return; } while (true);
+            print 6;
+        }
+        while (true) {}
+        print 7;";
+        let unreachable = |line| ("Unreachable code.".to_string(), line);
+        assert_eq!(
+            warnings(source, Lints::default()),
+            [5, 7, 8, 9, 13, 15, 18].map(unreachable)
+        );
+    }
+
+    #[test]
+    fn test_missing_return() {
+        let source = "
+            fun sign(n) {
+                if (n < 0) return -1;
+                if (n > 0) return 1;
+            }
+            fun abs(n) {
+                if (n < 0) return -n; else return n;
+            }
+            fun forever() {
+                while (true) { if (clock()) return 1; }
+            }
+            class A {
+                get(n) { fun inner() { return 1; } if (n) return; }
+            }
+        ";
+        let message = "'sign' can reach the end of its body without returning a value.";
+        assert_eq!(
+            warnings(source, Lints::default()),
+            [(message.to_string(), 2)]
+        );
+
+        let mut lints = Lints::default();
+        lints.set(Lint::MissingReturn, false);
+        assert!(warnings(source, lints).is_empty());
+    }
+}
@@ -4,6 +4,7 @@ use std::{cell::RefCell, rc::Rc, time::SystemTime};
 use crate::{
     environment::Environment,
     expr::{Literal, LoxCallable},
+    gc::{Node, Trace},
     interpreter::Interpreter,
     lox_class::LoxInstance,
     lox_result::LoxResult,
@@ -32,6 +33,36 @@ impl LoxCallable for Clock {
     fn to_string(&self) -> String {
         "<native fn>".to_string()
     }
+
+    fn name(&self) -> Rc<str> {
+        "clock".into()
+    }
+}
+
+/// Runs the cycle collector and returns how many objects it freed
+pub struct Gc;
+
+impl LoxCallable for Gc {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        _arguments: Vec<Literal>,
+    ) -> Result<Literal, LoxResult> {
+        let freed = interpreter.heap().collector().collect();
+        Ok(Literal::Number(freed as f64))
+    }
+
+    fn get_arity(&self) -> usize {
+        0
+    }
+
+    fn to_string(&self) -> String {
+        "<native fn>".to_string()
+    }
+
+    fn name(&self) -> Rc<str> {
+        "gc".into()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -39,6 +70,8 @@ pub struct LoxFunction {
     pub declaration: Rc<FunctionStmt>,
     closure: Rc<RefCell<Environment>>,
     is_initializer: bool,
+    /// Qualified with the class for methods, as shown in stack traces
+    name: Rc<str>,
 }
 
 impl LoxFunction {
@@ -48,22 +81,36 @@ impl LoxFunction {
         is_initializer: bool,
     ) -> Self {
         Self {
+            name: declaration.name.lexeme.as_str().into(),
             declaration,
             closure: Rc::clone(closure),
             is_initializer,
         }
     }
 
+    /// Marks the function as a method of `class`
+    pub fn in_class(mut self, class: &str) -> Self {
+        self.name = format!("{class}.{}", self.declaration.name.lexeme).into();
+        self
+    }
+
     pub fn bind_method(&self, instance: &Rc<RefCell<LoxInstance>>) -> LoxFunction {
         let environment = Environment::wrap(Rc::clone(&self.closure));
         environment
             .borrow_mut()
             .define("this", Literal::Instance(Rc::clone(instance)));
-        LoxFunction::new(
-            Rc::clone(&self.declaration),
-            &environment,
-            self.is_initializer,
-        )
+        LoxFunction {
+            declaration: Rc::clone(&self.declaration),
+            closure: environment,
+            is_initializer: self.is_initializer,
+            name: Rc::clone(&self.name),
+        }
+    }
+}
+
+impl Trace for LoxFunction {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.push(Node::Environment(Rc::clone(&self.closure)));
     }
 }
 
@@ -75,22 +122,35 @@ impl LoxCallable for LoxFunction {
         interpreter: &mut Interpreter,
         arguments: Vec<Literal>,
     ) -> Result<Literal, LoxResult> {
-        // Create a new nested environment (scope) for the block. Set enclosing to be the parent scope.
-        let environment = Environment::wrap(Rc::clone(&self.closure));
-        for (i, p) in self.declaration.params.iter().enumerate() {
-            environment
-                .borrow_mut()
-                .define(&p.lexeme, arguments.get(i).unwrap().clone())
-        }
+        let mut arguments = arguments;
+        // Trampoline: a tail call replaces the function being run rather than nesting a new call
+        let mut tail_call: Option<Rc<LoxFunction>> = None;
+        loop {
+            let function = tail_call.as_deref().unwrap_or(self);
 
-        match interpreter.execute_block(&self.declaration.body, environment) {
-            Err(LoxResult::Return(_)) if self.is_initializer => {
-                self.closure.borrow().get_at(&0, "this")
+            // Create a new nested environment (scope) for the block. Set enclosing to be the parent scope.
+            let environment = Environment::wrap(Rc::clone(&function.closure));
+            for (i, p) in function.declaration.params.iter().enumerate() {
+                environment
+                    .borrow_mut()
+                    .define(&p.lexeme, arguments.get(i).unwrap().clone())
             }
-            Err(LoxResult::Return(v)) => Ok(v),
-            Err(e) => Err(e),
-            Ok(_) if self.is_initializer => self.closure.borrow().get_at(&0, "this"),
-            Ok(_) => Ok(Literal::Nil),
+
+            return match interpreter.execute_block(&function.declaration.body, environment) {
+                Err(LoxResult::TailCall(next, next_arguments)) => {
+                    interpreter.replace_frame(next.name());
+                    tail_call = Some(next);
+                    arguments = next_arguments;
+                    continue;
+                }
+                Err(LoxResult::Return(_)) if function.is_initializer => {
+                    Ok(function.closure.borrow().get_at(0, 0))
+                }
+                Err(LoxResult::Return(v)) => Ok(v),
+                Err(e) => Err(e),
+                Ok(_) if function.is_initializer => Ok(function.closure.borrow().get_at(0, 0)),
+                Ok(_) => Ok(Literal::Nil),
+            };
         }
     }
 
@@ -101,4 +161,8 @@ impl LoxCallable for LoxFunction {
     fn to_string(&self) -> String {
         format!("<fn {}>", self.declaration.name.lexeme)
     }
+
+    fn name(&self) -> Rc<str> {
+        Rc::clone(&self.name)
+    }
 }
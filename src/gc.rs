@@ -0,0 +1,235 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    environment::Environment,
+    expr::Literal,
+    functions::LoxFunction,
+    lox_class::{LoxClass, LoxInstance},
+    lox_enum::LoxEnumValue,
+};
+
+/// Registrations after which a collection runs on its own, at the least
+const MIN_THRESHOLD: usize = 10_000;
+
+/// Reference-counted objects that can hold other objects. Cycles always pass through an
+/// environment or an instance, as those are the only ones that can be changed after creation.
+pub enum Node {
+    Environment(Rc<RefCell<Environment>>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    Function(Rc<LoxFunction>),
+    Class(Rc<LoxClass>),
+    EnumValue(Rc<LoxEnumValue>),
+}
+
+/// Reports the objects a value holds a strong reference to, once per reference.
+pub trait Trace {
+    fn trace(&self, edges: &mut Vec<Node>);
+}
+
+impl Trace for Literal {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        match self {
+            Literal::Function(f) => edges.push(Node::Function(Rc::clone(f))),
+            Literal::Instance(i) => edges.push(Node::Instance(Rc::clone(i))),
+            Literal::EnumValue(v) => edges.push(Node::EnumValue(Rc::clone(v))),
+            Literal::Class(c) => edges.push(Node::Class(Rc::clone(c))),
+            Literal::Identifier(_)
+            | Literal::Boolean(_)
+            | Literal::Nil
+            | Literal::String(_)
+            | Literal::Number(_)
+            | Literal::NativeFunction(..)
+            | Literal::Enum(_) => {}
+        }
+    }
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match self {
+            Node::Environment(e) => Rc::as_ptr(e) as *const () as usize,
+            Node::Instance(i) => Rc::as_ptr(i) as *const () as usize,
+            Node::Function(f) => Rc::as_ptr(f) as *const () as usize,
+            Node::Class(c) => Rc::as_ptr(c) as *const () as usize,
+            Node::EnumValue(v) => Rc::as_ptr(v) as *const () as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Environment(e) => Rc::strong_count(e),
+            Node::Instance(i) => Rc::strong_count(i),
+            Node::Function(f) => Rc::strong_count(f),
+            Node::Class(c) => Rc::strong_count(c),
+            Node::EnumValue(v) => Rc::strong_count(v),
+        }
+    }
+
+    /// Returns false if the node is borrowed at the moment and can't be looked into.
+    fn trace(&self, edges: &mut Vec<Node>) -> bool {
+        match self {
+            Node::Environment(e) => e.try_borrow().map(|e| e.trace(edges)).is_ok(),
+            Node::Instance(i) => i.try_borrow().map(|i| i.trace(edges)).is_ok(),
+            Node::Function(f) => {
+                f.trace(edges);
+                true
+            }
+            Node::Class(c) => {
+                c.trace(edges);
+                true
+            }
+            Node::EnumValue(v) => {
+                v.trace(edges);
+                true
+            }
+        }
+    }
+
+    /// Drops everything the node holds, which breaks any cycle going through it.
+    fn clear(&self) {
+        match self {
+            Node::Environment(e) => e.borrow_mut().clear(),
+            Node::Instance(i) => i.borrow_mut().clear(),
+            Node::Function(_) | Node::Class(_) | Node::EnumValue(_) => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    pub collections: usize,
+    /// Objects freed by all collections so far
+    pub freed: usize,
+    /// Environments and instances alive after the last collection
+    pub tracked: usize,
+}
+
+/// Cycle collector for environments and instances, which are otherwise freed by reference
+/// counting alone.
+///
+/// It uses trial deletion: an object whose strong references all come from other objects of
+/// the graph, rather than from the interpreter or a value being evaluated, can only be reached
+/// through a cycle. Whatever isn't reachable from the other objects is garbage.
+#[derive(Debug, Default)]
+pub struct Collector {
+    environments: RefCell<Vec<Weak<RefCell<Environment>>>>,
+    instances: RefCell<Vec<Weak<RefCell<LoxInstance>>>>,
+    registered: Cell<usize>,
+    threshold: Cell<usize>,
+    stats: Cell<GcStats>,
+}
+
+impl Collector {
+    pub fn register_environment(&self, environment: &Rc<RefCell<Environment>>) {
+        self.environments
+            .borrow_mut()
+            .push(Rc::downgrade(environment));
+        self.registered.set(self.registered.get() + 1);
+    }
+
+    pub fn register_instance(&self, instance: &Rc<RefCell<LoxInstance>>) {
+        self.instances.borrow_mut().push(Rc::downgrade(instance));
+        self.registered.set(self.registered.get() + 1);
+    }
+
+    /// Whether enough objects were registered since the last collection to run another
+    pub fn should_collect(&self) -> bool {
+        self.registered.get() >= self.threshold.get().max(MIN_THRESHOLD)
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats.get()
+    }
+
+    /// Frees unreachable cycles and returns how many objects were freed.
+    pub fn collect(&self) -> usize {
+        let mut pending = self.tracked();
+
+        // Find every object reachable from the tracked ones, with the references between them.
+        // Only `nodes` may keep a strong reference once this is done.
+        let mut nodes = HashMap::new();
+        let mut edges: HashMap<usize, Vec<usize>> = HashMap::new();
+        let mut opaque = HashSet::new();
+        while let Some(node) = pending.pop() {
+            let id = node.id();
+            if nodes.contains_key(&id) {
+                continue;
+            }
+            let mut children = Vec::new();
+            if !node.trace(&mut children) {
+                opaque.insert(id);
+            }
+            edges.insert(id, children.iter().map(Node::id).collect());
+            pending.extend(children);
+            nodes.insert(id, node);
+        }
+
+        // References not accounted for by the graph come from outside of it
+        let mut external: HashMap<usize, isize> = nodes
+            .iter()
+            .map(|(id, node)| (*id, node.strong_count() as isize - 1))
+            .collect();
+        for child in edges.values().flatten() {
+            *external.get_mut(child).unwrap() -= 1;
+        }
+
+        let mut reachable = HashSet::new();
+        let mut pending: Vec<usize> = external
+            .iter()
+            .filter(|(id, count)| **count > 0 || opaque.contains(*id))
+            .map(|(id, _)| *id)
+            .collect();
+        while let Some(id) = pending.pop() {
+            if reachable.insert(id) {
+                pending.extend(&edges[&id]);
+            }
+        }
+
+        let garbage: Vec<Node> = nodes
+            .into_iter()
+            .filter(|(id, _)| !reachable.contains(id))
+            .map(|(_, node)| node)
+            .collect();
+        garbage.iter().for_each(Node::clear);
+        let freed = garbage.len();
+        drop(garbage);
+
+        let tracked = self.prune();
+        self.registered.set(0);
+        self.threshold.set(tracked);
+        let stats = self.stats.get();
+        self.stats.set(GcStats {
+            collections: stats.collections + 1,
+            freed: stats.freed + freed,
+            tracked,
+        });
+        freed
+    }
+
+    fn tracked(&self) -> Vec<Node> {
+        let environments = self.environments.borrow();
+        let instances = self.instances.borrow();
+        let environments = environments
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Node::Environment);
+        let instances = instances
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(Node::Instance);
+        environments.chain(instances).collect()
+    }
+
+    /// Forgets objects that were freed and returns how many are left
+    fn prune(&self) -> usize {
+        let mut environments = self.environments.borrow_mut();
+        let mut instances = self.instances.borrow_mut();
+        environments.retain(|e| e.strong_count() > 0);
+        instances.retain(|i| i.strong_count() > 0);
+        environments.len() + instances.len()
+    }
+}
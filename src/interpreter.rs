@@ -1,53 +1,155 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
 
 use crate::{
     environment::Environment,
-    expr::{Expr, Literal, LoxCallable},
-    functions::{Clock, LoxFunction},
-    lox_class::LoxClass,
-    lox_result::LoxResult,
-    stmt::Stmt,
-    token::{Token, TokenType},
+    error_code::ErrorCode,
+    expr::{Expr, ExprId, Literal, LoxCallable},
+    functions::{Clock, Gc, LoxFunction},
+    gc::GcStats,
+    lox_class::{LoxClass, CLASS_BINDING},
+    lox_enum::LoxEnum,
+    lox_result::{LoxResult, TraceFrame},
+    memory::Heap,
+    stmt::{DeferStmt, Stmt},
+    suggest,
+    token::{Span, Token, TokenType},
 };
 
+/// Number of nested Lox calls allowed by default. Every call takes several Rust frames, so the
+/// interpreter has to run on a thread with a stack big enough for this many.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+/// A call being run, for stack traces
+struct CallFrame {
+    function: Rc<str>,
+    native: bool,
+    /// Where the function was called from
+    call_site: Span,
+    /// Functions that ran in this frame before `function` replaced them with a tail call
+    tail_calls: usize,
+}
+
+/// Where the resolver found a local variable: `depth` scopes out, in the given slot
+#[derive(Debug, Clone, Copy)]
+pub struct Local {
+    pub depth: usize,
+    pub slot: usize,
+}
+
 pub struct Interpreter {
     pub environment: Rc<RefCell<Environment>>,
-    locals: HashMap<Expr, usize>,
+    globals: Rc<RefCell<Environment>>,
+    /// Resolved locals by node id. Nodes without an entry refer to globals.
+    locals: HashMap<ExprId, Local>,
+    /// Locals in scope with a name close to that of a global, to suggest if it's undefined
+    similar_locals: HashMap<ExprId, String>,
+    /// Statements registered with `defer`, one list per block being executed
+    deferred: Vec<Vec<Rc<DeferStmt>>>,
+    /// Calls being run, outermost first
+    frames: Vec<CallFrame>,
+    max_call_depth: usize,
+    /// Statements and calls left before execution is interrupted, if limited
+    step_budget: Option<u64>,
+    deadline: Option<Instant>,
+    steps_used: u64,
+    heap: Rc<Heap>,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let mut environment = Environment::new(None);
+        let heap = Heap::new();
+        let mut environment = Environment::new(None, &heap);
 
         let clock = Literal::native_function(Clock);
         environment.define("clock", clock);
+        environment.define("gc", Literal::native_function(Gc));
 
+        let globals = Rc::new(RefCell::new(environment));
         Self {
-            environment: Rc::new(RefCell::new(environment)),
+            environment: Rc::clone(&globals),
+            globals,
             locals: HashMap::new(),
+            similar_locals: HashMap::new(),
+            deferred: Vec::new(),
+            frames: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            step_budget: None,
+            deadline: None,
+            steps_used: 0,
+            heap,
         }
     }
 
+    /// Limits how many Lox calls can be nested before "Stack overflow." is raised.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Limits how many more statements and calls can be executed, for running untrusted scripts.
+    /// `None` removes the limit.
+    pub fn set_step_budget(&mut self, steps: Option<u64>) {
+        self.step_budget = steps;
+    }
+
+    /// Interrupts execution once the deadline has passed. `None` removes the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Number of statements and calls executed so far
+    pub fn steps_used(&self) -> u64 {
+        self.steps_used
+    }
+
+    /// Raises "Memory limit exceeded." once the values held by the script would take more than
+    /// `bytes`. `None` removes the limit.
+    pub fn set_memory_limit(&mut self, bytes: Option<usize>) {
+        self.heap.set_limit(bytes);
+    }
+
+    /// Approximate bytes held by environments, instances and the values stored in them
+    pub fn memory_usage(&self) -> usize {
+        self.heap.current()
+    }
+
+    pub fn peak_memory_usage(&self) -> usize {
+        self.heap.peak()
+    }
+
+    /// Counts of the cycle collector's work so far
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.collector().stats()
+    }
+
+    pub fn heap(&self) -> &Rc<Heap> {
+        &self.heap
+    }
+
     pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), LoxResult> {
         for s in statements {
-            self.execute(s)?;
+            let mut result = self.execute(s);
+            // Errors raised outside of any call only have the script in their trace
+            self.add_stack_trace(&mut result);
+            result?;
         }
         Ok(())
     }
 
     fn execute(&mut self, s: &Stmt) -> Result<(), LoxResult> {
+        self.step()?;
         match s {
             Stmt::Block(s) => {
                 self.execute_block(&s.statements, Environment::wrap(self.environment.clone()))?;
             }
             Stmt::Class(s) => {
                 let superclass = if let Some(superclass) = &s.superclass {
-                    let sc = self.evaluate(&Expr::Variable(Box::new(superclass.clone())))?;
+                    let sc = self.look_up_variable(&superclass.name, superclass.id)?;
                     match sc {
-                        Literal::Class(c) => Some(Box::new(c)),
+                        Literal::Class(c) => Some(c),
                         _ => {
                             return Err(LoxResult::runtime_error(
                                 &superclass.name,
+                                ErrorCode::SuperclassNotClass,
                                 "Superclass must be a class.",
                             ));
                         }
@@ -56,15 +158,15 @@ impl Interpreter {
                     None
                 };
 
-                self.environment
-                    .borrow_mut()
-                    .define(&s.name.lexeme, Literal::Nil);
-
+                // Methods close over a scope holding `super` and, for private access, the class
+                // itself, which is only stored there once the methods exist
+                self.environment = Environment::wrap(self.environment.clone());
+                let scope = Rc::clone(&self.environment);
+                scope.borrow_mut().define(CLASS_BINDING, Literal::Nil);
                 if let Some(superclass) = &superclass {
-                    self.environment = Environment::wrap(self.environment.clone());
-                    self.environment
+                    scope
                         .borrow_mut()
-                        .define("super", Literal::Class(*superclass.clone()))
+                        .define("super", Literal::Class(Rc::clone(superclass)))
                 }
 
                 let mut methods = HashMap::new();
@@ -75,21 +177,66 @@ impl Interpreter {
                                 Rc::clone(f),
                                 &self.environment,
                                 f.name.lexeme.eq("init"),
-                            );
+                            )
+                            .in_class(&s.name.lexeme);
                             methods.insert(f.name.lexeme.clone(), function);
                         }
                         _ => unreachable!("I think"), // TODO: Validate
                     }
                 }
-                if superclass.is_some() {
-                    // TODO: std::mem::replace?
-                    let enclosing = self.environment.borrow_mut().enclosing.clone().unwrap();
-                    self.environment = enclosing;
+                // TODO: std::mem::replace?
+                let enclosing = self.environment.borrow_mut().enclosing.clone().unwrap();
+                self.environment = enclosing;
+                let abstract_methods = s
+                    .abstract_methods
+                    .iter()
+                    .map(|m| m.name.lexeme.clone())
+                    .collect();
+                // Defined only now, as the class scope above takes no slot in the current one
+                let class = LoxClass::new(&s.name.lexeme, superclass, methods, abstract_methods);
+                scope
+                    .borrow_mut()
+                    .assign_at(0, 0, Literal::Class(Rc::clone(&class)));
+                self.environment
+                    .borrow_mut()
+                    .define(&s.name.lexeme, Literal::Class(class));
+            }
+            Stmt::Break(s) => {
+                return Err(LoxResult::Break(s.label.as_ref().map(|l| l.lexeme.clone())));
+            }
+            Stmt::Continue(s) => {
+                return Err(LoxResult::Continue(
+                    s.label.as_ref().map(|l| l.lexeme.clone()),
+                ));
+            }
+            Stmt::Defer(s) => match self.deferred.last_mut() {
+                Some(deferred) => deferred.push(Rc::clone(s)),
+                None => unreachable!("Resolver rejects 'defer' outside of a block"),
+            },
+            Stmt::DoWhile(s) => loop {
+                match self.execute(&s.body) {
+                    Err(LoxResult::Break(label)) if is_jump_target(&label, &s.label) => break,
+                    Err(LoxResult::Continue(label)) if is_jump_target(&label, &s.label) => {}
+                    result => result?,
+                }
+                let condition = self.evaluate(&s.condition)?;
+                if !self.is_truthy(&condition) {
+                    break;
                 }
-                let class = LoxClass::new(&s.name.lexeme, superclass, methods);
+            },
+            Stmt::Enum(s) => {
+                let variants = s
+                    .variants
+                    .iter()
+                    .map(|v| {
+                        let fields = v.fields.iter().map(|f| f.lexeme.clone()).collect();
+                        (v.name.lexeme.clone(), fields)
+                    })
+                    .collect();
+                let enumeration = LoxEnum::new(&s.name.lexeme, variants);
                 self.environment
                     .borrow_mut()
-                    .assign(&s.name, Literal::Class(class))?;
+                    .define(&s.name.lexeme, Literal::Enum(Rc::new(enumeration)));
             }
             Stmt::Expression(s) => {
                 self.evaluate(&s.expression)?;
@@ -112,13 +259,31 @@ impl Interpreter {
                 let value = self.evaluate(&s.expression)?;
                 println!("{value}");
             }
-            Stmt::Return(s) => {
-                if let Some(v) = &s.value {
-                    return Err(LoxResult::Return(self.evaluate(v)?));
-                } else {
-                    return Err(LoxResult::Return(Literal::Nil));
+            Stmt::Return(s) => match &s.value {
+                Some(Expr::Call(call)) if s.tail_call => {
+                    let callee = self.evaluate(&call.callee)?;
+                    let mut arguments = Vec::new();
+                    for arg in call.arguments.iter() {
+                        arguments.push(self.evaluate(arg)?);
+                    }
+
+                    // Lox functions are handed back to the trampoline in `LoxFunction::call`
+                    // instead of growing the Rust stack. Anything else is called as usual.
+                    return match callee {
+                        Literal::Function(function) if arguments.len() == function.get_arity() => {
+                            self.step()?;
+                            Err(LoxResult::TailCall(function, arguments))
+                        }
+                        callee => Err(LoxResult::Return(self.call(
+                            callee,
+                            arguments,
+                            &call.paren,
+                        )?)),
+                    };
                 }
-            }
+                Some(v) => return Err(LoxResult::Return(self.evaluate(v)?)),
+                None => return Err(LoxResult::Return(Literal::Nil)),
+            },
             Stmt::Var(s) => {
                 let value = if let Some(initializer) = &s.initializer {
                     self.evaluate(initializer)?
@@ -126,11 +291,19 @@ impl Interpreter {
                     Literal::Nil
                 };
                 self.environment.borrow_mut().define(&s.name.lexeme, value);
+                self.check_memory(&s.name, 0)?;
             }
             Stmt::While(s) => {
                 let mut condition = self.evaluate(&s.condition)?;
                 while self.is_truthy(&condition) {
-                    self.execute(&s.body)?;
+                    match self.execute(&s.body) {
+                        Err(LoxResult::Break(label)) if is_jump_target(&label, &s.label) => break,
+                        Err(LoxResult::Continue(label)) if is_jump_target(&label, &s.label) => {}
+                        result => result?,
+                    }
+                    if let Some(increment) = &s.increment {
+                        self.evaluate(increment)?;
+                    }
                     condition = self.evaluate(&s.condition)?;
                 }
             }
@@ -138,8 +311,15 @@ impl Interpreter {
         Ok(())
     }
 
-    pub fn resolve(&mut self, expr: &Expr, depth: usize) {
-        self.locals.insert(expr.clone(), depth);
+    /// Records where the node `id` finds its variable, or that it's a global if `local` is `None`
+    pub fn resolve(&mut self, id: ExprId, local: Option<Local>) {
+        if let Some(local) = local {
+            self.locals.insert(id, local);
+        }
+    }
+
+    pub fn resolve_similar(&mut self, id: ExprId, local: &str) {
+        self.similar_locals.insert(id, local.to_string());
     }
 
     pub fn execute_block(
@@ -149,7 +329,24 @@ impl Interpreter {
     ) -> Result<(), LoxResult> {
         let previous = self.environment.clone();
         self.environment = environment;
-        let result = statements.iter().try_for_each(|s| self.execute(s));
+        self.deferred.push(Vec::new());
+        let mut result = statements.iter().try_for_each(|s| self.execute(s));
+
+        // Deferred expressions run last-in first-out in the block's own scope, however it exits.
+        // An error raised by one of them replaces a `return` or `break` but not an earlier error.
+        let deferred = self.deferred.pop().unwrap_or_default();
+        for defer in deferred.iter().rev() {
+            if let Err(e) = self.evaluate(&defer.expression) {
+                if !matches!(
+                    result,
+                    Err(LoxResult::RuntimeError { .. }
+                        | LoxResult::ParseError { .. }
+                        | LoxResult::Interrupted { .. })
+                ) {
+                    result = Err(e);
+                }
+            }
+        }
 
         self.environment = previous;
         result
@@ -168,7 +365,11 @@ impl Interpreter {
                     TokenType::Slash => {
                         let (n1, n2) = self.check_num(&left, &right, &e.operator)?;
                         if n2 == 0.0 {
-                            return Err(LoxResult::runtime_error(&e.operator, "Division by zero"));
+                            return Err(LoxResult::runtime_error(
+                                &e.operator,
+                                ErrorCode::DivisionByZero,
+                                "Division by zero",
+                            ));
                         }
                         Ok(Literal::Number(n1 / n2))
                     }
@@ -196,18 +397,24 @@ impl Interpreter {
                     TokenType::EqualEqual => Ok(Literal::Boolean(self.is_equal(left, right))),
                     TokenType::Plus => match (left, right) {
                         (Literal::String(mut s1), Literal::String(s2)) => {
+                            self.check_memory(&e.operator, s1.len() + s2.len())?;
                             s1.push_str(&s2);
                             Ok(Literal::String(s1))
                         }
                         (Literal::String(s), Literal::Number(n)) => {
-                            Ok(Literal::String(format!("{s}{n}")))
+                            let s = format!("{s}{n}");
+                            self.check_memory(&e.operator, s.len())?;
+                            Ok(Literal::String(s))
                         }
                         (Literal::Number(n), Literal::String(s)) => {
-                            Ok(Literal::String(format!("{n}{s}")))
+                            let s = format!("{n}{s}");
+                            self.check_memory(&e.operator, s.len())?;
+                            Ok(Literal::String(s))
                         }
                         (Literal::Number(n1), Literal::Number(n2)) => Ok(Literal::Number(n1 + n2)),
                         _ => Err(LoxResult::runtime_error(
                             &e.operator,
+                            ErrorCode::InvalidOperand,
                             "Operands must be two numbers or two strings.",
                         )),
                     },
@@ -221,64 +428,10 @@ impl Interpreter {
                     arguments.push(self.evaluate(arg)?);
                 }
 
-                match callee {
-                    Literal::Identifier(_)
-                    | Literal::Boolean(_)
-                    | Literal::Nil
-                    | Literal::String(_)
-                    | Literal::Instance(_)
-                    | Literal::Number(_) => Err(LoxResult::runtime_error(
-                        &e.paren,
-                        "Can only call functions and classes.",
-                    )),
-                    Literal::Function(function) => {
-                        if arguments.len() != function.get_arity() {
-                            return Err(LoxResult::runtime_error(
-                                &e.paren,
-                                &format!(
-                                    "Expected {} arguments but got {}.",
-                                    function.get_arity(),
-                                    arguments.len()
-                                ),
-                            ));
-                        }
-
-                        function.call(self, arguments)
-                    }
-                    Literal::NativeFunction(_, function) => {
-                        if arguments.len() != function.get_arity() {
-                            return Err(LoxResult::runtime_error(
-                                &e.paren,
-                                &format!(
-                                    "Expected {} arguments but got {}.",
-                                    function.get_arity(),
-                                    arguments.len()
-                                ),
-                            ));
-                        }
-
-                        function.call(self, arguments)
-                    }
-                    Literal::Class(class) => {
-                        // TODO: Is this the way to go or is there a cleaner implementation?
-                        let class = &class as &dyn LoxCallable;
-                        if arguments.len() != class.get_arity() {
-                            return Err(LoxResult::runtime_error(
-                                &e.paren,
-                                &format!(
-                                    "Expected {} arguments but got {}.",
-                                    class.get_arity(),
-                                    arguments.len()
-                                ),
-                            ));
-                        }
-
-                        class.call(self, arguments)
-                    }
-                }
+                self.call(callee, arguments, &e.paren)
             }
             Expr::Grouping(e) => self.evaluate(&e.expression),
-            Expr::Literal(e) => Ok(e.clone()), // TODO: ?
+            Expr::Literal(e) => Ok(e.value.clone()),
             Expr::Unary(e) => {
                 let right = self.evaluate(&e.right)?;
                 match e.operator.token_type {
@@ -286,6 +439,7 @@ impl Interpreter {
                         Literal::Number(n) => Ok(Literal::Number(-n)),
                         _ => Err(LoxResult::runtime_error(
                             &e.operator,
+                            ErrorCode::InvalidOperand,
                             "Operand must be a number.",
                         )),
                     },
@@ -301,20 +455,20 @@ impl Interpreter {
                     Ok(self.evaluate(&e.right)?)
                 }
             }
-            Expr::Variable(e) => self.look_up_variable(&e.name, expr),
+            Expr::Variable(e) => self.look_up_variable(&e.name, expr.id()),
             Expr::Assign(e) => {
                 let value = self.evaluate(&e.value)?;
-                if let Some(distance) = self.locals.get(expr) {
-                    self.environment.borrow_mut().assign_at(
-                        distance,
-                        &e.name.lexeme,
-                        value.clone(),
-                    )?;
-                } else {
+                if let Some(local) = self.locals.get(&expr.id()) {
                     self.environment
                         .borrow_mut()
-                        .assign(&e.name, value.clone())?;
+                        .assign_at(local.depth, local.slot, value.clone());
+                } else {
+                    let mut globals = self.globals.borrow_mut();
+                    globals
+                        .assign(&e.name, value.clone())
+                        .map_err(|_| self.undefined_global(&globals, &e.name, expr.id()))?;
                 }
+                self.check_memory(&e.name, 0)?;
                 Ok(value)
             }
             Expr::Logical(e) => {
@@ -340,9 +494,19 @@ impl Interpreter {
                     | Literal::Function(_)
                     | Literal::Class(_) => Err(LoxResult::runtime_error(
                         &e.name,
+                        ErrorCode::NotAnInstance,
                         "Only instances have properties.",
                     )),
-                    Literal::Instance(instance) => instance.borrow().get(&e.name, &instance),
+                    Literal::Enum(enumeration) => enumeration.get(&e.name),
+                    Literal::EnumValue(value) => value.get(&e.name),
+                    Literal::Instance(instance) => {
+                        if let TokenType::PrivateIdentifier(_) = e.name.token_type {
+                            let owner = self.private_owner(expr, &e.name)?;
+                            instance.borrow().get_private(&e.name, &owner, &instance)
+                        } else {
+                            instance.borrow().get(&e.name, &instance)
+                        }
+                    }
                 }
             }
             Expr::Set(e) => {
@@ -355,24 +519,36 @@ impl Interpreter {
                     | Literal::Number(_)
                     | Literal::NativeFunction(_, _)
                     | Literal::Function(_)
-                    | Literal::Class(_) => Err(LoxResult::runtime_error(
+                    | Literal::Class(_)
+                    | Literal::Enum(_)
+                    | Literal::EnumValue(_) => Err(LoxResult::runtime_error(
                         &e.name,
+                        ErrorCode::NotAnInstance,
                         "Only instances have fields.",
                     )),
                     Literal::Instance(instance) => {
                         let value = self.evaluate(&e.value)?;
-                        instance.borrow_mut().set(e.name.clone(), value.clone());
+                        if let TokenType::PrivateIdentifier(_) = e.name.token_type {
+                            let owner = self.private_owner(expr, &e.name)?;
+                            instance
+                                .borrow_mut()
+                                .set_private(&e.name, &owner, value.clone())?;
+                        } else {
+                            instance.borrow_mut().set(e.name.clone(), value.clone());
+                        }
+                        self.check_memory(&e.name, 0)?;
                         Ok(value)
                     }
                 }
             }
             Expr::Super(e) => {
-                let distance = self.locals.get(expr).unwrap();
-                let superclass = match self.environment.borrow().get_at(distance, "super")? {
+                let local = self.locals[&expr.id()];
+                let superclass = match self.environment.borrow().get_at(local.depth, local.slot) {
                     Literal::Class(c) => c,
                     _ => todo!("unreachable, must be a class"),
                 };
-                let object = match self.environment.borrow().get_at(&(distance - 1), "this")? {
+                // `this` is alone in the scope just inside the one holding `super`
+                let object = match self.environment.borrow().get_at(local.depth - 1, 0) {
                     Literal::Instance(i) => i,
                     _ => todo!("unreachable, must be an instance"),
                 };
@@ -380,33 +556,250 @@ impl Interpreter {
                 if let Some(Literal::Function(m)) = method {
                     Ok(Literal::Function(Rc::new(m.bind_method(&object))))
                 } else {
+                    let message = format!("Undefined property '{}'.", e.method.lexeme);
+                    let names = superclass.method_names();
                     Err(LoxResult::runtime_error(
                         &e.method,
-                        &format!("Undefined property '{p}'.", p = e.method.lexeme),
+                        ErrorCode::UndefinedProperty,
+                        &suggest::did_you_mean(message, &e.method.lexeme, names),
                     ))
                 }
             }
-            Expr::This(e) => self.look_up_variable(&e.keyword, expr),
+            Expr::This(e) => self.look_up_variable(&e.keyword, expr.id()),
         }
     }
 
-    fn look_up_variable(&self, name: &Token, expr: &Expr) -> Result<Literal, LoxResult> {
-        match expr {
-            Expr::This(_) => {
-                if let Some(distance) = self.locals.get(expr) {
-                    self.environment.borrow().get_at(distance, &name.lexeme)
-                } else {
-                    self.environment.borrow().get(name)
+    fn call(
+        &mut self,
+        callee: Literal,
+        arguments: Vec<Literal>,
+        paren: &Token,
+    ) -> Result<Literal, LoxResult> {
+        self.step()?;
+        self.check_memory(paren, 0)?;
+        match callee {
+            Literal::Identifier(_)
+            | Literal::Boolean(_)
+            | Literal::Nil
+            | Literal::String(_)
+            | Literal::Instance(_)
+            | Literal::Number(_) => Err(LoxResult::runtime_error(
+                paren,
+                ErrorCode::NotCallable,
+                "Can only call functions and classes.",
+            )),
+            Literal::EnumValue(variant) => {
+                if !variant.is_constructor() {
+                    return Err(LoxResult::runtime_error(
+                        paren,
+                        ErrorCode::NotCallable,
+                        "Can only call functions and classes.",
+                    ));
+                }
+                if arguments.len() != variant.get_arity() {
+                    return Err(LoxResult::runtime_error(
+                        paren,
+                        ErrorCode::WrongArity,
+                        &format!(
+                            "Expected {} arguments but got {}.",
+                            variant.get_arity(),
+                            arguments.len()
+                        ),
+                    ));
                 }
+
+                Ok(variant.construct(arguments))
             }
-            Expr::Variable(_) => {
-                if let Some(distance) = self.locals.get(expr) {
-                    self.environment.borrow().get_at(distance, &name.lexeme)
-                } else {
-                    self.environment.borrow().get(name)
+            // Enums are called with an ordinal to look up a variant, e.g. `Color(0)`
+            Literal::Enum(enumeration) => {
+                if arguments.len() != 1 {
+                    return Err(LoxResult::runtime_error(
+                        paren,
+                        ErrorCode::WrongArity,
+                        &format!("Expected 1 arguments but got {}.", arguments.len()),
+                    ));
                 }
+
+                enumeration.variant_at(&arguments[0]).ok_or_else(|| {
+                    LoxResult::runtime_error(
+                        paren,
+                        ErrorCode::InvalidOrdinal,
+                        &format!(
+                            "Enum {} has no variant with ordinal {}.",
+                            enumeration.name, arguments[0]
+                        ),
+                    )
+                })
+            }
+            Literal::Function(function) => {
+                self.call_callable(function.as_ref(), arguments, paren, false)
+            }
+            Literal::NativeFunction(_, function) => {
+                self.call_callable(function.as_ref(), arguments, paren, true)
+            }
+            Literal::Class(class) => {
+                // TODO: Is this the way to go or is there a cleaner implementation?
+                self.call_callable(class.as_ref(), arguments, paren, false)
             }
-            _ => unreachable!("Only This and Variable exprs call this function"),
+        }
+    }
+
+    /// Checks the arity and keeps track of the call depth, so that runaway recursion is
+    /// reported as a runtime error before it can overflow the native stack.
+    fn call_callable(
+        &mut self,
+        callable: &dyn LoxCallable,
+        arguments: Vec<Literal>,
+        paren: &Token,
+        native: bool,
+    ) -> Result<Literal, LoxResult> {
+        if arguments.len() != callable.get_arity() {
+            return Err(LoxResult::runtime_error(
+                paren,
+                ErrorCode::WrongArity,
+                &format!(
+                    "Expected {} arguments but got {}.",
+                    callable.get_arity(),
+                    arguments.len()
+                ),
+            ));
+        }
+        if self.frames.len() >= self.max_call_depth {
+            return Err(LoxResult::runtime_error(
+                paren,
+                ErrorCode::StackOverflow,
+                "Stack overflow.",
+            ));
+        }
+
+        self.frames.push(CallFrame {
+            function: callable.name(),
+            native,
+            call_site: paren.span,
+            tail_calls: 0,
+        });
+        let mut result = callable.call(self, arguments);
+        self.add_stack_trace(&mut result);
+        self.frames.pop();
+        result
+    }
+
+    /// The `)` of the innermost call being run, for errors raised by the callable itself. Outside
+    /// of any call, e.g. when called by an embedder, it has no position.
+    pub fn call_site(&self) -> Token {
+        let span = self
+            .frames
+            .last()
+            .map_or(Span::default(), |frame| frame.call_site);
+        Token::with_span(TokenType::RightParen, ")".to_string(), span)
+    }
+
+    /// Renames the innermost frame for a tail call, which runs in place of the function called,
+    /// and counts the call it replaces so that traces can tell it was there
+    pub fn replace_frame(&mut self, function: Rc<str>) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.function = function;
+            frame.tail_calls += 1;
+        }
+    }
+
+    /// Records the calls being run in a runtime error that doesn't have a trace yet
+    fn add_stack_trace<T>(&self, result: &mut Result<T, LoxResult>) {
+        let Err(LoxResult::RuntimeError { token, trace, .. }) = result else {
+            return;
+        };
+        if !trace.is_empty() {
+            return;
+        }
+        // Each frame is at the line it called the next one from, the innermost at the error
+        let mut line = Some(token.line);
+        for frame in self.frames.iter().rev() {
+            trace.push(TraceFrame {
+                function: Rc::clone(&frame.function),
+                line: if frame.native { None } else { line },
+                tail_calls: frame.tail_calls,
+            });
+            line = Some(frame.call_site.line);
+        }
+        trace.push(TraceFrame {
+            function: "<script>".into(),
+            line,
+            tail_calls: 0,
+        });
+    }
+
+    /// Counts one statement or call against the step budget and checks the deadline
+    fn step(&mut self) -> Result<(), LoxResult> {
+        if self.heap.collector().should_collect() {
+            self.heap.collector().collect();
+        }
+        if self.step_budget == Some(0) {
+            return Err(LoxResult::Interrupted {
+                code: ErrorCode::StepBudget,
+                message: "Step budget exhausted.".to_string(),
+            });
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(LoxResult::Interrupted {
+                code: ErrorCode::Deadline,
+                message: "Deadline exceeded.".to_string(),
+            });
+        }
+        if let Some(budget) = &mut self.step_budget {
+            *budget -= 1;
+        }
+        self.steps_used += 1;
+        Ok(())
+    }
+
+    /// Raises a runtime error if holding `bytes` more would go over the memory limit
+    fn check_memory(&self, token: &Token, bytes: usize) -> Result<(), LoxResult> {
+        if self.heap.would_exceed(bytes) {
+            Err(LoxResult::runtime_error(
+                token,
+                ErrorCode::MemoryLimit,
+                "Memory limit exceeded.",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn look_up_variable(&self, name: &Token, id: ExprId) -> Result<Literal, LoxResult> {
+        if let Some(local) = self.locals.get(&id) {
+            Ok(self.environment.borrow().get_at(local.depth, local.slot))
+        } else {
+            let globals = self.globals.borrow();
+            globals
+                .get(name)
+                .map_err(|_| self.undefined_global(&globals, name, id))
+        }
+    }
+
+    /// Also suggests the locals the resolver found near where the global is used
+    fn undefined_global(&self, globals: &Environment, name: &Token, id: ExprId) -> LoxResult {
+        globals.undefined(name, self.similar_locals.get(&id).map(String::as_str))
+    }
+
+    /// The class whose body lexically encloses the code currently running.
+    fn private_owner(&self, expr: &Expr, name: &Token) -> Result<Rc<LoxClass>, LoxResult> {
+        let owner = self
+            .locals
+            .get(&expr.id())
+            .map(|local| self.environment.borrow().get_at(local.depth, local.slot));
+        match owner {
+            Some(Literal::Class(class)) => Ok(class),
+            _ => Err(LoxResult::runtime_error(
+                name,
+                ErrorCode::PrivateAccess,
+                &format!(
+                    "Can't access private property '{}' outside of its class.",
+                    name.lexeme
+                ),
+            )),
         }
     }
 
@@ -418,7 +811,11 @@ impl Interpreter {
     ) -> Result<(f64, f64), LoxResult> {
         match (left, right) {
             (Literal::Number(n1), Literal::Number(n2)) => Ok((*n1, *n2)),
-            _ => Err(LoxResult::runtime_error(op, "Operands must be numbers.")),
+            _ => Err(LoxResult::runtime_error(
+                op,
+                ErrorCode::InvalidOperand,
+                "Operands must be numbers.",
+            )),
         }
     }
 
@@ -432,6 +829,8 @@ impl Interpreter {
             | Literal::Number(_)
             | Literal::Class(_)
             | Literal::Instance(_)
+            | Literal::Enum(_)
+            | Literal::EnumValue(_)
             | Literal::NativeFunction(_, _)
             | Literal::Function(_) => true,
         }
@@ -447,21 +846,69 @@ impl Interpreter {
     }
 }
 
-#[cfg(test)]
+/// Whether a `break` or `continue`, optionally labeled, applies to a loop with the given label.
+fn is_jump_target(jump: &Option<String>, label: &Option<Token>) -> bool {
+    match jump {
+        None => true,
+        Some(jump) => label.as_ref().is_some_and(|l| &l.lexeme == jump),
+    }
+}
 
+#[cfg(test)]
 mod tests {
     use crate::{
-        expr::{BinaryExpr, Expr, Literal},
-        token::{Token, TokenType},
+        expr::{BinaryExpr, Expr, ExprId, Literal, LiteralExpr, LoxCallable},
+        lox_result::LoxResult,
+        parser::Parser,
+        resolver::Resolver,
+        scanner::Scanner,
+        token::{Span, Token, TokenType},
     };
 
     use super::Interpreter;
+    use std::time::{Duration, Instant};
+
+    fn run_in(interpreter: &mut Interpreter, source: &str) -> Result<(), LoxResult> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        Resolver::new(interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+        interpreter.interpret(&statements)
+    }
+
+    fn run(source: &str) -> (Interpreter, Result<(), LoxResult>) {
+        let mut interpreter = Interpreter::new();
+        let result = run_in(&mut interpreter, source);
+        (interpreter, result)
+    }
+
+    fn literal(value: Literal) -> Expr {
+        Expr::Literal(Box::new(LiteralExpr::new(
+            ExprId(0),
+            value,
+            Span::default(),
+        )))
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Literal {
+        let name = Token::new(TokenType::Identifier(name.to_owned()), name.to_owned(), 1);
+        interpreter.environment.borrow().get(&name).unwrap()
+    }
+
+    fn runtime_error(result: Result<(), LoxResult>) -> String {
+        match result {
+            Err(LoxResult::RuntimeError { message, .. }) => message,
+            r => panic!("Expected a runtime error, got {r:?}"),
+        }
+    }
 
     #[test]
     fn test_multiplication() {
-        let left = Expr::Literal(Literal::Number(2.0));
-        let right = Expr::Literal(Literal::Number(8.0));
+        let left = literal(Literal::Number(2.0));
+        let right = literal(Literal::Number(8.0));
         let e = Expr::Binary(Box::new(BinaryExpr::new(
+            ExprId(0),
             left,
             Token::new(TokenType::Star, "*".to_owned(), 1),
             right,
@@ -478,16 +925,19 @@ mod tests {
     #[test]
     fn test_precedence() {
         let left = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(1.0)),
+            ExprId(0),
+            literal(Literal::Number(1.0)),
             Token::new(TokenType::Plus, "+".to_owned(), 1),
-            Expr::Literal(Literal::Number(2.0)),
+            literal(Literal::Number(2.0)),
         )));
         let right = Expr::Binary(Box::new(BinaryExpr::new(
-            Expr::Literal(Literal::Number(4.0)),
+            ExprId(0),
+            literal(Literal::Number(4.0)),
             Token::new(TokenType::Minus, "-".to_owned(), 1),
-            Expr::Literal(Literal::Number(5.0)),
+            literal(Literal::Number(5.0)),
         )));
         let e = Expr::Binary(Box::new(BinaryExpr::new(
+            ExprId(0),
             left,
             Token::new(TokenType::Star, "*".to_owned(), 1),
             right,
@@ -500,4 +950,532 @@ mod tests {
             Err(_) => unreachable!(),
         }
     }
+
+    fn trace(result: Result<(), LoxResult>) -> Vec<String> {
+        match result {
+            Err(LoxResult::RuntimeError { trace, .. }) => {
+                trace.iter().map(|frame| frame.to_string()).collect()
+            }
+            r => panic!("Expected a runtime error, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let (_, result) = run(r#"
+            class Foo {
+                bar(x) {
+                    return x + nil;
+                }
+            }
+            fun outer() {
+                var result = Foo().bar(1);
+                return result;
+            }
+            fun forward() { return outer(); }
+            forward();
+        "#);
+        assert_eq!(
+            trace(result),
+            [
+                "at Foo.bar (line 4)",
+                "at outer (line 8) [1 tail call elided]",
+                "at <script> (line 12)"
+            ]
+        );
+
+        // Tail calls run in the frame of the call they replace, which says how many it took
+        let (_, result) = run(r#"
+            class Foo { bar() { return baz(); } }
+            fun baz() { return qux(); }
+            fun qux() { return -nil; }
+            fun top() { var r = Foo().bar(); return r; }
+            top();
+        "#);
+        assert_eq!(
+            trace(result),
+            [
+                "at qux (line 4) [2 tail calls elided]",
+                "at top (line 5)",
+                "at <script> (line 6)"
+            ]
+        );
+
+        let (interpreter, result) = run("var x = 1;\n-\"a\";");
+        assert_eq!(trace(result), ["at <script> (line 2)"]);
+        assert!(interpreter.frames.is_empty());
+    }
+
+    #[test]
+    fn test_suggestions() {
+        let cases = [
+            ("var length = 1; print lenght;", "Did you mean 'length'?"),
+            ("fun f(count) { cuont = 2; } f(1);", "Did you mean 'count'?"),
+            (
+                "class A { area() {} } class B < A { init() { this.side = 1; } }
+                 B().aera();",
+                "Did you mean 'area'?",
+            ),
+            (
+                "class A { init() { this.side = 1; } } A().sied;",
+                "Did you mean 'side'?",
+            ),
+            (
+                "class A { size() {} } class B < A { f() { super.szie(); } } B().f();",
+                "Did you mean 'size'?",
+            ),
+            (
+                "enum Colour { Red, Green } Colour.Gren;",
+                "Did you mean 'Green'?",
+            ),
+        ];
+        for (source, suggestion) in cases {
+            let message = runtime_error(run(source).1);
+            assert!(message.ends_with(suggestion), "{source}: {message}");
+        }
+        let (_, result) = run("var apple = 1; print zebra;");
+        assert_eq!(runtime_error(result), "Undefined variable 'zebra'.");
+    }
+
+    #[test]
+    fn test_private_members() {
+        let (interpreter, result) = run(r#"
+            class Counter {
+                init() { this.#count = 0; }
+                increment() { this.#count = this.#count + 1; return this.#current(); }
+                #current() { return this.#count; }
+                same(other) { return other.#count == this.#count; }
+            }
+            class Sub < Counter {
+                peek() { return this.#count; }
+            }
+            var c = Counter();
+            c.increment();
+            var count = c.increment();
+            var same = c.same(c);
+            var sub = Sub();
+            sub.increment();
+        "#);
+        result.unwrap();
+        assert_eq!(global(&interpreter, "count"), Literal::Number(2.0));
+        assert_eq!(global(&interpreter, "same"), Literal::Boolean(true));
+
+        // Private fields are keyed by their declaring class, subclasses can't see them
+        let (_, result) = run(r#"
+            class Counter { init() { this.#count = 0; } }
+            class Sub < Counter { peek() { return this.#count; } }
+            Sub().peek();
+        "#);
+        assert_eq!(runtime_error(result), "Undefined property '#count'.");
+
+        let (_, result) = run(r#"
+            class A { peek(other) { return other.#secret; } }
+            class B { init() { this.#secret = 1; } }
+            A().peek(B());
+        "#);
+        assert_eq!(
+            runtime_error(result),
+            "Can't access private property '#secret' of a B instance from class A."
+        );
+
+        // Another class with the same name is still another class
+        let (_, result) = run(r#"
+            class Box { init() { this.#v = "secret"; } }
+            fun spy() { class Box { steal(o) { return o.#v; } } return Box().steal; }
+            spy()(Box());
+        "#);
+        assert_eq!(
+            runtime_error(result),
+            "Can't access private property '#v' of a Box instance from class Box."
+        );
+        let (interpreter, result) = run(r#"
+            fun make() {
+                class Box { init(v) { this.#v = v; } peek(o) { return o.#v; } }
+                return Box;
+            }
+            var A = make();
+            var B = make();
+            var same = A == B;
+            var own = A(1).peek(A(2));
+            B(1).peek(A(2));
+        "#);
+        assert_eq!(global(&interpreter, "same"), Literal::Boolean(false));
+        assert_eq!(global(&interpreter, "own"), Literal::Number(2.0));
+        assert_eq!(
+            runtime_error(result),
+            "Can't access private property '#v' of a Box instance from class Box."
+        );
+    }
+
+    #[test]
+    fn test_abstract_methods() {
+        let (_, result) = run(r#"
+            class Shape {
+                abstract area();
+                abstract perimeter();
+                describe() { return "Shape with area " + this.area(); }
+            }
+            class Square < Shape {
+                init(side) { this.side = side; }
+                area() { return this.side * this.side; }
+            }
+            Square(2);
+        "#);
+        assert_eq!(
+            runtime_error(result),
+            "Can't instantiate abstract class Square without an implementation of 'perimeter'."
+        );
+
+        // Also refused when the class is called other than from Lox code
+        let (mut interpreter, result) = run("class Shape { abstract area(); }");
+        result.unwrap();
+        let Literal::Class(shape) = global(&interpreter, "Shape") else {
+            panic!("Shape is a class");
+        };
+        let result = shape.call(&mut interpreter, Vec::new()).map(|_| ());
+        assert_eq!(
+            runtime_error(result),
+            "Can't instantiate abstract class Shape without an implementation of 'area'."
+        );
+
+        let (interpreter, result) = run(r#"
+            class Shape {
+                abstract area();
+                describe() { return "Shape with area " + this.area(); }
+            }
+            class Square < Shape {
+                init(side) { this.side = side; }
+                area() { return this.side * this.side; }
+            }
+            var description = Square(2).describe();
+        "#);
+        result.unwrap();
+        assert_eq!(
+            global(&interpreter, "description"),
+            Literal::String("Shape with area 4".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_do_while_and_loop() {
+        let (interpreter, result) = run(r#"
+            var runs = 0;
+            do runs = runs + 1; while (false);
+
+            var i = 0;
+            do {
+                i = i + 1;
+            } while (i < 5);
+
+            fun first_square_above(n) {
+                var k = 0;
+                loop {
+                    if (k * k > n) return k;
+                    k = k + 1;
+                }
+            }
+            var k = first_square_above(50);
+        "#);
+        result.unwrap();
+        assert_eq!(global(&interpreter, "runs"), Literal::Number(1.0));
+        assert_eq!(global(&interpreter, "i"), Literal::Number(5.0));
+        assert_eq!(global(&interpreter, "k"), Literal::Number(8.0));
+    }
+
+    #[test]
+    fn test_enums() {
+        let (interpreter, result) = run(r#"
+            enum Color { Red, Green, Blue }
+            var names = "";
+            for (var i = 0; i < Color.count; i = i + 1) {
+                names = names + Color(i).name;
+            }
+            var same = Color.Green == Color(1);
+            var different = Color.Green == Color.Blue;
+            var ordinal = Color.Blue.ordinal;
+
+            enum Shape { Circle(radius), Rect(width, height) }
+            var circle = Shape.Circle(2);
+            var radius = circle.radius;
+            var area = Shape.Rect(2, 3).width * Shape.Rect(2, 3).height;
+        "#);
+        result.unwrap();
+        assert_eq!(
+            global(&interpreter, "names"),
+            Literal::String("RedGreenBlue".to_owned())
+        );
+        assert_eq!(global(&interpreter, "same"), Literal::Boolean(true));
+        assert_eq!(global(&interpreter, "different"), Literal::Boolean(false));
+        assert_eq!(global(&interpreter, "ordinal"), Literal::Number(2.0));
+        assert_eq!(
+            global(&interpreter, "circle").to_string(),
+            "Shape.Circle(2)"
+        );
+        assert_eq!(global(&interpreter, "radius"), Literal::Number(2.0));
+        assert_eq!(global(&interpreter, "area"), Literal::Number(6.0));
+
+        let (_, result) = run("enum Color { Red } Color.Purple;");
+        assert_eq!(runtime_error(result), "Undefined variant 'Purple'.");
+        let (_, result) = run("enum Color { Red } Color(1);");
+        assert_eq!(
+            runtime_error(result),
+            "Enum Color has no variant with ordinal 1."
+        );
+    }
+
+    #[test]
+    fn test_defer() {
+        let (interpreter, result) = run(r#"
+            var log = "";
+            fun work(fail) {
+                defer log = log + "1";
+                defer log = log + "2";
+                {
+                    defer log = log + "b";
+                }
+                if (fail) return "early";
+                log = log + "e";
+                return "done";
+            }
+            var early = work(true);
+            log = log + "|";
+            var done = work(false);
+            log = log + "|";
+            fun fails() {
+                defer log = log + "cleanup";
+                nil.field;
+            }
+            fails();
+        "#);
+        assert_eq!(runtime_error(result), "Only instances have properties.");
+        assert_eq!(
+            global(&interpreter, "early"),
+            Literal::String("early".to_owned())
+        );
+        assert_eq!(
+            global(&interpreter, "log"),
+            Literal::String("b21|be21|cleanup".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_labeled_break_and_continue() {
+        let (interpreter, result) = run(r#"
+            var pairs = "";
+            outer: for (var i = 0; i < 3; i = i + 1) {
+                for (var j = 0; j < 3; j = j + 1) {
+                    if (j == 1) continue outer;
+                    if (i == 2) break outer;
+                    pairs = pairs + i + j + " ";
+                }
+            }
+            var n = 0;
+            loop {
+                n = n + 1;
+                if (n < 5) continue;
+                break;
+            }
+        "#);
+        result.unwrap();
+        assert_eq!(
+            global(&interpreter, "pairs"),
+            Literal::String("00 10 ".to_owned())
+        );
+        assert_eq!(global(&interpreter, "n"), Literal::Number(5.0));
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        // Far deeper than the test thread's stack allows without reusing frames. The tiny call
+        // depth limit shows the frames are reused rather than just fitting under the default one.
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_call_depth(3);
+        let result = run_in(
+            &mut interpreter,
+            r#"
+            fun count(n, acc) {
+                if (n == 0) return acc;
+                return count(n - 1, acc + 1);
+            }
+            var counted = count(1000000, 0);
+
+            fun is_even(n) {
+                if (n == 0) return true;
+                return is_odd(n - 1);
+            }
+            fun is_odd(n) {
+                if (n == 0) return false;
+                return is_even(n - 1);
+            }
+            var even = is_even(1000001);
+        "#,
+        );
+        result.unwrap();
+        assert_eq!(global(&interpreter, "counted"), Literal::Number(1000000.0));
+        assert_eq!(global(&interpreter, "even"), Literal::Boolean(false));
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        // Every Lox call takes several Rust frames, more than the test thread has room for
+        crate::with_interpreter_stack(stack_overflow)
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+    }
+
+    fn stack_overflow() {
+        let source = r#"
+            fun count(n) {
+                if (n == 0) return 0;
+                return 1 + count(n - 1);
+            }
+        "#;
+        let (mut interpreter, result) = run(source);
+        result.unwrap();
+        interpreter.set_max_call_depth(50);
+
+        run_in(&mut interpreter, "var ok = count(49);").unwrap();
+        assert_eq!(global(&interpreter, "ok"), Literal::Number(49.0));
+        let result = run_in(&mut interpreter, "count(50);");
+        assert_eq!(runtime_error(result), "Stack overflow.");
+
+        // The depth unwinds with the error, so the interpreter stays usable
+        run_in(&mut interpreter, "ok = count(10);").unwrap();
+        assert_eq!(global(&interpreter, "ok"), Literal::Number(10.0));
+    }
+
+    #[test]
+    fn test_step_budget() {
+        let (mut interpreter, result) = run("var i = 0; while (i < 10) i = i + 1;");
+        result.unwrap();
+        // The var, the while and ten executions of its body
+        assert_eq!(interpreter.steps_used(), 12);
+
+        interpreter.set_step_budget(Some(1000));
+        let result = run_in(&mut interpreter, "while (true) {}");
+        assert!(
+            matches!(&result, Err(LoxResult::Interrupted { message, .. }) if message == "Step budget exhausted."),
+            "{result:?}"
+        );
+        assert_eq!(interpreter.steps_used(), 1012);
+
+        // Calls count too, including the ones a tail call runs in place of its caller
+        interpreter.set_step_budget(Some(1000));
+        let result = run_in(
+            &mut interpreter,
+            "fun spin(n) { return spin(n + 1); } spin(0);",
+        );
+        assert!(matches!(result, Err(LoxResult::Interrupted { .. })));
+
+        interpreter.set_step_budget(None);
+        interpreter.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+        let result = run_in(&mut interpreter, "while (true) {}");
+        assert!(
+            matches!(&result, Err(LoxResult::Interrupted { message, .. }) if message == "Deadline exceeded."),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let (mut interpreter, result) = run(r#"
+            class Node {
+                init(next) {
+                    this.next = next;
+                }
+            }
+            var list = nil;
+            for (var i = 0; i < 1000; i = i + 1) list = Node(list);
+        "#);
+        result.unwrap();
+        let with_list = interpreter.memory_usage();
+        run_in(&mut interpreter, "list = nil;").unwrap();
+        assert!(interpreter.memory_usage() < with_list);
+        assert!(interpreter.peak_memory_usage() >= with_list);
+
+        interpreter.set_memory_limit(Some(interpreter.memory_usage() + 10_000));
+        let result = run_in(&mut interpreter, r#"var s = "x"; while (true) s = s + s;"#);
+        assert_eq!(runtime_error(result), "Memory limit exceeded.");
+        let result = run_in(&mut interpreter, "loop { list = Node(list); }");
+        assert_eq!(runtime_error(result), "Memory limit exceeded.");
+    }
+
+    #[test]
+    fn test_cycle_collection() {
+        let (mut interpreter, result) = run(r#"
+            class Node {}
+            fun make_cycles() {
+                for (var i = 0; i < 100; i = i + 1) {
+                    // A closure stored in its own scope and an instance holding itself
+                    fun recurse() { return recurse; }
+                    var node = Node();
+                    node.self = node;
+                    node.method = recurse;
+                }
+            }
+            make_cycles();
+            var kept = Node();
+            kept.self = kept;
+        "#);
+        result.unwrap();
+        let before = interpreter.memory_usage();
+        run_in(&mut interpreter, "var freed = gc();").unwrap();
+        let Literal::Number(freed) = global(&interpreter, "freed") else {
+            panic!("gc() should return a number");
+        };
+        assert!(freed >= 200.0, "freed {freed}");
+        assert!(interpreter.memory_usage() < before);
+
+        // Nothing that's still reachable is touched
+        run_in(&mut interpreter, "var again = gc();").unwrap();
+        assert_eq!(global(&interpreter, "again"), Literal::Number(0.0));
+        run_in(&mut interpreter, "var same = kept.self == kept;").unwrap();
+        assert_eq!(global(&interpreter, "same"), Literal::Boolean(true));
+        let stats = interpreter.gc_stats();
+        assert_eq!(stats.collections, 2);
+        assert_eq!(stats.freed, freed as usize);
+    }
+
+    #[test]
+    fn test_closures_see_resolved_variables() {
+        let (interpreter, result) = run(r#"
+            var a = "global";
+            var seen = "";
+            {
+                fun show() { seen = seen + a; }
+                show();
+                var a = "block";
+                show();
+                seen = seen + a;
+            }
+        "#);
+        result.unwrap();
+        assert_eq!(
+            global(&interpreter, "seen"),
+            Literal::String("globalglobalblock".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_cloned_ast_stays_resolved() {
+        let source = r#"
+            var total = 0;
+            {
+                var a = 1;
+                fun add(b) { total = total + a + b; }
+                add(2);
+            }
+        "#;
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+
+        let copy = statements.clone();
+        drop(statements);
+        interpreter.interpret(&copy).unwrap();
+        interpreter.interpret(&copy.clone()).unwrap();
+        assert_eq!(global(&interpreter, "total"), Literal::Number(3.0));
+    }
 }
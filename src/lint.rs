@@ -0,0 +1,66 @@
+use crate::error_code::ErrorCode;
+
+/// A warning the resolver or the flow analysis can report. Each can be turned off with `--allow=<name>` and back on
+/// with `--warn=<name>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    Shadowing,
+    UnreachableCode,
+    MissingReturn,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 5] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::Shadowing,
+        Lint::UnreachableCode,
+        Lint::MissingReturn,
+    ];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::Shadowing => "shadowing",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::MissingReturn => "missing-return",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    pub fn code(self) -> ErrorCode {
+        match self {
+            Lint::UnusedVariable => ErrorCode::UnusedVariable,
+            Lint::UnusedParameter => ErrorCode::UnusedParameter,
+            Lint::Shadowing => ErrorCode::Shadowing,
+            Lint::UnreachableCode => ErrorCode::UnreachableCode,
+            Lint::MissingReturn => ErrorCode::MissingReturn,
+        }
+    }
+}
+
+/// Which lints are reported. All of them are by default.
+#[derive(Debug, Clone, Default)]
+pub struct Lints {
+    allowed: Vec<Lint>,
+}
+
+impl Lints {
+    pub fn set(&mut self, lint: Lint, enabled: bool) {
+        self.allowed.retain(|allowed| *allowed != lint);
+        if !enabled {
+            self.allowed.push(lint);
+        }
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        !self.allowed.contains(&lint)
+    }
+}
@@ -1,31 +1,81 @@
-use std::{cell::RefCell, collections::HashMap, fmt::Display, hash::Hash, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    hash::Hash,
+    mem::size_of,
+    rc::{Rc, Weak},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
 use crate::{
+    error_code::ErrorCode,
     expr::{Literal, LoxCallable},
     functions::LoxFunction,
+    gc::{Node, Trace},
     interpreter::Interpreter,
     lox_result::LoxResult,
+    memory::{entry_size, Allocation, Heap},
+    suggest,
     token::Token,
 };
 
-#[derive(Debug, Clone)]
+/// Hidden binding in a class body's scope holding the class itself, used to decide who may
+/// access private (`#name`) members.
+pub const CLASS_BINDING: &str = "#class";
+
+/// A number no other class of either backend has, to key private fields by
+pub fn next_class_id() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Classes are only ever shared through an `Rc`, and two classes are the same only if they are
+/// the same object, even when they have the same name.
+#[derive(Debug)]
 pub struct LoxClass {
     pub name: String,
+    pub id: usize,
     pub methods: HashMap<String, LoxFunction>,
-    pub superclass: Option<Box<LoxClass>>,
+    pub abstract_methods: Vec<String>,
+    pub superclass: Option<Rc<LoxClass>>,
+    /// The `Rc` holding the class, so that its instances can point back to it
+    this: Weak<LoxClass>,
 }
 
 impl LoxClass {
     pub fn new(
         name: &str,
-        superclass: Option<Box<LoxClass>>,
+        superclass: Option<Rc<LoxClass>>,
         methods: HashMap<String, LoxFunction>,
-    ) -> Self {
-        Self {
+        abstract_methods: Vec<String>,
+    ) -> Rc<Self> {
+        Rc::new_cyclic(|this| Self {
             name: name.to_string(),
+            id: next_class_id(),
             methods,
+            abstract_methods,
             superclass,
+            this: Weak::clone(this),
+        })
+    }
+
+    /// Abstract methods declared anywhere in the superclass chain that no class below the
+    /// declaring one implements. A class can only be instantiated once this is empty.
+    pub fn unimplemented_methods(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        let mut class = Some(self);
+        while let Some(c) = class {
+            for name in &c.abstract_methods {
+                if seen.insert(name) {
+                    missing.push(name.clone());
+                }
+            }
+            seen.extend(c.methods.keys());
+            class = c.superclass.as_deref();
         }
+        missing
     }
 
     pub fn find_method(&self, name: &str) -> Option<Literal> {
@@ -40,6 +90,48 @@ impl LoxClass {
             }
         }
     }
+
+    /// Private methods are only looked up in the class that declared them, never inherited.
+    pub fn find_private_method(&self, owner: &LoxClass, name: &str) -> Option<Literal> {
+        if self == owner {
+            self.methods
+                .get(name)
+                .map(|m| Literal::Function(Rc::new(m.clone())))
+        } else if let Some(superclass) = &self.superclass {
+            superclass.find_private_method(owner, name)
+        } else {
+            None
+        }
+    }
+
+    /// Names of the public methods of the class and its superclasses
+    pub fn method_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut class = Some(self);
+        while let Some(c) = class {
+            let public = c.methods.keys().filter(|name| !name.starts_with('#'));
+            names.extend(public.map(String::as_str));
+            class = c.superclass.as_deref();
+        }
+        names
+    }
+
+    pub fn inherits_from(&self, class: &LoxClass) -> bool {
+        self == class
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.inherits_from(class))
+    }
+}
+
+impl Trace for LoxClass {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        self.methods.values().for_each(|m| m.trace(edges));
+        if let Some(superclass) = &self.superclass {
+            edges.push(Node::Class(Rc::clone(superclass)));
+        }
+    }
 }
 
 impl PartialEq for LoxClass {
@@ -50,9 +142,7 @@ impl PartialEq for LoxClass {
 
 impl Hash for LoxClass {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        // Classes are defined once and therefore alloc'ed once
-        // any def'n with same name is shadowing
-        (self as *const _ as usize).hash(state)
+        self.id.hash(state)
     }
 }
 
@@ -68,7 +158,26 @@ impl LoxCallable for LoxClass {
         interpreter: &mut Interpreter,
         arguments: Vec<Literal>,
     ) -> Result<Literal, LoxResult> {
-        let instance = Rc::new(RefCell::new(LoxInstance::new(self.clone())));
+        let missing = self.unimplemented_methods();
+        if !missing.is_empty() {
+            let missing: Vec<_> = missing.iter().map(|m| format!("'{m}'")).collect();
+            return Err(LoxResult::runtime_error(
+                &interpreter.call_site(),
+                ErrorCode::AbstractInstantiation,
+                &format!(
+                    "Can't instantiate abstract class {} without an implementation of {}.",
+                    self.name,
+                    missing.join(", ")
+                ),
+            ));
+        }
+
+        let class = self
+            .this
+            .upgrade()
+            .expect("Classes are called through their Rc");
+        let instance = Rc::new(RefCell::new(LoxInstance::new(class, interpreter.heap())));
+        interpreter.heap().collector().register_instance(&instance);
         if let Some(Literal::Function(initializer)) = self.find_method("init") {
             initializer
                 .bind_method(&instance)
@@ -89,12 +198,21 @@ impl LoxCallable for LoxClass {
     fn to_string(&self) -> String {
         self.name.to_string()
     }
+
+    /// Calling a class runs its initializer, so that's where errors are raised
+    fn name(&self) -> Rc<str> {
+        match self.find_method("init") {
+            Some(Literal::Function(initializer)) => initializer.name(),
+            _ => self.name.as_str().into(),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct LoxInstance {
-    class: LoxClass,
+    class: Rc<LoxClass>,
     fields: HashMap<String, Literal>,
+    allocation: Allocation,
 }
 
 impl Hash for LoxInstance {
@@ -114,10 +232,11 @@ impl PartialEq for LoxInstance {
 }
 
 impl LoxInstance {
-    pub fn new(class: LoxClass) -> Self {
+    pub fn new(class: Rc<LoxClass>, heap: &Rc<Heap>) -> Self {
         Self {
             class,
             fields: HashMap::new(),
+            allocation: Allocation::new(heap, size_of::<Self>()),
         }
     }
 
@@ -131,15 +250,96 @@ impl LoxInstance {
                 unreachable!("Non-methods are handled beforehand");
             }
         } else {
+            // Private fields are keyed by `<class id>.#name` and never suggested here
+            let fields = self.fields.keys().filter(|key| !key.contains('#'));
+            let names = fields.map(String::as_str).chain(self.class.method_names());
+            let message = format!("Undefined property '{}'.", name.lexeme);
             Err(LoxResult::runtime_error(
                 name,
-                &format!("Undefined property '{}'.", name.lexeme),
+                ErrorCode::UndefinedProperty,
+                &suggest::did_you_mean(message, &name.lexeme, names),
             ))
         }
     }
 
     pub fn set(&mut self, name: Token, value: Literal) {
-        self.fields.insert(name.lexeme, value);
+        self.insert(name.lexeme, value);
+    }
+
+    fn insert(&mut self, key: String, value: Literal) {
+        let new = entry_size(&key, &value);
+        let old = self.fields.insert(key.clone(), value);
+        let old = old.map_or(0, |old| entry_size(&key, &old));
+        self.allocation.replace(old, new);
+    }
+
+    /// Reads a private member on behalf of code declared in the body of class `owner`.
+    pub fn get_private(
+        &self,
+        name: &Token,
+        owner: &LoxClass,
+        this: &Rc<RefCell<LoxInstance>>,
+    ) -> Result<Literal, LoxResult> {
+        self.check_private_access(name, owner)?;
+        if let Some(v) = self.fields.get(&private_key(owner, &name.lexeme)) {
+            Ok(v.clone())
+        } else if let Some(Literal::Function(f)) =
+            self.class.find_private_method(owner, &name.lexeme)
+        {
+            Ok(Literal::Function(Rc::new(f.bind_method(this))))
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                ErrorCode::UndefinedProperty,
+                &format!("Undefined property '{}'.", name.lexeme),
+            ))
+        }
+    }
+
+    /// Private fields are keyed by their declaring class, so neither a subclass nor another class
+    /// with the same name can see or clobber them.
+    pub fn set_private(
+        &mut self,
+        name: &Token,
+        owner: &LoxClass,
+        value: Literal,
+    ) -> Result<(), LoxResult> {
+        self.check_private_access(name, owner)?;
+        self.insert(private_key(owner, &name.lexeme), value);
+        Ok(())
+    }
+
+    /// Drops all fields. Only used by the collector.
+    pub fn clear(&mut self) {
+        let held = self.fields.iter().map(|(n, v)| entry_size(n, v)).sum();
+        self.fields.clear();
+        self.allocation.replace(held, 0);
+    }
+
+    fn check_private_access(&self, name: &Token, owner: &LoxClass) -> Result<(), LoxResult> {
+        if self.class.inherits_from(owner) {
+            Ok(())
+        } else {
+            Err(LoxResult::runtime_error(
+                name,
+                ErrorCode::PrivateAccess,
+                &format!(
+                    "Can't access private property '{}' of a {} instance from class {}.",
+                    name.lexeme, self.class.name, owner.name
+                ),
+            ))
+        }
+    }
+}
+
+fn private_key(owner: &LoxClass, name: &str) -> String {
+    format!("{}.{name}", owner.id)
+}
+
+impl Trace for LoxInstance {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        edges.push(Node::Class(Rc::clone(&self.class)));
+        self.fields.values().for_each(|v| v.trace(edges));
     }
 }
 
@@ -0,0 +1,174 @@
+use std::{fmt::Display, hash::Hash, rc::Rc};
+
+use crate::{
+    error_code::ErrorCode,
+    expr::Literal,
+    gc::{Node, Trace},
+    lox_result::LoxResult,
+    suggest,
+    token::Token,
+};
+
+/// Namespace-like value created by an `enum` declaration. Its properties are the variants.
+#[derive(Debug)]
+pub struct LoxEnum {
+    pub name: String,
+    variants: Vec<Literal>,
+}
+
+impl LoxEnum {
+    /// Each variant is given as its name and the names of its payload fields, if any.
+    pub fn new(name: &str, variants: Vec<(String, Vec<String>)>) -> Self {
+        let variants = variants
+            .into_iter()
+            .enumerate()
+            .map(|(ordinal, (variant, fields))| {
+                Literal::EnumValue(Rc::new(LoxEnumValue {
+                    variant: Rc::new(LoxVariant {
+                        enum_name: name.to_string(),
+                        name: variant,
+                        ordinal,
+                        fields,
+                    }),
+                    payload: None,
+                }))
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            variants,
+        }
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Literal, LoxResult> {
+        if name.lexeme == "count" {
+            return Ok(Literal::Number(self.variants.len() as f64));
+        }
+        self.variants
+            .iter()
+            .find(|v| matches!(v, Literal::EnumValue(v) if v.variant.name == name.lexeme))
+            .cloned()
+            .ok_or_else(|| {
+                let names = self.variants.iter().filter_map(|v| match v {
+                    Literal::EnumValue(v) => Some(v.variant.name.as_str()),
+                    _ => None,
+                });
+                let message = format!("Undefined variant '{}'.", name.lexeme);
+                LoxResult::runtime_error(
+                    name,
+                    ErrorCode::UndefinedVariant,
+                    &suggest::did_you_mean(message, &name.lexeme, names.chain(["count"])),
+                )
+            })
+    }
+
+    /// Looks a variant up by its ordinal, which is how scripts iterate over an enum.
+    pub fn variant_at(&self, ordinal: &Literal) -> Option<Literal> {
+        match ordinal {
+            Literal::Number(n) if n.fract() == 0.0 && *n >= 0.0 => {
+                self.variants.get(*n as usize).cloned()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for LoxEnum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxVariant {
+    enum_name: String,
+    name: String,
+    ordinal: usize,
+    fields: Vec<String>,
+}
+
+/// A variant of an enum. Variants declared with fields (`Circle(radius)`) act as constructors
+/// until they are called with a payload.
+#[derive(Debug)]
+pub struct LoxEnumValue {
+    variant: Rc<LoxVariant>,
+    payload: Option<Vec<Literal>>,
+}
+
+impl LoxEnumValue {
+    pub fn is_constructor(&self) -> bool {
+        !self.variant.fields.is_empty() && self.payload.is_none()
+    }
+
+    pub fn get_arity(&self) -> usize {
+        self.variant.fields.len()
+    }
+
+    /// Field values of a constructed variant, empty for plain variants and constructors
+    pub fn payload(&self) -> &[Literal] {
+        self.payload.as_deref().unwrap_or_default()
+    }
+
+    pub fn construct(&self, payload: Vec<Literal>) -> Literal {
+        Literal::EnumValue(Rc::new(LoxEnumValue {
+            variant: Rc::clone(&self.variant),
+            payload: Some(payload),
+        }))
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Literal, LoxResult> {
+        match name.lexeme.as_str() {
+            "name" => Ok(Literal::String(self.variant.name.clone())),
+            "ordinal" => Ok(Literal::Number(self.variant.ordinal as f64)),
+            field => {
+                let value = self.payload.as_ref().and_then(|payload| {
+                    let i = self.variant.fields.iter().position(|f| f == field)?;
+                    payload.get(i).cloned()
+                });
+                value.ok_or_else(|| {
+                    let fields = self.variant.fields.iter().map(String::as_str);
+                    let names = fields.chain(["name", "ordinal"]);
+                    let message = format!("Undefined property '{field}'.");
+                    LoxResult::runtime_error(
+                        name,
+                        ErrorCode::UndefinedProperty,
+                        &suggest::did_you_mean(message, field, names),
+                    )
+                })
+            }
+        }
+    }
+}
+
+impl Trace for LoxEnumValue {
+    fn trace(&self, edges: &mut Vec<Node>) {
+        self.payload().iter().for_each(|v| v.trace(edges));
+    }
+}
+
+impl PartialEq for LoxEnumValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.variant.enum_name == other.variant.enum_name
+            && self.variant.ordinal == other.variant.ordinal
+            && self.payload == other.payload
+    }
+}
+
+impl Hash for LoxEnumValue {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.variant.enum_name.hash(state);
+        self.variant.ordinal.hash(state);
+        self.payload.hash(state);
+    }
+}
+
+impl Display for LoxEnumValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.variant.enum_name, self.variant.name)?;
+        if let Some(payload) = &self.payload {
+            let payload: Vec<_> = payload.iter().map(|v| v.to_string()).collect();
+            write!(f, "({})", payload.join(", "))?;
+        }
+        Ok(())
+    }
+}
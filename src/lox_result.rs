@@ -1,27 +1,92 @@
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
 use crate::{
+    diagnostic::{Diagnostic, Label, Severity},
+    error_code::ErrorCode,
     expr::Literal,
-    token::{Token, TokenType},
+    functions::LoxFunction,
+    token::{Span, Token, TokenType},
 };
 
 #[derive(Debug)]
 
 pub enum LoxResult {
-    ParseError { causes: Vec<ParseErrorCause> },
-    RuntimeError { token: Token, message: String },
+    ParseError {
+        causes: Vec<ParseErrorCause>,
+    },
+    RuntimeError {
+        token: Box<Token>,
+        code: ErrorCode,
+        message: String,
+        /// Calls being run when the error was raised, innermost first. Filled in as the error
+        /// leaves the innermost call.
+        trace: Vec<TraceFrame>,
+    },
+    /// Execution was stopped by the step budget or deadline set on the interpreter
+    Interrupted {
+        code: ErrorCode,
+        message: String,
+    },
     Return(Literal),
-    // Break // TODO:
+    /// `return f(...)` in tail position, run by the caller's trampoline in place of its own frame
+    TailCall(Rc<LoxFunction>, Vec<Literal>),
+    /// Unwinds to the innermost loop, or the loop with the given label
+    Break(Option<String>),
+    Continue(Option<String>),
 }
 
 impl LoxResult {
-    pub fn runtime_error(token: &Token, message: &str) -> LoxResult {
-        let err = LoxResult::RuntimeError {
-            token: token.clone(),
+    /// Creating an error doesn't show it to the user: that's up to whichever `Reporter` the
+    /// error ends up with, if it isn't handled first.
+    pub fn runtime_error(token: &Token, code: ErrorCode, message: &str) -> LoxResult {
+        LoxResult::RuntimeError {
+            token: Box::new(token.clone()),
+            code,
             message: message.to_string(),
-        };
-        eprintln!("{err}");
-        err
+            trace: Vec::new(),
+        }
+    }
+
+    /// The errors to show the user, none for the variants that only unwind the interpreter
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            LoxResult::ParseError { causes } => causes
+                .iter()
+                .map(|c| Diagnostic {
+                    severity: Severity::Error,
+                    code: c.code,
+                    message: c.message.clone(),
+                    span: Some(c.span.unwrap_or(Span::line(c.line))),
+                    labels: c.labels.clone(),
+                    trace: Vec::new(),
+                })
+                .collect(),
+            LoxResult::RuntimeError {
+                token,
+                code,
+                message,
+                trace,
+            } => vec![Diagnostic {
+                severity: Severity::Error,
+                code: *code,
+                message: message.clone(),
+                span: Some(token.span),
+                labels: Vec::new(),
+                trace: trace.clone(),
+            }],
+            LoxResult::Interrupted { code, .. } => vec![Diagnostic {
+                severity: Severity::Error,
+                code: *code,
+                message: self.to_string(),
+                span: None,
+                labels: Vec::new(),
+                trace: Vec::new(),
+            }],
+            LoxResult::Return(_)
+            | LoxResult::TailCall(..)
+            | LoxResult::Break(_)
+            | LoxResult::Continue(_) => Vec::new(),
+        }
     }
 }
 
@@ -43,14 +108,45 @@ impl Display for LoxResult {
                 }
                 Ok(())
             }
-            LoxResult::RuntimeError { token, message } => {
+            LoxResult::RuntimeError { token, message, .. } => {
                 if token.token_type == TokenType::Eof {
                     write!(f, "[{}] Error at end: {}", token.line, message)
                 } else {
                     write!(f, "{}\n[line {}]", message, token.line)
                 }
             }
-            LoxResult::Return { .. } => write!(f, ""),
+            LoxResult::Interrupted { message, .. } => write!(f, "Interrupted: {message}"),
+            LoxResult::Return { .. }
+            | LoxResult::TailCall(..)
+            | LoxResult::Break(_)
+            | LoxResult::Continue(_) => {
+                write!(f, "")
+            }
+        }
+    }
+}
+
+/// A call in the stack trace of a runtime error
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// Such as `Foo.bar`, `clock` or `<script>`
+    pub function: Rc<str>,
+    /// Line the call was at when the error was raised, `None` in a native function
+    pub line: Option<usize>,
+    /// Tail calls that ran in this frame before `function` took it over
+    pub tail_calls: usize,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "at {} (line {line})", self.function)?,
+            None => write!(f, "at {} (native)", self.function)?,
+        }
+        match self.tail_calls {
+            0 => Ok(()),
+            1 => write!(f, " [1 tail call elided]"),
+            n => write!(f, " [{n} tail calls elided]"),
         }
     }
 }
@@ -58,16 +154,46 @@ impl Display for LoxResult {
 #[derive(Debug)]
 pub struct ParseErrorCause {
     pub line: usize,
-    pub token: Option<String>,
+    /// Lexeme of the token the error is at. Boxed to keep results small: this is the `Err` of
+    /// every parser method.
+    pub token: Option<Box<str>>,
+    pub code: ErrorCode,
     pub message: String,
+    /// Where in the source the error is, when that's more precise than the line
+    pub span: Option<Span>,
+    /// Other places involved, such as an earlier declaration
+    pub labels: Vec<Label>,
 }
 
 impl ParseErrorCause {
-    pub fn new(line: usize, lexeme: Option<String>, message: &str) -> ParseErrorCause {
+    pub fn new(
+        line: usize,
+        lexeme: Option<String>,
+        code: ErrorCode,
+        message: &str,
+    ) -> ParseErrorCause {
         ParseErrorCause {
             line,
-            token: lexeme,
+            token: lexeme.map(String::into_boxed_str),
+            code,
             message: message.to_string(),
+            span: None,
+            labels: Vec::new(),
+        }
+    }
+
+    /// An error at `token`
+    pub fn at(token: &Token, code: ErrorCode, message: &str) -> ParseErrorCause {
+        ParseErrorCause {
+            span: Some(token.span).filter(Span::is_known),
+            ..ParseErrorCause::new(token.line, Some(token.lexeme.clone()), code, message)
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: &str) -> ParseErrorCause {
+        if span.is_known() {
+            self.labels.push(Label::new(span, message));
         }
+        self
     }
 }
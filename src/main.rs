@@ -1,52 +1,288 @@
 use std::{
     env, fs,
     io::{self, BufRead, Write},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant},
 };
 
 mod lox_result;
 mod token;
+use compiler::Compiler;
+use error_code::ErrorCode;
 use parser::Parser;
 use resolver::Resolver;
+use stmt::Stmt;
 use token::{Token, TokenType};
 mod scanner;
 use scanner::Scanner;
 mod interpreter;
+use diagnostic::Diagnostic;
 use interpreter::Interpreter;
+use lint::{Lint, Lints};
+use reporter::{JsonReporter, Reporter, StderrReporter};
+use value::Function;
+use vm::Vm;
+mod cache;
+mod chunk;
+mod compiler;
+mod diagnostic;
+mod disassembler;
 mod environment;
+mod error_code;
 mod expr;
+mod flow;
 mod functions;
+mod gc;
+mod lint;
 mod lox_class;
+mod lox_enum;
+mod memory;
+mod optimizer;
 mod parser;
+mod reporter;
 mod resolver;
 mod stmt;
+mod suggest;
+mod value;
+mod vm;
+
+/// The tree-walker recurses on the Rust stack for every nested call, statement and expression.
+/// The main thread's stack is too small for `DEFAULT_MAX_CALL_DEPTH` calls, so run on our own.
+const INTERPRETER_STACK_SIZE: usize = 256 * 1024 * 1024;
+
+const USAGE: &str = "Usage: jlox [--vm] [--disassemble] [--optimize] [--error-format=human|json] \
+                     [--allow=LINT] [--warn=LINT] [--max-call-depth=N] [--step-budget=N] \
+                     [--timeout=MS] [--memory-limit=BYTES] [--stats] [script]\n       \
+                     jlox --explain CODE";
+
+/// Command-line flags, which may come before or after the script
+#[derive(Debug, Default)]
+struct Options {
+    /// Run on the bytecode VM instead of the tree-walking interpreter
+    vm: bool,
+    /// Print the bytecode a script compiles to instead of running it
+    disassemble: bool,
+    /// Fold constant expressions and drop dead branches once the script has been checked
+    optimize: bool,
+    error_format: ErrorFormat,
+    /// Print the explanation of an error code instead of running anything
+    explain: Option<ErrorCode>,
+    /// Warnings turned off with `--allow` or back on with `--warn`
+    lints: Lints,
+    /// Calls that can be nested before "Stack overflow.", instead of `DEFAULT_MAX_CALL_DEPTH`
+    max_call_depth: Option<usize>,
+    /// Statements and calls each script or REPL line may execute
+    step_budget: Option<u64>,
+    /// Time each script or REPL line may run for
+    timeout: Option<Duration>,
+    /// Bytes the values held by the script may take
+    memory_limit: Option<usize>,
+    /// Print what running took to stderr afterwards
+    stats: bool,
+    script: Option<String>,
+}
+
+/// How errors are shown, set with `--error-format`
+#[derive(Debug, Default, PartialEq)]
+enum ErrorFormat {
+    /// Rendered with source snippets, for people
+    #[default]
+    Human,
+    /// One JSON object per line, for editors and CI
+    Json,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--vm" => options.vm = true,
+                "--disassemble" => options.disassemble = true,
+                "--optimize" => options.optimize = true,
+                "--stats" => options.stats = true,
+                "--error-format=human" => options.error_format = ErrorFormat::Human,
+                "--error-format=json" => options.error_format = ErrorFormat::Json,
+                "--explain" => {
+                    let code = args
+                        .next()
+                        .ok_or("Expect an error code after '--explain'.")?;
+                    let explain = ErrorCode::from_code(code);
+                    options.explain = Some(explain.ok_or(format!("Unknown error code '{code}'."))?);
+                }
+                flag if flag.starts_with("--allow=") || flag.starts_with("--warn=") => {
+                    let (option, name) = flag.split_once('=').unwrap();
+                    let lint = Lint::from_name(name).ok_or(format!("Unknown lint '{name}'."))?;
+                    options.lints.set(lint, option == "--warn");
+                }
+                flag if flag.starts_with("--max-call-depth=") => {
+                    options.max_call_depth = Some(flag_value(flag)?);
+                }
+                flag if flag.starts_with("--step-budget=") => {
+                    options.step_budget = Some(flag_value(flag)?);
+                }
+                flag if flag.starts_with("--timeout=") => {
+                    options.timeout = Some(Duration::from_millis(flag_value(flag)?));
+                }
+                flag if flag.starts_with("--memory-limit=") => {
+                    options.memory_limit = Some(flag_value(flag)?);
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option '{flag}'.")),
+                script if options.script.is_none() => options.script = Some(script.to_string()),
+                _ => return Err("Only one script can be run at a time.".to_string()),
+            }
+        }
+        // The VM doesn't count steps or memory
+        let interpreter_only = options.step_budget.is_some()
+            || options.timeout.is_some()
+            || options.memory_limit.is_some();
+        if options.vm && (interpreter_only || options.stats) {
+            let flags = "--step-budget, --timeout, --memory-limit and --stats";
+            return Err(format!("{flags} are only supported without --vm."));
+        }
+        Ok(options)
+    }
+}
+
+/// The value of a `--name=value` flag
+fn flag_value<T: std::str::FromStr>(flag: &str) -> Result<T, String> {
+    let (_, value) = flag.split_once('=').unwrap_or_default();
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{value}' in '{flag}'."))
+}
+
+/// State kept from one REPL line to the next. The interpreter is also what the resolver
+/// records its results in, so there is one even when running on the VM.
+struct Session {
+    options: Options,
+    interpreter: Interpreter,
+    vm: Vm,
+    reporter: Box<dyn Reporter>,
+}
+
+impl Session {
+    fn new(options: Options) -> Self {
+        let file = options.script.as_deref().unwrap_or("<stdin>");
+        let reporter: Box<dyn Reporter> = match options.error_format {
+            ErrorFormat::Human => Box::new(StderrReporter::new(file, diagnostic::use_colour())),
+            ErrorFormat::Json => Box::new(JsonReporter::new(file)),
+        };
+        let mut interpreter = Interpreter::new();
+        let mut vm = Vm::new();
+        if let Some(depth) = options.max_call_depth {
+            interpreter.set_max_call_depth(depth);
+            vm.set_max_call_depth(depth);
+        }
+        interpreter.set_memory_limit(options.memory_limit);
+        Self {
+            options,
+            interpreter,
+            vm,
+            reporter,
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-
-    match args.len() {
-        1 => run_prompt(),
-        2 => run_file(&args[1]).expect("Unable to run file."),
-        _ => {
-            println!("Usage: jlox [script]");
+    let options = match Options::parse(&args[1..]) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            println!("{USAGE}");
             // EX_USAGE (64) Command was used incorrectly, e.g., with the wrong number of arguments, a bad flag, bad syntax in a parameter, or whatever.
             std::process::exit(64)
         }
+    };
+    if let Some(code) = options.explain {
+        println!("{}", code.explanation());
+        return;
+    }
+
+    let result = with_interpreter_stack(move || match options.script.clone() {
+        None => run_prompt(Session::new(options)),
+        Some(path) => run_file(&path, Session::new(options)).expect("Unable to run file."),
+    });
+    if result.is_err() {
+        // EX_SOFTWARE (70) Internal software error. Limited to non-OS errors.
+        std::process::exit(70)
     }
 }
 
-fn run_file(file_path: &str) -> std::io::Result<()> {
+/// Runs `f` to completion on a thread with a stack of `INTERPRETER_STACK_SIZE`
+fn with_interpreter_stack<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> std::thread::Result<T> {
+    std::thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(f)
+        .expect("Unable to spawn interpreter thread.")
+        .join()
+}
+
+fn run_file(file_path: &str, mut session: Session) -> std::io::Result<()> {
     let contents = fs::read_to_string(file_path)?;
-    let mut interpreter = Interpreter::new();
     // EX_DATAERR (65) User input data was incorrect in some way.
     // EX_SOFTWARE (70) Internal software error. Limited to non-OS errors.
-    run(&contents, &mut interpreter);
+    if session.options.vm && !session.options.disassemble {
+        run_cached(
+            &contents,
+            &Path::new(file_path).with_extension("loxc"),
+            &mut session,
+        );
+    } else {
+        run(&contents, &mut session);
+    }
     std::process::exit(0)
 }
 
+/// Runs a script on the VM from the bytecode cached at `cache_path`, compiling it and updating
+/// the cache when there's none for this source.
+fn run_cached(source: &str, cache_path: &Path, session: &mut Session) {
+    let options = cache_options(&session.options);
+    let cached = fs::read(cache_path)
+        .ok()
+        .and_then(|bytes| cache::decode(&bytes, source, &options).ok());
+    let function = match cached {
+        Some((function, warnings)) => {
+            for warning in warnings {
+                session.reporter.report(warning, source);
+            }
+            function
+        }
+        None => {
+            let (statements, warnings) = analyse(source, session);
+            let function = compile(&statements, source, session);
+            let bytes = cache::encode(&function, &warnings, source, &options);
+            // Not being able to cache (e.g. in a read-only directory) only costs startup time
+            let _ = fs::write(cache_path, bytes);
+            function
+        }
+    };
+    execute(function, source, session);
+}
+
+/// The flags that change what a script compiles to or the warnings about it, which a cached
+/// script must match
+fn cache_options(options: &Options) -> String {
+    let optimize = options.optimize.then_some("--optimize".to_string());
+    let allowed = Lint::ALL
+        .into_iter()
+        .filter(|lint| !options.lints.is_enabled(*lint))
+        .map(|lint| format!("--allow={}", lint.name()));
+    optimize
+        .into_iter()
+        .chain(allowed)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Goes into prompt-mode. Starts a REPL:
 /// Read a line of input, Evaluate it, Print the result, then Loop
-fn run_prompt() {
-    let mut interpreter = Interpreter::new();
+fn run_prompt(mut session: Session) {
     print!("> ");
     io::stdout().flush().expect("Unable to flush stdout");
     for line in io::stdin().lock().lines() {
@@ -55,7 +291,7 @@ fn run_prompt() {
                 if line.is_empty() {
                     break;
                 }
-                run(&line, &mut interpreter);
+                run(&line, &mut session);
                 print!("> ");
                 io::stdout().flush().expect("Unable to flush stdout");
             }
@@ -65,13 +301,55 @@ fn run_prompt() {
 }
 
 /// On error, returns an instance of LoxResult::Error and an ExitCode
-fn run(source: &str, interpreter: &mut Interpreter) {
+fn run(source: &str, session: &mut Session) {
+    let (statements, _) = analyse(source, session);
+    if !session.options.vm && !session.options.disassemble {
+        let interpreter = &mut session.interpreter;
+        interpreter.set_step_budget(session.options.step_budget);
+        interpreter.set_deadline(session.options.timeout.map(|t| Instant::now() + t));
+        let result = interpreter.interpret(&statements);
+        if session.options.stats {
+            print_stats(interpreter);
+        }
+        if let Err(e) = result {
+            session.reporter.report_error(&e, source);
+            std::process::exit(70)
+        }
+        return;
+    }
+
+    let function = compile(&statements, source, session);
+    if session.options.disassemble {
+        print!("{}", disassembler::disassemble(&function));
+    } else {
+        execute(function, source, session);
+    }
+}
+
+/// For `--stats`, after the script has run or been stopped
+fn print_stats(interpreter: &Interpreter) {
+    eprintln!("steps: {}", interpreter.steps_used());
+    eprintln!(
+        "memory: {} bytes, peak {} bytes",
+        interpreter.memory_usage(),
+        interpreter.peak_memory_usage()
+    );
+    let gc = interpreter.gc_stats();
+    eprintln!(
+        "gc: {} collections, {} freed, {} tracked",
+        gc.collections, gc.freed, gc.tracked
+    );
+}
+
+/// Scans, parses and resolves `source`, exiting on any error. Returns the warnings along with
+/// the statements, once they have been reported.
+fn analyse(source: &str, session: &mut Session) -> (Vec<Stmt>, Vec<Diagnostic>) {
     let mut scanner = Scanner::new(source);
     let tokens = {
         match scanner.scan_tokens() {
             Ok(t) => t,
             Err(e) => {
-                eprintln!("{e}");
+                session.reporter.report_error(&e, source);
                 std::process::exit(65);
             }
         }
@@ -81,19 +359,53 @@ fn run(source: &str, interpreter: &mut Interpreter) {
     let statements = match parser.parse() {
         Ok(s) => s,
         Err(e) => {
-            eprintln!("{e}");
+            session.reporter.report_error(&e, source);
             std::process::exit(65);
         }
     };
+    let mut warnings = flow::analyse(&statements, &session.options.lints);
+    for warning in &warnings {
+        session.reporter.report(warning.clone(), source);
+    }
 
-    let mut resolver = Resolver::new(interpreter);
-    if let Err(e) = resolver.resolve_stmts(&statements) {
-        eprintln!("{e}");
+    let mut resolver = Resolver::new(&mut session.interpreter);
+    resolver.set_lints(session.options.lints.clone());
+    let result = resolver.resolve_stmts(&statements);
+    for warning in resolver.take_warnings() {
+        session.reporter.report(warning.clone(), source);
+        warnings.push(warning);
+    }
+    if let Err(e) = result {
+        session.reporter.report_error(&e, source);
         std::process::exit(65);
     }
+    if !session.options.optimize {
+        return (statements, warnings);
+    }
+
+    // Resolved again for the interpreter, as folding and removing code changes what there is
+    // to resolve. Nothing new can be wrong with it, and its warnings were reported above.
+    let statements = optimizer::optimize(statements);
+    if let Err(e) = Resolver::new(&mut session.interpreter).resolve_stmts(&statements) {
+        session.reporter.report_error(&e, source);
+        std::process::exit(65);
+    }
+    (statements, warnings)
+}
+
+fn compile(statements: &[Stmt], source: &str, session: &mut Session) -> Rc<Function> {
+    match Compiler::new().compile(statements) {
+        Ok(f) => f,
+        Err(e) => {
+            session.reporter.report_error(&e, source);
+            std::process::exit(65);
+        }
+    }
+}
 
-    if let Err(e) = interpreter.interpret(&statements) {
-        eprintln!("{e}");
+fn execute(function: Rc<Function>, source: &str, session: &mut Session) {
+    if let Err(e) = session.vm.interpret(function) {
+        session.reporter.report_error(&e, source);
         std::process::exit(70)
     }
 }
@@ -0,0 +1,104 @@
+use std::{cell::Cell, mem::size_of, rc::Rc};
+
+use crate::{expr::Literal, gc::Collector};
+
+/// Approximate count of the bytes held by environments, instances and the values stored in
+/// them, with an optional ceiling for embedders running untrusted scripts.
+#[derive(Debug, Default)]
+pub struct Heap {
+    current: Cell<usize>,
+    peak: Cell<usize>,
+    limit: Cell<Option<usize>>,
+    collector: Collector,
+}
+
+impl Heap {
+    pub fn new() -> Rc<Self> {
+        Rc::new(Self::default())
+    }
+
+    pub fn collector(&self) -> &Collector {
+        &self.collector
+    }
+
+    pub fn current(&self) -> usize {
+        self.current.get()
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.limit.set(limit);
+    }
+
+    /// Whether holding `bytes` more than is currently held would go over the limit
+    pub fn would_exceed(&self, bytes: usize) -> bool {
+        self.limit
+            .get()
+            .is_some_and(|limit| self.current.get().saturating_add(bytes) > limit)
+    }
+
+    fn grow(&self, bytes: usize) {
+        let current = self.current.get() + bytes;
+        self.current.set(current);
+        self.peak.set(self.peak.get().max(current));
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.current.set(self.current.get() - bytes);
+    }
+}
+
+/// Bytes charged to a heap by one environment or instance, given back when it is dropped.
+#[derive(Debug)]
+pub struct Allocation {
+    heap: Rc<Heap>,
+    bytes: usize,
+}
+
+impl Allocation {
+    pub fn new(heap: &Rc<Heap>, bytes: usize) -> Self {
+        heap.grow(bytes);
+        Self {
+            heap: Rc::clone(heap),
+            bytes,
+        }
+    }
+
+    pub fn heap(&self) -> &Rc<Heap> {
+        &self.heap
+    }
+
+    /// Updates the charge after a held value of `old` bytes was replaced by one of `new` bytes
+    pub fn replace(&mut self, old: usize, new: usize) {
+        self.heap.shrink(old);
+        self.heap.grow(new);
+        self.bytes = self.bytes - old + new;
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        self.heap.shrink(self.bytes);
+    }
+}
+
+/// Approximate size of a variable or field named `name` holding `value`. Strings and enum
+/// payloads are stored by value, so their contents count towards the entry.
+pub fn entry_size(name: &str, value: &Literal) -> usize {
+    size_of::<(String, Literal)>() + name.len() + contents_size(value)
+}
+
+fn contents_size(value: &Literal) -> usize {
+    match value {
+        Literal::String(s) => s.len(),
+        Literal::EnumValue(v) => v
+            .payload()
+            .iter()
+            .map(|field| size_of::<Literal>() + contents_size(field))
+            .sum(),
+        _ => 0,
+    }
+}
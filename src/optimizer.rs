@@ -0,0 +1,337 @@
+use std::rc::Rc;
+
+use crate::{
+    expr::{ConditionalExpr, Expr, ExprId, Literal, LiteralExpr, LogicalExpr},
+    stmt::{BlockStmt, IfStmt, Stmt},
+    token::{Span, Token, TokenType},
+};
+
+/// Folds operators over constant operands and removes branches and loops that can never run.
+/// Runs once the resolver has checked the statements as parsed, so errors in removed code are
+/// still reported, and the result is resolved again.
+///
+/// Anything that would fail at runtime (`1 / 0`, `-"a"`) is left for the interpreter to report,
+/// as is string concatenation, which counts towards the memory limit.
+pub fn optimize(statements: Vec<Stmt>) -> Vec<Stmt> {
+    statements.into_iter().filter_map(statement).collect()
+}
+
+/// Returns `None` for a statement that can never run
+fn statement(s: Stmt) -> Option<Stmt> {
+    let s = match s {
+        Stmt::Block(mut b) => {
+            b.statements = optimize(b.statements);
+            Stmt::Block(b)
+        }
+        Stmt::Class(mut c) => {
+            c.methods = optimize(c.methods);
+            Stmt::Class(c)
+        }
+        Stmt::Defer(d) => {
+            let mut d = Rc::unwrap_or_clone(d);
+            d.expression = expression(d.expression);
+            Stmt::Defer(Rc::new(d))
+        }
+        Stmt::DoWhile(mut d) => {
+            d.body = or_empty(statement(d.body));
+            d.condition = expression(d.condition);
+            Stmt::DoWhile(d)
+        }
+        Stmt::Expression(mut e) => {
+            e.expression = expression(e.expression);
+            Stmt::Expression(e)
+        }
+        Stmt::Function(f) => {
+            let mut f = Rc::unwrap_or_clone(f);
+            f.body = optimize(f.body);
+            Stmt::Function(Rc::new(f))
+        }
+        Stmt::If(i) => {
+            let IfStmt {
+                condition,
+                then_branch,
+                else_branch,
+            } = *i;
+            let condition = expression(condition);
+            match constant(&condition) {
+                Some(c) if is_truthy(c) => return statement(then_branch),
+                Some(_) => return else_branch.and_then(statement),
+                None => Stmt::If(Box::new(IfStmt::new(
+                    condition,
+                    or_empty(statement(then_branch)),
+                    else_branch.and_then(statement),
+                ))),
+            }
+        }
+        Stmt::Print(mut p) => {
+            p.expression = expression(p.expression);
+            Stmt::Print(p)
+        }
+        Stmt::Return(mut r) => {
+            r.value = r.value.map(expression);
+            Stmt::Return(r)
+        }
+        Stmt::Var(mut v) => {
+            v.initializer = v.initializer.map(expression);
+            Stmt::Var(v)
+        }
+        Stmt::While(mut w) => {
+            w.condition = expression(w.condition);
+            if constant(&w.condition).is_some_and(|c| !is_truthy(c)) {
+                return None;
+            }
+            w.body = or_empty(statement(w.body));
+            w.increment = w.increment.map(expression);
+            Stmt::While(w)
+        }
+        Stmt::Break(_) | Stmt::Continue(_) | Stmt::Enum(_) => s,
+    };
+    Some(s)
+}
+
+/// Stands in for a removed statement where the grammar needs one, like a loop body
+fn or_empty(s: Option<Stmt>) -> Stmt {
+    s.unwrap_or_else(|| Stmt::Block(Box::new(BlockStmt::new(Vec::new()))))
+}
+
+fn expression(expr: Expr) -> Expr {
+    match expr {
+        Expr::Assign(mut e) => {
+            e.value = expression(e.value);
+            Expr::Assign(e)
+        }
+        Expr::Binary(mut e) => {
+            e.left = expression(e.left);
+            e.right = expression(e.right);
+            let folded = match (constant(&e.left), constant(&e.right)) {
+                (Some(left), Some(right)) => binary(&e.operator, left, right),
+                _ => None,
+            };
+            match folded {
+                Some(value) => literal(e.id, value, e.left.span().to(e.right.span())),
+                None => Expr::Binary(e),
+            }
+        }
+        Expr::Call(mut e) => {
+            e.callee = expression(e.callee);
+            e.arguments = e.arguments.into_iter().map(expression).collect();
+            Expr::Call(e)
+        }
+        Expr::Conditional(e) => {
+            let ConditionalExpr {
+                id,
+                condition,
+                left,
+                right,
+            } = *e;
+            let condition = expression(condition);
+            match constant(&condition) {
+                Some(c) if is_truthy(c) => expression(left),
+                Some(_) => expression(right),
+                None => Expr::Conditional(Box::new(ConditionalExpr::new(
+                    id,
+                    condition,
+                    expression(left),
+                    expression(right),
+                ))),
+            }
+        }
+        Expr::Get(mut e) => {
+            e.object = expression(e.object);
+            Expr::Get(e)
+        }
+        Expr::Grouping(mut e) => {
+            e.expression = expression(e.expression);
+            match e.expression {
+                Expr::Literal(_) => e.expression,
+                _ => Expr::Grouping(e),
+            }
+        }
+        Expr::Logical(e) => {
+            let LogicalExpr {
+                id,
+                left,
+                operator,
+                right,
+            } = *e;
+            let left = expression(left);
+            let Some(value) = constant(&left) else {
+                let right = expression(right);
+                return Expr::Logical(Box::new(LogicalExpr::new(id, left, operator, right)));
+            };
+            // The left operand is the result when it decides the outcome on its own
+            if is_truthy(value) == (operator.token_type == TokenType::Or) {
+                left
+            } else {
+                expression(right)
+            }
+        }
+        Expr::Set(mut e) => {
+            e.object = expression(e.object);
+            e.value = expression(e.value);
+            Expr::Set(e)
+        }
+        Expr::Unary(mut e) => {
+            e.right = expression(e.right);
+            let folded = match (&e.operator.token_type, constant(&e.right)) {
+                (TokenType::Minus, Some(Literal::Number(n))) => Some(Literal::Number(-n)),
+                (TokenType::Bang, Some(value)) => Some(Literal::Boolean(!is_truthy(value))),
+                _ => None,
+            };
+            match folded {
+                Some(value) => literal(e.id, value, e.operator.span.to(e.right.span())),
+                None => Expr::Unary(e),
+            }
+        }
+        Expr::Literal(_) | Expr::Super(_) | Expr::This(_) | Expr::Variable(_) => expr,
+    }
+}
+
+/// Same results as `Interpreter::evaluate`, or `None` where it would report an error
+fn binary(operator: &Token, left: &Literal, right: &Literal) -> Option<Literal> {
+    match operator.token_type {
+        TokenType::EqualEqual => return Some(Literal::Boolean(left == right)),
+        TokenType::BangEqual => return Some(Literal::Boolean(left != right)),
+        _ => {}
+    }
+    let (Literal::Number(l), Literal::Number(r)) = (left, right) else {
+        return None;
+    };
+    let value = match operator.token_type {
+        TokenType::Plus => Literal::Number(l + r),
+        TokenType::Minus => Literal::Number(l - r),
+        TokenType::Star => Literal::Number(l * r),
+        TokenType::Slash if *r != 0.0 => Literal::Number(l / r),
+        TokenType::Greater => Literal::Boolean(l > r),
+        TokenType::GreaterEqual => Literal::Boolean(l >= r),
+        TokenType::Less => Literal::Boolean(l < r),
+        TokenType::LessEqual => Literal::Boolean(l <= r),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn constant(expr: &Expr) -> Option<&Literal> {
+    match expr {
+        Expr::Literal(e) => Some(&e.value),
+        _ => None,
+    }
+}
+
+/// The folded node keeps the id and span of the expression it replaces
+fn literal(id: ExprId, value: Literal, span: Span) -> Expr {
+    Expr::Literal(Box::new(LiteralExpr::new(id, value, span)))
+}
+
+fn is_truthy(value: &Literal) -> bool {
+    !matches!(value, Literal::Nil | Literal::Boolean(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::{
+        expr::{Expr, Literal},
+        interpreter::Interpreter,
+        parser::Parser,
+        resolver::Resolver,
+        scanner::Scanner,
+        stmt::Stmt,
+        token::{Token, TokenType},
+    };
+
+    fn optimized(source: &str) -> Vec<Stmt> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        optimize(Parser::new(&tokens).parse().unwrap())
+    }
+
+    fn printed(statements: &[Stmt]) -> Vec<&Expr> {
+        statements
+            .iter()
+            .map(|s| match s {
+                Stmt::Print(p) => &p.expression,
+                s => panic!("Expected a print statement, got {s:?}"),
+            })
+            .collect()
+    }
+
+    fn is_literal(expr: &Expr, value: Literal) -> bool {
+        matches!(expr, Expr::Literal(e) if e.value == value)
+    }
+
+    #[test]
+    fn test_folds_constants() {
+        let statements = optimized(
+            r#"
+            print 1 + 2 * 3;
+            print -(4 - 6) >= 2;
+            print !nil == (true and "yes");
+            print false or 5 > 6 ? "a" : "b";
+            print "x" == "x";
+            "#,
+        );
+        let values = [
+            Literal::Number(7.0),
+            Literal::Boolean(true),
+            Literal::Boolean(false),
+            Literal::String("b".to_string()),
+            Literal::Boolean(true),
+        ];
+        for (expr, value) in printed(&statements).into_iter().zip(values) {
+            assert!(is_literal(expr, value.clone()), "{expr:?} isn't {value:?}");
+        }
+    }
+
+    #[test]
+    fn test_keeps_runtime_errors() {
+        let statements = optimized(r#"print 1 / 0; print -"a"; print 1 + "b"; print "c" + "d";"#);
+        for expr in printed(&statements) {
+            assert!(!matches!(expr, Expr::Literal(_)), "{expr:?} was folded");
+        }
+    }
+
+    #[test]
+    fn test_removes_dead_branches() {
+        let statements = optimized(
+            r#"
+            if (true) print 1; else print 2;
+            if (nil) print 3;
+            while (false) print 4;
+            for (var i = 0; 1 > 2; i = i + 1) print 5;
+            "#,
+        );
+        assert_eq!(statements.len(), 2);
+        assert!(is_literal(
+            printed(&statements[..1])[0],
+            Literal::Number(1.0)
+        ));
+        let Stmt::Block(block) = &statements[1] else {
+            panic!("Expected the for loop's block, got {:?}", statements[1]);
+        };
+        assert!(matches!(block.statements[..], [Stmt::Var(_)]));
+    }
+
+    #[test]
+    fn test_same_result() {
+        let source = r#"
+            var result = "";
+            fun f(n) { return n * (2 + 3) - (true ? 1 : 0); }
+            if (!false) result = result + f(4);
+            while (1 < 0) result = "never";
+            result = result + (nil or "!");
+        "#;
+        for statements in [optimized(source), {
+            let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+            Parser::new(&tokens).parse().unwrap()
+        }] {
+            let mut interpreter = Interpreter::new();
+            Resolver::new(&mut interpreter)
+                .resolve_stmts(&statements)
+                .unwrap();
+            interpreter.interpret(&statements).unwrap();
+            let name = Token::new(TokenType::Identifier("result".into()), "result".into(), 1);
+            let result = interpreter.environment.borrow().get(&name).unwrap();
+            assert_eq!(result, Literal::String("19!".to_string()));
+        }
+    }
+}
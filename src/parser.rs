@@ -1,43 +1,88 @@
 use crate::{
+    error_code::ErrorCode,
     expr::{
-        AssignExpr, BinaryExpr, CallExpr, ConditionalExpr, Expr, GetExpr, GroupingExpr, Literal,
-        LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr,
+        AssignExpr, BinaryExpr, CallExpr, ConditionalExpr, Expr, ExprId, GetExpr, GroupingExpr,
+        Literal, LiteralExpr, LogicalExpr, SetExpr, SuperExpr, ThisExpr, UnaryExpr, VariableExpr,
     },
     lox_result::{LoxResult, ParseErrorCause},
     stmt::{
-        BlockStmt, ClassStmt, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt,
-        VarStmt, WhileStmt,
+        BlockStmt, BreakStmt, ClassStmt, ContinueStmt, DeferStmt, DoWhileStmt, EnumStmt,
+        EnumVariant, ExpressionStmt, FunctionStmt, IfStmt, PrintStmt, ReturnStmt, Stmt, VarStmt,
+        WhileStmt,
+    },
+    token::{Span, Token, TokenType},
+};
+use std::{
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// How deeply statements and expressions can nest before parsing gives up, so that
+/// pathological input is reported instead of overflowing the stack.
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// Next expression id to hand out. Ids are shared by every parser, so lines parsed one at a
+/// time in the REPL never reuse the id of an expression that is still alive.
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Stands in for the end of input when the tokens run out without an `Eof` token
+static EOF: Token = Token {
+    token_type: TokenType::Eof,
+    lexeme: String::new(),
+    line: 0,
+    span: Span {
+        start: 0,
+        end: 0,
+        line: 0,
+        column: 0,
     },
-    token::{Token, TokenType},
 };
-use std::rc::Rc;
 
 /// Recursive decent parser
 pub struct Parser<'a> {
     tokens: std::iter::Peekable<std::slice::Iter<'a, Token>>,
+    depth: usize,
+    /// Errors recovered from so far, in source order
+    errors: Vec<ParseErrorCause>,
 }
 
 /*
 program        → statement* EOF ;
 classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
-                 "{" function* "}" ;
+                 "{" ( method | abstractMethod )* "}" ;
+method         → ( IDENTIFIER | PRIVATE_IDENTIFIER ) "(" parameters? ")" block ;
+abstractMethod → "abstract" IDENTIFIER "(" parameters? ")" ";" ;
 function       → IDENTIFIER "(" parameters? ")" block ;
+enumDecl       → "enum" IDENTIFIER "{" ( variant ( "," variant )* ","? )? "}" ;
+variant        → IDENTIFIER ( "(" parameters? ")" )? ;
 declaration    → classDecl
+               | enumDecl
                | funDecl
                | varDecl
                | statement ;
 statement      → exprStmt
-               | forStmt
+               | ( IDENTIFIER ":" )? loop
                | ifStmt
                | printStmt
                | returnStmt
-               | whileStmt
+               | breakStmt
+               | continueStmt
+               | deferStmt
                | block ;
+loop           → forStmt
+               | whileStmt
+               | doWhileStmt
+               | loopStmt ;
+breakStmt      → "break" IDENTIFIER? ";" ;
+continueStmt   → "continue" IDENTIFIER? ";" ;
+deferStmt      → "defer" expression ";" ;
 returnStmt     → "return" expression? ";" ;
 forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
                  expression? ";"
                  expression? ")" statement ;
 whileStmt      → "while" "(" expression ")" statement ;
+doWhileStmt    → "do" statement "while" "(" expression ")" ";" ;
+loopStmt       → "loop" block ;
 ifStmt         → "if" "(" expression ")" statement
                ( "else" statement )? ;
 block          → "{" declaration* "}" ;
@@ -57,7 +102,7 @@ comparison     → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
 term           → factor ( ( "-" | "+" ) factor )* ;
 factor         → unary ( ( "/" | "*" ) unary )* ;
 unary          → ( "!" | "-" ) unary | call ;
-call           → primary ( "(" arguments? ")" | "." IDENTIFIER )* ;
+call           → primary ( "(" arguments? ")" | "." ( IDENTIFIER | PRIVATE_IDENTIFIER ) )* ;
 arguments      → expression ( "," expression )* ;
 primary        → "true" | "false" | "nil" | "this"
                | NUMBER | STRING | IDENTIFIER | "(" expression ")"
@@ -68,41 +113,108 @@ impl<'a> Parser<'a> {
     pub fn new(tokens: &'a [Token]) -> Self {
         Self {
             tokens: tokens.iter().peekable(),
+            depth: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    fn peek(&mut self) -> &'a Token {
+        self.tokens.peek().copied().unwrap_or(&EOF)
+    }
+
+    fn advance(&mut self) -> &'a Token {
+        self.tokens.next().unwrap_or(&EOF)
+    }
+
+    fn next_id(&self) -> ExprId {
+        ExprId(NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn literal(&self, value: Literal, span: Span) -> Expr {
+        Expr::Literal(Box::new(LiteralExpr::new(self.next_id(), value, span)))
+    }
+
+    /// Runs a parse function that recurses into a nested statement or expression.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseErrorCause>,
+    ) -> Result<T, ParseErrorCause> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            let t = self.peek();
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::TooMuchNesting,
+                "Too much nesting.",
+            ));
+        }
+        // Restored as it was, as a chain that failed to parse doesn't give its levels back
+        let depth = self.depth;
+        self.depth += 1;
+        let result = parse(self);
+        self.depth = depth;
+        result
+    }
+
+    /// Counts one more level of nesting for a chain like `1 + 1 + 1` or `a.b.c`, where every
+    /// `operator` wraps the expression parsed so far in a new one. The chain restores the depth
+    /// it started at once it ends.
+    fn deepen(&mut self, operator: &Token) -> Result<(), ParseErrorCause> {
+        if self.depth >= MAX_NESTING_DEPTH {
+            return Err(ParseErrorCause::at(
+                operator,
+                ErrorCode::TooMuchNesting,
+                "Too much nesting.",
+            ));
         }
+        self.depth += 1;
+        Ok(())
     }
 
     pub fn parse(&mut self) -> Result<Vec<Stmt>, LoxResult> {
         let mut statements = Vec::new();
-        let mut errors = Vec::new();
-        while let Some(t) = self.tokens.peek() {
-            if t.token_type == TokenType::Eof {
-                break;
-            }
+        while self.peek().token_type != TokenType::Eof {
+            let remaining = self.tokens.len();
             match self.declaration() {
-                Ok(d) => {
-                    statements.push(d);
-                }
+                Ok(d) => statements.push(d),
                 Err(e) => {
-                    errors.push(e);
-                    self.sync();
+                    self.recover(e, remaining);
+                    // A stray '}' the failed statement stopped at, with no block to close
+                    self.tokens
+                        .next_if(|t| t.token_type == TokenType::RightBrace);
                 }
             }
         }
 
-        if errors.is_empty() {
+        if self.errors.is_empty() {
             Ok(statements)
         } else {
-            Err(LoxResult::ParseError { causes: errors })
+            let causes = std::mem::take(&mut self.errors);
+            Err(LoxResult::ParseError { causes })
+        }
+    }
+
+    /// Records `error` and skips to where the next declaration probably starts. Always moves on
+    /// from the `remaining` tokens there were when the declaration started, so one that fails
+    /// without consuming anything isn't retried forever.
+    fn recover(&mut self, error: ParseErrorCause, remaining: usize) {
+        self.errors.push(error);
+        // `sync` skips a '{' itself, along with the rest of its block
+        if self.tokens.len() == remaining {
+            self.tokens
+                .next_if(|t| t.token_type != TokenType::LeftBrace);
         }
+        self.sync();
     }
 
     fn declaration(&mut self) -> Result<Stmt, ParseErrorCause> {
         if let Some(_t) = self.tokens.next_if(|t| t.token_type == TokenType::Var) {
             self.var_declaration()
         } else if let Some(_t) = self.tokens.next_if(|t| t.token_type == TokenType::Fun) {
-            return self.function("function");
+            self.function("function")
         } else if let Some(_t) = self.tokens.next_if(|t| t.token_type == TokenType::Class) {
             self.class_declaration()
+        } else if let Some(_t) = self.tokens.next_if(|t| t.token_type == TokenType::Enum) {
+            self.enum_declaration()
         } else {
             self.statement()
         }
@@ -110,13 +222,13 @@ impl<'a> Parser<'a> {
 
     fn class_declaration(&mut self) -> Result<Stmt, ParseErrorCause> {
         let name = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if let TokenType::Identifier(_) = &t.token_type {
-                self.tokens.next().unwrap()
+                self.advance()
             } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedName,
                     "Expect class name.",
                 ));
             }
@@ -127,13 +239,13 @@ impl<'a> Parser<'a> {
             .next_if(|t| t.token_type == TokenType::Less)
             .is_some()
         {
-            let next_t = self.tokens.peek().unwrap();
+            let next_t = self.peek();
             if let TokenType::Identifier(_) = &next_t.token_type {
-                Some(VariableExpr::new(self.tokens.next().unwrap().clone()))
+                Some(VariableExpr::new(self.next_id(), self.advance().clone()))
             } else {
-                return Err(ParseErrorCause::new(
-                    next_t.line,
-                    Some(next_t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    next_t,
+                    ErrorCode::ExpectedName,
                     "Expect superclass name.",
                 ));
             }
@@ -141,61 +253,135 @@ impl<'a> Parser<'a> {
             None
         };
 
-        if let Some(t) = self.tokens.peek() {
-            if let TokenType::LeftBrace = &t.token_type {
-                self.tokens.next();
-            } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
-                    "Expect '{' before class body.",
-                ));
-            }
+        let open = self.peek();
+        if let TokenType::LeftBrace = &open.token_type {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                open,
+                ErrorCode::ExpectedToken,
+                "Expect '{' before class body.",
+            ));
         }
 
         let mut methods = Vec::new();
-        while let Some(t) = self.tokens.peek() {
-            if t.token_type == TokenType::RightBrace || t.token_type == TokenType::Eof {
-                break;
-            }
-            methods.push(self.function("method")?);
-        }
-
-        if let Some(t) = self.tokens.peek() {
-            if let TokenType::RightBrace = &t.token_type {
-                self.tokens.next();
+        let mut abstract_methods = Vec::new();
+        while !matches!(
+            self.peek().token_type,
+            TokenType::RightBrace | TokenType::Eof
+        ) {
+            let remaining = self.tokens.len();
+            let method = if self
+                .tokens
+                .next_if(|t| t.token_type == TokenType::Abstract)
+                .is_some()
+            {
+                self.abstract_method().map(|m| abstract_methods.push(m))
             } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
-                    "Expect '}' after class body.",
-                ));
+                self.function("method").map(|m| methods.push(m))
+            };
+            if let Err(e) = method {
+                self.recover(e, remaining);
             }
         }
+        self.close_brace(open.span, "Expect '}' after class body.");
 
         Ok(Stmt::Class(Box::new(ClassStmt::new(
             name.clone(),
             methods,
+            abstract_methods,
             superclass,
         ))))
     }
 
+    fn enum_declaration(&mut self) -> Result<Stmt, ParseErrorCause> {
+        let name = {
+            let t = self.peek();
+            if let TokenType::Identifier(_) = &t.token_type {
+                self.advance()
+            } else {
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedName,
+                    "Expect enum name.",
+                ));
+            }
+        };
+
+        let t = self.peek();
+        if t.token_type == TokenType::LeftBrace {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect '{' before enum body.",
+            ));
+        }
+
+        let mut variants = Vec::new();
+        while self.peek().token_type != TokenType::RightBrace {
+            let t = self.peek();
+            let variant = if let TokenType::Identifier(_) = &t.token_type {
+                self.advance()
+            } else {
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedName,
+                    "Expect variant name.",
+                ));
+            };
+            let fields = if self
+                .tokens
+                .next_if(|t| t.token_type == TokenType::LeftParen)
+                .is_some()
+            {
+                self.parameters()?
+            } else {
+                vec![]
+            };
+            variants.push(EnumVariant::new(variant.clone(), fields));
+
+            // Trailing comma is allowed
+            if self
+                .tokens
+                .next_if(|t| t.token_type == TokenType::Comma)
+                .is_none()
+            {
+                break;
+            }
+        }
+
+        let t = self.peek();
+        if t.token_type == TokenType::RightBrace {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect '}' after enum body.",
+            ));
+        }
+
+        Ok(Stmt::Enum(Box::new(EnumStmt::new(name.clone(), variants))))
+    }
+
     fn var_declaration(&mut self) -> Result<Stmt, ParseErrorCause> {
         let name = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if let TokenType::Identifier(_) = &t.token_type {
-                self.tokens.next().unwrap()
+                self.advance()
             } else if let TokenType::Eof = &t.token_type {
                 // TODO: unreachable?
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedName,
                     "Expect variable name.",
                 ));
             } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedName,
                     "Expect variable name.",
                 ));
             }
@@ -203,7 +389,7 @@ impl<'a> Parser<'a> {
 
         // TODO: Can be cleaned up into one loop?
         let initializer = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type == TokenType::Equal {
                 self.tokens.next();
                 Some(self.expression()?)
@@ -212,13 +398,13 @@ impl<'a> Parser<'a> {
             }
         };
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::Semicolon {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ';' after variable declaration.",
             ));
         }
@@ -227,7 +413,12 @@ impl<'a> Parser<'a> {
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseErrorCause> {
-        let t = self.tokens.peek().unwrap();
+        // `label: while (...)`, needs a second token of lookahead to tell it from an expression
+        let is_label = matches!(
+            self.tokens.clone().nth(1),
+            Some(t) if t.token_type == TokenType::Colon
+        );
+        let t = self.peek();
         match t.token_type {
             TokenType::If => {
                 self.tokens.next();
@@ -238,17 +429,20 @@ impl<'a> Parser<'a> {
                 self.print_statement()
             }
             TokenType::Return => self.return_statement(),
-            TokenType::While => {
-                self.tokens.next();
-                self.while_statement()
+            TokenType::Break => self.break_statement(),
+            TokenType::Continue => self.continue_statement(),
+            TokenType::Defer => self.defer_statement(),
+            TokenType::While | TokenType::For | TokenType::Do | TokenType::Loop => {
+                self.loop_statement(None)
             }
-            TokenType::For => {
+            TokenType::Identifier(_) if is_label => {
+                let label = self.advance().clone();
                 self.tokens.next();
-                self.for_statement()
+                self.loop_statement(Some(label))
             }
             TokenType::LeftBrace => {
                 self.tokens.next();
-                let s = self.block()?;
+                let s = self.block(t.span);
                 // TODO: Check this new
                 Ok(Stmt::Block(Box::new(BlockStmt::new(s))))
             }
@@ -256,47 +450,136 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn while_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
-        let t = self.tokens.peek().unwrap();
+    fn loop_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseErrorCause> {
+        let t = self.advance();
+        match t.token_type {
+            TokenType::While => self.while_statement(label),
+            TokenType::For => self.for_statement(label),
+            TokenType::Do => self.do_while_statement(label),
+            TokenType::Loop => self.infinite_loop_statement(label),
+            _ => Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect loop after label.",
+            )),
+        }
+    }
+
+    fn while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseErrorCause> {
+        let t = self.peek();
+        if t.token_type == TokenType::LeftParen {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect '(' after 'while'.",
+            ));
+        }
+        let condition = self.expression()?;
+        let t = self.peek();
+        if t.token_type == TokenType::RightParen {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect ')' after condition.",
+            ));
+        }
+        let body = self.nested(Self::statement)?;
+
+        Ok(Stmt::While(Box::new(WhileStmt::new(
+            condition, body, None, label,
+        ))))
+    }
+
+    fn do_while_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseErrorCause> {
+        let body = self.nested(Self::statement)?;
+        let t = self.peek();
+        if t.token_type == TokenType::While {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect 'while' after do loop body.",
+            ));
+        }
+        let t = self.peek();
         if t.token_type == TokenType::LeftParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect '(' after 'while'.",
             ));
         }
         let condition = self.expression()?;
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::RightParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ')' after condition.",
             ));
         }
-        let body = self.statement()?;
+        let t = self.peek();
+        if t.token_type == TokenType::Semicolon {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect ';' after do-while condition.",
+            ));
+        }
 
-        Ok(Stmt::While(Box::new(WhileStmt::new(condition, body))))
+        Ok(Stmt::DoWhile(Box::new(DoWhileStmt::new(
+            body, condition, label,
+        ))))
     }
 
-    fn for_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
-        let t = self.tokens.peek().unwrap();
+    /// An infinite loop, desugared into `while (true)` like a `for` without condition.
+    fn infinite_loop_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseErrorCause> {
+        let t = self.peek();
+        let brace = t.span;
+        if t.token_type == TokenType::LeftBrace {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect '{' after 'loop'.",
+            ));
+        }
+        let body = Stmt::Block(Box::new(BlockStmt::new(self.block(brace))));
+
+        Ok(Stmt::While(Box::new(WhileStmt::new(
+            self.literal(Literal::Boolean(true), brace),
+            body,
+            None,
+            label,
+        ))))
+    }
+
+    fn for_statement(&mut self, label: Option<Token>) -> Result<Stmt, ParseErrorCause> {
+        let t = self.peek();
         if t.token_type == TokenType::LeftParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect '(' after 'for'.",
             ));
         }
 
         let initializer = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type == TokenType::Semicolon {
                 self.tokens.next();
                 None
@@ -310,27 +593,28 @@ impl<'a> Parser<'a> {
 
         // Either there is a condition or it an infinite loop (true)
         let condition = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type != TokenType::Semicolon {
                 self.expression()?
             } else {
-                Expr::Literal(Literal::Boolean(true))
+                let span = t.span;
+                self.literal(Literal::Boolean(true), span)
             }
         };
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::Semicolon {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ';' after loop condition.",
             ));
         }
 
         let increment = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type != TokenType::RightParen {
                 Some(self.expression()?)
             } else {
@@ -338,30 +622,21 @@ impl<'a> Parser<'a> {
             }
         };
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::RightParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ')' after for clauses.",
             ));
         }
 
-        let mut body = self.statement()?;
+        let body = self.nested(Self::statement)?;
 
-        // If an increment stmt exists, append it so it executes after the body
-        // TODO: Verify generated ast nodes vs while
-        if let Some(increment) = increment {
-            let stmts = vec![
-                body,
-                Stmt::Expression(Box::new(ExpressionStmt::new(increment))),
-            ];
-            body = Stmt::Block(Box::new(BlockStmt::new(stmts)));
-        }
-
-        body = Stmt::While(Box::new(WhileStmt::new(condition, body)));
+        // The increment is kept apart from the body so that it still runs after a `continue`
+        let mut body = Stmt::While(Box::new(WhileStmt::new(condition, body, increment, label)));
 
         // If an initializer exists, run it first, then execute the loop (fancy while loop)
         if let Some(initializer) = initializer {
@@ -372,31 +647,31 @@ impl<'a> Parser<'a> {
     }
 
     fn if_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::LeftParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect '(' after if.",
             ));
         }
         let condition = self.expression()?;
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::RightParen {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ')' after if condition.",
             ));
         }
-        let then_branch = self.statement()?;
+        let then_branch = self.nested(Self::statement)?;
         let else_branch = {
             match self.tokens.next_if(|t| t.token_type == TokenType::Else) {
-                Some(_t) => Some(self.statement()?),
+                Some(_t) => Some(self.nested(Self::statement)?),
                 None => None,
             }
         };
@@ -410,13 +685,13 @@ impl<'a> Parser<'a> {
 
     fn print_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
         let value = self.expression()?;
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::Semicolon {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ';' after expression",
             ));
         }
@@ -425,9 +700,9 @@ impl<'a> Parser<'a> {
     }
 
     fn return_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
-        let keyword = self.tokens.next().unwrap();
+        let keyword = self.advance();
         let value = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type != TokenType::Semicolon {
                 Some(self.expression()?)
             } else {
@@ -435,13 +710,13 @@ impl<'a> Parser<'a> {
             }
         };
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::Semicolon {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ';' after return value.",
             ));
         }
@@ -452,15 +727,63 @@ impl<'a> Parser<'a> {
         ))))
     }
 
+    fn break_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
+        let keyword = self.advance().clone();
+        let label = self.jump_label("break")?;
+        Ok(Stmt::Break(Box::new(BreakStmt::new(keyword, label))))
+    }
+
+    fn continue_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
+        let keyword = self.advance().clone();
+        let label = self.jump_label("continue")?;
+        Ok(Stmt::Continue(Box::new(ContinueStmt::new(keyword, label))))
+    }
+
+    /// Optional label after `break` or `continue`, including the terminating ';'.
+    fn jump_label(&mut self, keyword: &str) -> Result<Option<Token>, ParseErrorCause> {
+        let label = self
+            .tokens
+            .next_if(|t| matches!(t.token_type, TokenType::Identifier(_)))
+            .cloned();
+
+        let t = self.peek();
+        if t.token_type == TokenType::Semicolon {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                &format!("Expect ';' after '{keyword}'."),
+            ));
+        }
+        Ok(label)
+    }
+
+    fn defer_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
+        let keyword = self.advance().clone();
+        let expression = self.expression()?;
+        let t = self.peek();
+        if t.token_type == TokenType::Semicolon {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect ';' after deferred expression.",
+            ));
+        }
+        Ok(Stmt::Defer(Rc::new(DeferStmt::new(keyword, expression))))
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt, ParseErrorCause> {
         let expr = self.expression()?;
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if t.token_type == TokenType::Semicolon {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ';' after expression.",
             ));
         }
@@ -468,52 +791,102 @@ impl<'a> Parser<'a> {
     }
 
     fn function(&mut self, kind: &str) -> Result<Stmt, ParseErrorCause> {
+        let (name, params) = self.function_signature(kind)?;
+
+        let t = self.peek();
+        if let TokenType::LeftBrace = &t.token_type {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                &format!("Expect '{{' before {kind} body."),
+            ));
+        }
+
+        let mut body = self.block(t.span);
+        mark_tail_calls(&mut body);
+
+        Ok(Stmt::Function(Rc::new(FunctionStmt::new(
+            name, params, body,
+        ))))
+    }
+
+    /// Abstract methods only have a signature, subclasses must provide the body.
+    fn abstract_method(&mut self) -> Result<Rc<FunctionStmt>, ParseErrorCause> {
+        let (name, params) = self.function_signature("abstract method")?;
+
+        let t = self.peek();
+        if t.token_type == TokenType::Semicolon {
+            self.tokens.next();
+        } else {
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
+                "Expect ';' after abstract method declaration.",
+            ));
+        }
+
+        Ok(Rc::new(FunctionStmt::new(name, params, vec![])))
+    }
+
+    fn function_signature(&mut self, kind: &str) -> Result<(Token, Vec<Token>), ParseErrorCause> {
         let name = {
-            let t = self.tokens.peek().unwrap();
-            if let TokenType::Identifier(_) = &t.token_type {
-                self.tokens.next().unwrap()
-            } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
-                    &format!("Expect {kind} name."),
-                ));
+            let t = self.peek();
+            match &t.token_type {
+                TokenType::Identifier(_) => self.advance(),
+                // Only methods can be private
+                TokenType::PrivateIdentifier(_) if kind == "method" => self.advance(),
+                _ => {
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::ExpectedName,
+                        &format!("Expect {kind} name."),
+                    ))
+                }
             }
         };
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if let TokenType::LeftParen = &t.token_type {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 &format!("Expect '(' after {kind} name."),
             ));
         }
 
+        let params = self.parameters()?;
+
+        Ok((name.clone(), params))
+    }
+
+    /// Comma separated identifiers up to and including the closing ')'.
+    fn parameters(&mut self) -> Result<Vec<Token>, ParseErrorCause> {
         let mut params = Vec::new();
-        let t = &(*self.tokens.peek().unwrap()).clone();
-        if t.token_type != TokenType::RightParen {
+        if self.peek().token_type != TokenType::RightParen {
             loop {
+                let t = self.peek();
                 let p = if let TokenType::Identifier(_) = &t.token_type {
-                    self.tokens.next().unwrap().clone()
+                    self.advance().clone()
                 } else {
-                    return Err(ParseErrorCause::new(
-                        t.line,
-                        Some(t.lexeme.clone()),
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::ExpectedName,
                         "Expect parameter name.",
                     ));
                 };
                 if params.len() >= 255 {
-                    return Err(ParseErrorCause::new(
-                        p.line,
-                        Some(p.lexeme),
+                    return Err(ParseErrorCause::at(
+                        &p,
+                        ErrorCode::TooManyParameters,
                         "Can't have more than 255 parameters.",
                     ));
                 }
                 params.push(p);
-                let t = self.tokens.peek().unwrap();
+                let t = self.peek();
                 if t.token_type != TokenType::Comma {
                     break;
                 } else {
@@ -522,60 +895,54 @@ impl<'a> Parser<'a> {
             }
         }
 
-        let t = self.tokens.peek().unwrap();
+        let t = self.peek();
         if let TokenType::RightParen = &t.token_type {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            return Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedToken,
                 "Expect ')' after parameters.",
             ));
         }
 
-        let t = self.tokens.peek().unwrap();
-        if let TokenType::LeftBrace = &t.token_type {
-            self.tokens.next();
-        } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
-                &format!("Expect '{{' before {kind} body."),
-            ));
-        }
-
-        let body = self.block()?;
-
-        Ok(Stmt::Function(Rc::new(FunctionStmt::new(
-            name.clone(),
-            params,
-            body,
-        ))))
+        Ok(params)
     }
 
-    fn block(&mut self) -> Result<Vec<Stmt>, ParseErrorCause> {
+    /// Declarations up to and including the '}' matching the '{' at `open`. Errors in them are
+    /// recovered from, so that one mistake doesn't hide the rest of the block.
+    fn block(&mut self, open: Span) -> Vec<Stmt> {
         let mut statements = Vec::new();
-        while let Some(t) = self.tokens.peek() {
-            match t.token_type {
+        loop {
+            match self.peek().token_type {
                 TokenType::RightBrace | TokenType::Eof => break,
-                _ => statements.push(self.declaration()?),
+                _ => {
+                    let remaining = self.tokens.len();
+                    match self.nested(Self::declaration) {
+                        Ok(d) => statements.push(d),
+                        Err(e) => self.recover(e, remaining),
+                    }
+                }
             }
         }
-        let t = self.tokens.peek().unwrap();
+        self.close_brace(open, "Expect '}' after block.");
+        statements
+    }
+
+    /// Consumes the '}' matching the '{' at `open`. A missing one is reported but not
+    /// propagated, as there is nothing left to recover from at the end of the input.
+    fn close_brace(&mut self, open: Span, message: &str) {
+        let t = self.peek();
         if t.token_type == TokenType::RightBrace {
             self.tokens.next();
         } else {
-            return Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
-                "Expect '}' after block.",
-            ));
+            let error = ParseErrorCause::at(t, ErrorCode::ExpectedToken, message);
+            self.errors.push(error.with_label(open, "unclosed '{'"));
         }
-        Ok(statements)
     }
 
     fn expression(&mut self) -> Result<Expr, ParseErrorCause> {
-        self.conditional()
+        self.nested(Self::conditional)
     }
 
     fn conditional(&mut self) -> Result<Expr, ParseErrorCause> {
@@ -586,18 +953,23 @@ impl<'a> Parser<'a> {
             .next_if(|t| t.token_type == TokenType::QuestionMark)
         {
             let left = self.expression()?;
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type == TokenType::Colon {
                 self.tokens.next();
             } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedToken,
                     "Expect ':' after truthy expression",
                 ));
             }
-            let right = self.conditional()?;
-            expr = Expr::Conditional(Box::new(ConditionalExpr::new(expr, left, right)))
+            let right = self.nested(Self::conditional)?;
+            expr = Expr::Conditional(Box::new(ConditionalExpr::new(
+                self.next_id(),
+                expr,
+                left,
+                right,
+            )))
         }
         Ok(expr)
     }
@@ -607,23 +979,32 @@ impl<'a> Parser<'a> {
 
         if let Some(t) = self.tokens.peek() {
             if t.token_type == TokenType::Equal {
-                let equals = self.tokens.next().unwrap();
+                let equals = self.advance();
                 // Recursively parse right-hand side since assignment is right-associative
-                let value = self.assignment()?;
+                let value = self.nested(Self::assignment)?;
 
                 match expr {
                     Expr::Variable(s) => {
-                        return Ok(Expr::Assign(Box::new(AssignExpr::new(s.name, value))));
+                        return Ok(Expr::Assign(Box::new(AssignExpr::new(
+                            self.next_id(),
+                            s.name,
+                            value,
+                        ))));
                     }
                     Expr::Get(e) => {
-                        return Ok(Expr::Set(Box::new(SetExpr::new(e.object, e.name, value))));
+                        return Ok(Expr::Set(Box::new(SetExpr::new(
+                            self.next_id(),
+                            e.object,
+                            e.name,
+                            value,
+                        ))));
                     }
                     _ => {}
                 }
                 // NOTE: Err is reported but not thrown here, parser is not in confused state where it needs to panic and sync
-                return Err(ParseErrorCause::new(
-                    equals.line,
-                    Some(equals.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    equals,
+                    ErrorCode::InvalidAssignmentTarget,
                     "Invalid assignment target.",
                 ));
             }
@@ -635,50 +1016,75 @@ impl<'a> Parser<'a> {
     fn logic_or(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.logic_and()?;
 
+        let depth = self.depth;
         // TODO: Next if
         while let Some(t) = self.tokens.peek() {
             if t.token_type == TokenType::Or {
-                let operator = self.tokens.next().unwrap();
+                let operator = self.advance();
+                self.deepen(operator)?;
                 let right = self.logic_and()?;
-                expr = Expr::Logical(Box::new(LogicalExpr::new(expr, operator.clone(), right)));
+                expr = Expr::Logical(Box::new(LogicalExpr::new(
+                    self.next_id(),
+                    expr,
+                    operator.clone(),
+                    right,
+                )));
             } else {
                 break;
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn logic_and(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.equality()?;
 
+        let depth = self.depth;
         while let Some(t) = self.tokens.peek() {
             if t.token_type == TokenType::And {
-                let operator = self.tokens.next().unwrap();
+                let operator = self.advance();
+                self.deepen(operator)?;
                 let right = self.equality()?;
-                expr = Expr::Logical(Box::new(LogicalExpr::new(expr, operator.clone(), right)));
+                expr = Expr::Logical(Box::new(LogicalExpr::new(
+                    self.next_id(),
+                    expr,
+                    operator.clone(),
+                    right,
+                )));
             } else {
                 break;
             }
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.comparison()?;
 
+        let depth = self.depth;
         while let Some(t) = self.tokens.next_if(|t| {
             t.token_type == TokenType::BangEqual || t.token_type == TokenType::EqualEqual
         }) {
             let operator = t;
+            self.deepen(operator)?;
             let right = self.comparison()?;
-            expr = Expr::Binary(Box::new(BinaryExpr::new(expr, operator.to_owned(), right)));
+            expr = Expr::Binary(Box::new(BinaryExpr::new(
+                self.next_id(),
+                expr,
+                operator.to_owned(),
+                right,
+            )));
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.term()?;
 
+        let depth = self.depth;
         while let Some(t) = self.tokens.next_if(|t| {
             t.token_type == TokenType::Greater
                 || t.token_type == TokenType::GreaterEqual
@@ -686,38 +1092,60 @@ impl<'a> Parser<'a> {
                 || t.token_type == TokenType::LessEqual
         }) {
             let operator = t;
+            self.deepen(operator)?;
             let right = self.term()?;
-            expr = Expr::Binary(Box::new(BinaryExpr::new(expr, operator.to_owned(), right)));
+            expr = Expr::Binary(Box::new(BinaryExpr::new(
+                self.next_id(),
+                expr,
+                operator.to_owned(),
+                right,
+            )));
         }
-
+        self.depth = depth;
         Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.factor()?;
 
+        let depth = self.depth;
         while let Some(t) = self
             .tokens
             .next_if(|t| t.token_type == TokenType::Minus || t.token_type == TokenType::Plus)
         {
             let operator = t;
+            self.deepen(operator)?;
             let right = self.factor()?;
-            expr = Expr::Binary(Box::new(BinaryExpr::new(expr, operator.to_owned(), right)));
+            expr = Expr::Binary(Box::new(BinaryExpr::new(
+                self.next_id(),
+                expr,
+                operator.to_owned(),
+                right,
+            )));
         }
+        self.depth = depth;
         Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.unary()?;
 
+        let depth = self.depth;
         while let Some(t) = self
             .tokens
             .next_if(|t| t.token_type == TokenType::Slash || t.token_type == TokenType::Star)
         {
             let operator = t;
+            self.deepen(operator)?;
             let right = self.unary()?;
-            expr = Expr::Binary(Box::new(BinaryExpr::new(expr, operator.to_owned(), right)));
+            expr = Expr::Binary(Box::new(BinaryExpr::new(
+                self.next_id(),
+                expr,
+                operator.to_owned(),
+                right,
+            )));
         }
+        self.depth = depth;
         Ok(expr)
     }
 
@@ -727,8 +1155,12 @@ impl<'a> Parser<'a> {
             .next_if(|t| t.token_type == TokenType::Bang || t.token_type == TokenType::Minus)
         {
             let operator = t;
-            let right = self.unary()?;
-            let e = Expr::Unary(Box::new(UnaryExpr::new(operator.clone(), right)));
+            let right = self.nested(Self::unary)?;
+            let e = Expr::Unary(Box::new(UnaryExpr::new(
+                self.next_id(),
+                operator.clone(),
+                right,
+            )));
             return Ok(e);
         }
         self.call()
@@ -737,22 +1169,25 @@ impl<'a> Parser<'a> {
     fn call(&mut self) -> Result<Expr, ParseErrorCause> {
         let mut expr = self.primary()?;
 
+        let depth = self.depth;
         // Deliberate loop. Setting up for parsing object properties later on.
         loop {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type == TokenType::LeftParen {
                 self.tokens.next();
+                self.deepen(t)?;
                 expr = self.finish_call(expr)?;
             } else if t.token_type == TokenType::Dot {
                 self.tokens.next();
-                let t = self.tokens.peek().unwrap();
-                if let TokenType::Identifier(_) = &t.token_type {
-                    let name = self.tokens.next().unwrap();
-                    expr = Expr::Get(Box::new(GetExpr::new(name.clone(), expr)));
+                self.deepen(t)?;
+                let t = self.peek();
+                if let TokenType::Identifier(_) | TokenType::PrivateIdentifier(_) = &t.token_type {
+                    let name = self.advance();
+                    expr = Expr::Get(Box::new(GetExpr::new(self.next_id(), name.clone(), expr)));
                 } else {
-                    return Err(ParseErrorCause::new(
-                        t.line,
-                        Some(t.lexeme.clone()),
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::ExpectedName,
                         "Expect property name after '.'.",
                     ));
                 }
@@ -760,23 +1195,23 @@ impl<'a> Parser<'a> {
                 break;
             }
         }
-
+        self.depth = depth;
         Ok(expr)
     }
 
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseErrorCause> {
         let mut arguments = Vec::new();
 
-        let t = &(*self.tokens.peek().unwrap()).clone();
+        let t = &(*self.peek()).clone();
         if t.token_type != TokenType::RightParen {
             // Do-while
             arguments.push(self.expression()?);
             while let Some(_nxt_t) = self.tokens.next_if(|t| t.token_type == TokenType::Comma) {
                 if arguments.len() >= 255 {
-                    let t = self.tokens.next().unwrap();
-                    return Err(ParseErrorCause::new(
-                        t.line,
-                        Some(t.lexeme.clone()),
+                    let t = self.advance();
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::TooManyArguments,
                         "Can't have more than 255 arguments.",
                     ));
                 } else {
@@ -786,19 +1221,20 @@ impl<'a> Parser<'a> {
         }
 
         let paren = {
-            let t = self.tokens.peek().unwrap();
+            let t = self.peek();
             if t.token_type == TokenType::RightParen {
-                self.tokens.next().unwrap()
+                self.advance()
             } else {
-                return Err(ParseErrorCause::new(
-                    t.line,
-                    Some(t.lexeme.clone()),
+                return Err(ParseErrorCause::at(
+                    t,
+                    ErrorCode::ExpectedToken,
                     "Expect ')' after arguments.",
                 ));
             }
         };
 
         Ok(Expr::Call(Box::new(CallExpr::new(
+            self.next_id(),
             callee,
             paren.clone(),
             arguments,
@@ -807,87 +1243,160 @@ impl<'a> Parser<'a> {
 
     // TODO: Error propagation and handle panics.
     fn primary(&mut self) -> Result<Expr, ParseErrorCause> {
-        let t = self.tokens.next().unwrap();
+        let t = self.advance();
         match &t.token_type {
-            TokenType::False => Ok(Expr::Literal(Literal::Boolean(false))),
-            TokenType::True => Ok(Expr::Literal(Literal::Boolean(true))),
-            TokenType::Nil => Ok(Expr::Literal(Literal::Nil)),
-            TokenType::String(s) => Ok(Expr::Literal(Literal::String(s.to_string()))),
-            TokenType::Number(n) => Ok(Expr::Literal(Literal::Number(*n))),
-            TokenType::This => Ok(Expr::This(Box::new(ThisExpr::new(t.clone())))),
+            TokenType::False => Ok(self.literal(Literal::Boolean(false), t.span)),
+            TokenType::True => Ok(self.literal(Literal::Boolean(true), t.span)),
+            TokenType::Nil => Ok(self.literal(Literal::Nil, t.span)),
+            TokenType::String(s) => Ok(self.literal(Literal::String(s.to_string()), t.span)),
+            TokenType::Number(n) => Ok(self.literal(Literal::Number(*n), t.span)),
+            TokenType::This => Ok(Expr::This(Box::new(ThisExpr::new(
+                self.next_id(),
+                t.clone(),
+            )))),
             TokenType::Super => {
                 let keyword = t;
-                let t = self.tokens.peek().unwrap();
+                let t = self.peek();
                 if t.token_type == TokenType::Dot {
                     self.tokens.next();
                 } else {
-                    return Err(ParseErrorCause::new(
-                        t.line,
-                        Some(t.lexeme.clone()),
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::ExpectedToken,
                         "Expect '.' after 'super'.",
                     ));
                 }
 
                 let method = {
-                    let t = self.tokens.peek().unwrap();
+                    let t = self.peek();
                     if let TokenType::Identifier(_) = &t.token_type {
-                        self.tokens.next().unwrap()
+                        self.advance()
                     } else {
-                        return Err(ParseErrorCause::new(
-                            t.line,
-                            Some(t.lexeme.clone()),
+                        return Err(ParseErrorCause::at(
+                            t,
+                            ErrorCode::ExpectedName,
                             "Expect superclass method name.",
                         ));
                     }
                 };
 
                 Ok(Expr::Super(Box::new(SuperExpr::new(
+                    self.next_id(),
                     keyword.clone(),
                     method.clone(),
                 ))))
             }
             TokenType::LeftParen => {
+                let open = t.span;
                 let expr = self.expression()?;
-                let t = self.tokens.peek().unwrap();
-                if t.token_type == TokenType::RightParen {
-                    self.tokens.next();
+                let t = self.peek();
+                let close = if t.token_type == TokenType::RightParen {
+                    self.advance().span
                 } else {
-                    return Err(ParseErrorCause::new(
-                        t.line,
-                        Some(t.lexeme.clone()),
+                    return Err(ParseErrorCause::at(
+                        t,
+                        ErrorCode::ExpectedToken,
                         "Expect ')' after expression",
-                    ));
-                }
-                Ok(Expr::Grouping(Box::new(GroupingExpr::new(expr))))
+                    )
+                    .with_label(open, "unclosed '('"));
+                };
+                Ok(Expr::Grouping(Box::new(GroupingExpr::new(
+                    self.next_id(),
+                    expr,
+                    open.to(close),
+                ))))
             }
-            TokenType::Identifier(_) => Ok(Expr::Variable(Box::new(VariableExpr::new(t.clone())))),
-            _ => Err(ParseErrorCause::new(
-                t.line,
-                Some(t.lexeme.clone()),
+            TokenType::Identifier(_) => Ok(Expr::Variable(Box::new(VariableExpr::new(
+                self.next_id(),
+                t.clone(),
+            )))),
+            _ => Err(ParseErrorCause::at(
+                t,
+                ErrorCode::ExpectedExpression,
                 "Expect expression.",
             )),
         }
     }
 
+    /// Skips to the next statement boundary: past a ';' or a `{ ... }` group, or up to a keyword
+    /// that starts a statement or the '}' closing the enclosing block. Braces in what is skipped
+    /// are kept balanced, so blocks still end at their own '}'.
     fn sync(&mut self) {
-        while let Some(t) = self.tokens.peek() {
-            if t.token_type == TokenType::Eof {
-                break;
-            }
+        let mut depth = 0;
+        loop {
+            let t = self.peek();
             match t.token_type {
-                TokenType::Class
+                TokenType::Eof => return,
+                TokenType::RightBrace
+                | TokenType::Class
+                | TokenType::Enum
                 | TokenType::Fun
                 | TokenType::Var
                 | TokenType::For
                 | TokenType::If
                 | TokenType::While
+                | TokenType::Do
+                | TokenType::Loop
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                    if depth == 0 =>
+                {
+                    return
+                }
                 _ => (),
             }
 
             self.tokens.next();
+            match t.token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                TokenType::Semicolon if depth == 0 => return,
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Marks `return f(...)` statements of a function body as tail calls. A function with deferred
+/// expressions is left alone, because those have to run after the returned call finishes.
+fn mark_tail_calls(body: &mut [Stmt]) {
+    if !body.iter().any(contains_defer) {
+        body.iter_mut().for_each(mark_tail_call);
+    }
+}
+
+/// Nested functions and classes are not searched, they are marked when they are parsed.
+fn contains_defer(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Defer(_) => true,
+        Stmt::Block(s) => s.statements.iter().any(contains_defer),
+        Stmt::If(s) => {
+            contains_defer(&s.then_branch) || s.else_branch.as_ref().is_some_and(contains_defer)
         }
+        Stmt::While(s) => contains_defer(&s.body),
+        Stmt::DoWhile(s) => contains_defer(&s.body),
+        _ => false,
+    }
+}
+
+fn mark_tail_call(stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Return(s) => s.tail_call = matches!(s.value, Some(Expr::Call(_))),
+        Stmt::Block(s) => s.statements.iter_mut().for_each(mark_tail_call),
+        Stmt::If(s) => {
+            mark_tail_call(&mut s.then_branch);
+            if let Some(else_branch) = &mut s.else_branch {
+                mark_tail_call(else_branch);
+            }
+        }
+        Stmt::While(s) => mark_tail_call(&mut s.body),
+        Stmt::DoWhile(s) => mark_tail_call(&mut s.body),
+        _ => {}
     }
 }
 
@@ -927,3 +1436,150 @@ impl<'a> Parser<'a> {
 //         assert_eq!(e.unwrap().to_string(), "(?: (< 5 6) (- 1 2) (* 4 3))")
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use crate::{lox_result::LoxResult, scanner::Scanner, stmt::Stmt};
+
+    use super::{Parser, MAX_NESTING_DEPTH};
+
+    fn parse_errors(source: &str) -> Vec<String> {
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        match Parser::new(tokens).parse() {
+            Ok(_) => vec![],
+            Err(LoxResult::ParseError { causes }) => {
+                causes.into_iter().map(|c| c.message).collect()
+            }
+            Err(e) => panic!("Unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        // Deeper than the test thread's stack allows, so parse like main() does
+        crate::with_interpreter_stack(nesting_limit)
+            .unwrap_or_else(|e| std::panic::resume_unwind(e));
+    }
+
+    fn nesting_limit() {
+        let nested = |open: &str, inner: &str, close: &str, depth: usize| {
+            format!("{}{inner}{}", open.repeat(depth), close.repeat(depth))
+        };
+
+        let shallow = MAX_NESTING_DEPTH / 2;
+        assert!(parse_errors(&nested("-", "1;", "", shallow)).is_empty());
+        assert!(parse_errors(&nested("{", "", "}", shallow)).is_empty());
+
+        let deep = MAX_NESTING_DEPTH * 2;
+        assert_eq!(
+            parse_errors(&nested("-", "1;", "", deep)),
+            vec!["Too much nesting."]
+        );
+        assert_eq!(
+            parse_errors(&nested("{", "", "}", deep)),
+            vec!["Too much nesting."]
+        );
+        assert_eq!(
+            parse_errors(&nested("{", "print 1;", "}", deep)),
+            vec!["Too much nesting."]
+        );
+        assert_eq!(
+            parse_errors(&format!("print {};", nested("(", "1", ")", deep))),
+            vec!["Too much nesting."]
+        );
+
+        // Chains of left-associative operators nest as deeply as they are long
+        let chain = |operator: &str, terms: usize| vec!["a"; terms].join(operator);
+        for operator in [" + ", " * ", " == ", " < ", " and ", " or ", "."] {
+            let shallow = format!("print {};", chain(operator, shallow));
+            assert!(parse_errors(&shallow).is_empty(), "{operator}");
+            let flat = format!("print {};\nprint 1;", chain(operator, 5_000));
+            assert_eq!(parse_errors(&flat), vec!["Too much nesting."], "{operator}");
+        }
+        let calls = format!("f{};", "()".repeat(5_000));
+        assert_eq!(parse_errors(&calls), vec!["Too much nesting."]);
+        let sum = format!("print 1{};", "+1".repeat(50_000));
+        assert_eq!(parse_errors(&sum), vec!["Too much nesting."]);
+    }
+
+    #[test]
+    fn test_recovers_inside_blocks() {
+        let source = "
+            fun f() {
+                var a = ;
+                print a;
+                b = 3 +;
+            }
+            class A {
+                m(x y) { return 1; }
+                n() { print ; }
+                abstract o(;
+                ok() {}
+            }
+            print \"after\" }
+            { print 1;
+        ";
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "Expect expression.",
+                "Expect expression.",
+                "Expect ')' after parameters.",
+                "Expect expression.",
+                "Expect parameter name.",
+                "Expect ';' after expression",
+                "Expect '}' after block.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncated_tokens() {
+        for source in ["print", "fun f(", "class A { m() {", "if (true) {"] {
+            let mut tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+            tokens.pop();
+            assert!(Parser::new(&tokens).parse().is_err(), "{source}");
+        }
+        assert!(Parser::new(&[]).parse().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        let source = "print -(1 + 2) * foo.bar(3, \"x\");\nvar a = b = c or nil;";
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let spanned: Vec<_> = statements
+            .iter()
+            .map(|s| {
+                let span = match s {
+                    Stmt::Print(p) => p.expression.span(),
+                    Stmt::Var(v) => v.initializer.as_ref().unwrap().span(),
+                    s => panic!("Unexpected statement {s:?}"),
+                };
+                (&source[span.start..span.end], span.line, span.column)
+            })
+            .collect();
+        assert_eq!(
+            spanned,
+            vec![
+                ("-(1 + 2) * foo.bar(3, \"x\")", 1, 7),
+                ("b = c or nil", 2, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_span() {
+        let source = "var x = 1;\nprint x +;";
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let Err(LoxResult::ParseError { causes }) = Parser::new(&tokens).parse() else {
+            panic!("Expected a parse error");
+        };
+        let span = causes[0].span.unwrap();
+        assert_eq!(
+            (span.line, span.column, &source[span.start..span.end]),
+            (2, 10, ";")
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{diagnostic::Diagnostic, lox_result::LoxResult};
+
+/// Where errors go once they reach the top level. Errors are only values until then, so a
+/// reporter is the one place that decides whether they are printed, kept or serialised.
+pub trait Reporter {
+    /// Reports one diagnostic about `source`, the text that was being run
+    fn report(&mut self, diagnostic: Diagnostic, source: &str);
+
+    /// Reports every diagnostic in `error`
+    fn report_error(&mut self, error: &LoxResult, source: &str) {
+        for diagnostic in error.diagnostics() {
+            self.report(diagnostic, source);
+        }
+    }
+}
+
+/// Prints diagnostics to stderr with the lines of source they point at
+pub struct StderrReporter {
+    /// Shown in the location of every diagnostic
+    file: String,
+    colour: bool,
+}
+
+impl StderrReporter {
+    pub fn new(file: &str, colour: bool) -> Self {
+        Self {
+            file: file.to_string(),
+            colour,
+        }
+    }
+}
+
+impl Reporter for StderrReporter {
+    fn report(&mut self, diagnostic: Diagnostic, source: &str) {
+        eprint!("{}", diagnostic.render(&self.file, source, self.colour));
+    }
+}
+
+/// Prints each diagnostic to stderr as one line of JSON, like `rustc --error-format=json`
+pub struct JsonReporter {
+    file: String,
+}
+
+impl JsonReporter {
+    pub fn new(file: &str) -> Self {
+        Self {
+            file: file.to_string(),
+        }
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn report(&mut self, diagnostic: Diagnostic, _source: &str) {
+        eprintln!("{}", diagnostic.to_json(&self.file));
+    }
+}
+
+/// Keeps diagnostics instead of showing them, for tests and embedders
+#[allow(unused)]
+#[derive(Debug, Default)]
+pub struct CollectingReporter {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Reporter for CollectingReporter {
+    fn report(&mut self, diagnostic: Diagnostic, _source: &str) {
+        self.diagnostics.push(diagnostic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CollectingReporter, Reporter};
+    use crate::{
+        error_code::ErrorCode, interpreter::Interpreter, parser::Parser, resolver::Resolver,
+        scanner::Scanner,
+    };
+
+    #[test]
+    fn test_collects_each_error_once() {
+        let source = "fun f() { return nil + 1; }\nf();\n";
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new();
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+        let error = interpreter.interpret(&statements).unwrap_err();
+
+        let mut reporter = CollectingReporter::default();
+        reporter.report_error(&error, source);
+        assert_eq!(reporter.diagnostics.len(), 1);
+        let diagnostic = &reporter.diagnostics[0];
+        assert_eq!(diagnostic.code, ErrorCode::InvalidOperand);
+        assert_eq!(
+            diagnostic.message,
+            "Operands must be two numbers or two strings."
+        );
+        assert_eq!(diagnostic.span.map(|span| span.line), Some(1));
+        assert_eq!(diagnostic.trace.len(), 2);
+    }
+}
@@ -1,11 +1,16 @@
 use std::collections::HashMap;
 
 use crate::{
-    expr::Expr,
-    interpreter::Interpreter,
+    diagnostic::{Diagnostic, Label},
+    error_code::ErrorCode,
+    expr::{Expr, ExprId},
+    interpreter::{Interpreter, Local},
+    lint::{Lint, Lints},
+    lox_class::CLASS_BINDING,
     lox_result::{LoxResult, ParseErrorCause},
     stmt::{FunctionStmt, Stmt},
-    token::Token,
+    suggest,
+    token::{Span, Token, TokenType},
 };
 
 #[derive(Clone, Copy)]
@@ -22,12 +27,53 @@ enum ClassType {
     Subclass,
 }
 
+/// What declared a local, for the warnings about it
+#[derive(Clone, Copy, PartialEq)]
+enum Binding {
+    Variable,
+    Function,
+    Class,
+    Enum,
+    Parameter,
+    /// Made by the interpreter, like `this`
+    Hidden,
+}
+
+impl Binding {
+    fn describe(self) -> &'static str {
+        match self {
+            Binding::Variable => "variable",
+            Binding::Function => "function",
+            Binding::Class => "class",
+            Binding::Enum => "enum",
+            Binding::Parameter => "parameter",
+            Binding::Hidden => "binding",
+        }
+    }
+}
+
+/// A variable declared in a local scope, numbered in the order of declaration
+struct Declared {
+    slot: usize,
+    defined: bool,
+    /// Whether anything reads it. Assignments don't count.
+    read: bool,
+    binding: Binding,
+    /// Name in the declaration, unknown for the ones the interpreter makes
+    span: Span,
+}
+
 pub struct Resolver<'a> {
     pub interpreter: &'a mut Interpreter,
-    scopes: Vec<HashMap<String, bool>>,
+    scopes: Vec<HashMap<String, Declared>>,
     current_function: FunctionType,
     current_class: ClassType,
+    /// Labels of the loops enclosing the current statement
+    loops: Vec<Option<String>>,
     errors: Vec<ParseErrorCause>,
+    lints: Lints,
+    /// Reported separately from `errors`, as they don't stop the script from running
+    warnings: Vec<Diagnostic>,
 }
 
 impl<'a> Resolver<'a> {
@@ -37,10 +83,24 @@ impl<'a> Resolver<'a> {
             scopes: vec![],
             current_function: FunctionType::None,
             current_class: ClassType::None,
+            loops: vec![],
             errors: vec![],
+            lints: Lints::default(),
+            warnings: vec![],
         }
     }
 
+    pub fn set_lints(&mut self, lints: Lints) {
+        self.lints = lints;
+    }
+
+    /// The warnings found so far in source order, which `resolve_stmts` doesn't return
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.sort_by_key(|warning| warning.span.map(|span| span.start));
+        warnings
+    }
+
     // TODO: Refactor
     pub fn resolve_stmts(&mut self, stmts: &[Stmt]) -> Result<(), LoxResult> {
         for s in stmts {
@@ -66,26 +126,48 @@ impl<'a> Resolver<'a> {
             }
             Stmt::Class(s) => {
                 let enclosing_class = std::mem::replace(&mut self.current_class, ClassType::Class);
-                self.declare(&s.name);
+                self.declare(&s.name, Binding::Class);
                 self.define(&s.name);
                 if let Some(superclass) = &s.superclass {
                     self.current_class = ClassType::Subclass;
 
                     if superclass.name.lexeme == s.name.lexeme {
-                        self.error(&s.name, "A class can't inherit from itself.");
+                        self.error(
+                            &s.name,
+                            ErrorCode::InheritsFromItself,
+                            "A class can't inherit from itself.",
+                        );
+                    }
+                    self.resolve_local(superclass.id, &superclass.name.lexeme, true);
+                }
+                // Mirrors the scope the interpreter creates for `super` and the private-access binding
+                self.begin_scope();
+                self.declare_hidden(CLASS_BINDING);
+                if s.superclass.is_some() {
+                    self.declare_hidden("super");
+                }
+                for method in &s.abstract_methods {
+                    if method.name.lexeme == "init" {
+                        self.error(
+                            &method.name,
+                            ErrorCode::AbstractInitializer,
+                            "An initializer can't be abstract.",
+                        );
+                    }
+                    let implemented = s.methods.iter().any(|m| match m {
+                        Stmt::Function(f) => f.name.lexeme == method.name.lexeme,
+                        _ => false,
+                    });
+                    if implemented {
+                        self.error(
+                            &method.name,
+                            ErrorCode::AbstractAndImplemented,
+                            "A method can't be both abstract and implemented in the same class.",
+                        );
                     }
-                    self.resolve_expr(&Expr::Variable(Box::new(superclass.clone())));
-                    self.begin_scope();
-                    self.scopes
-                        .last_mut()
-                        .unwrap()
-                        .insert("super".to_string(), true);
                 }
                 self.begin_scope();
-                self.scopes
-                    .last_mut()
-                    .unwrap()
-                    .insert("this".to_string(), true);
+                self.declare_hidden("this");
                 for method in &s.methods {
                     match method {
                         Stmt::Function(f) => {
@@ -100,38 +182,101 @@ impl<'a> Resolver<'a> {
                     }
                 }
                 self.end_scope();
-                if s.superclass.is_some() {
-                    self.end_scope();
-                }
+                self.end_scope();
                 self.current_class = enclosing_class;
             }
+            Stmt::Break(s) => self.resolve_jump(&s.keyword, &s.label),
+            Stmt::Continue(s) => self.resolve_jump(&s.keyword, &s.label),
+            Stmt::Defer(s) => {
+                if self.scopes.is_empty() {
+                    self.error(
+                        &s.keyword,
+                        ErrorCode::DeferOutsideBlock,
+                        "Can't use 'defer' outside of a block.",
+                    );
+                }
+                self.resolve_expr(&s.expression);
+            }
+            Stmt::DoWhile(s) => {
+                self.begin_loop(&s.label);
+                self.resolve_body(&s.body);
+                self.loops.pop();
+                self.resolve_expr(&s.condition);
+            }
+            Stmt::Enum(s) => {
+                self.declare(&s.name, Binding::Enum);
+                self.define(&s.name);
+                let mut variants = HashMap::new();
+                for variant in &s.variants {
+                    if variant.name.lexeme == "count" {
+                        self.error(
+                            &variant.name,
+                            ErrorCode::ReservedEnumName,
+                            "'count' is reserved for the number of variants.",
+                        );
+                    } else if let Some(first) = variants.insert(&variant.name.lexeme, &variant.name)
+                    {
+                        self.duplicate(
+                            &variant.name,
+                            first,
+                            ErrorCode::DuplicateEnumMember,
+                            "Already a variant with this name in this enum.",
+                        );
+                    }
+                    let mut fields = HashMap::new();
+                    for field in &variant.fields {
+                        if field.lexeme == "name" || field.lexeme == "ordinal" {
+                            self.error(
+                                field,
+                                ErrorCode::ReservedEnumName,
+                                &format!("'{}' is reserved for every variant.", field.lexeme),
+                            );
+                        } else if let Some(first) = fields.insert(&field.lexeme, field) {
+                            self.duplicate(
+                                field,
+                                first,
+                                ErrorCode::DuplicateEnumMember,
+                                "Already a field with this name in this variant.",
+                            );
+                        }
+                    }
+                }
+            }
             Stmt::Expression(s) => self.resolve_expr(&s.expression),
             Stmt::Function(s) => {
-                self.declare(&s.name);
+                self.declare(&s.name, Binding::Function);
                 self.define(&s.name);
                 self.resolve_function(s, FunctionType::Function);
             }
             Stmt::If(s) => {
                 self.resolve_expr(&s.condition);
-                self.resolve_stmt(&s.then_branch);
-                if let Some(else_branch) = s.else_branch.clone() {
-                    self.resolve_stmt(&else_branch);
+                self.resolve_body(&s.then_branch);
+                if let Some(else_branch) = &s.else_branch {
+                    self.resolve_body(else_branch);
                 }
             }
             Stmt::Print(s) => self.resolve_expr(&s.expression),
             Stmt::Return(s) => {
                 if matches!(self.current_function, FunctionType::None) {
-                    self.error(&s.keyword, "Can't return from top-level code.");
+                    self.error(
+                        &s.keyword,
+                        ErrorCode::TopLevelReturn,
+                        "Can't return from top-level code.",
+                    );
                 }
                 if let Some(v) = &s.value {
                     if matches!(self.current_function, FunctionType::Initializer) {
-                        self.error(&s.keyword, "Can't return a value from an initializer.");
+                        self.error(
+                            &s.keyword,
+                            ErrorCode::InitializerReturn,
+                            "Can't return a value from an initializer.",
+                        );
                     }
                     self.resolve_expr(v);
                 }
             }
             Stmt::Var(s) => {
-                self.declare(&s.name);
+                self.declare(&s.name, Binding::Variable);
                 if let Some(i) = s.initializer.as_ref() {
                     self.resolve_expr(i);
                 }
@@ -139,7 +284,12 @@ impl<'a> Resolver<'a> {
             }
             Stmt::While(s) => {
                 self.resolve_expr(&s.condition);
-                self.resolve_stmt(&s.body);
+                self.begin_loop(&s.label);
+                self.resolve_body(&s.body);
+                self.loops.pop();
+                if let Some(increment) = &s.increment {
+                    self.resolve_expr(increment);
+                }
             }
         }
     }
@@ -148,7 +298,7 @@ impl<'a> Resolver<'a> {
         match expr {
             Expr::Assign(e) => {
                 self.resolve_expr(&e.value);
-                self.resolve_local(expr, &e.name);
+                self.resolve_local(expr.id(), &e.name.lexeme, false);
             }
             Expr::Binary(e) => {
                 self.resolve_expr(&e.left);
@@ -168,43 +318,60 @@ impl<'a> Resolver<'a> {
             }
             Expr::Grouping(e) => self.resolve_expr(&e.expression),
             // Property dispatch is clearly dynamic since it is not processed during static resolution pass
-            Expr::Get(e) => self.resolve_expr(&e.object),
+            Expr::Get(e) => {
+                self.check_private_access(expr, &e.name);
+                self.resolve_expr(&e.object);
+            }
             Expr::Literal(_e) => {}
             Expr::Logical(e) => {
                 self.resolve_expr(&e.left);
                 self.resolve_expr(&e.right);
             }
             Expr::Set(e) => {
+                self.check_private_access(expr, &e.name);
                 self.resolve_expr(&e.value);
                 self.resolve_expr(&e.object);
             }
             Expr::Super(e) => match self.current_class {
                 ClassType::None => {
-                    self.error(&e.keyword, "Can't use 'super' outside of a class.");
+                    self.error(
+                        &e.keyword,
+                        ErrorCode::SuperOutsideClass,
+                        "Can't use 'super' outside of a class.",
+                    );
                 }
                 ClassType::Class => {
                     self.error(
                         &e.keyword,
+                        ErrorCode::SuperWithoutSuperclass,
                         "Can't use 'super' in a class with no superclass.",
                     );
                 }
-                ClassType::Subclass => self.resolve_local(expr, &e.keyword),
+                ClassType::Subclass => self.resolve_local(expr.id(), "super", true),
             },
             Expr::This(e) => {
                 if matches!(self.current_class, ClassType::None) {
-                    self.error(&e.keyword, "Can't use 'this' outside of a class.");
+                    self.error(
+                        &e.keyword,
+                        ErrorCode::ThisOutsideClass,
+                        "Can't use 'this' outside of a class.",
+                    );
                     return;
                 }
-                self.resolve_local(expr, &e.keyword);
+                self.resolve_local(expr.id(), "this", true);
             }
             Expr::Unary(e) => self.resolve_expr(&e.right),
             Expr::Variable(e) => {
                 if let Some(l) = self.scopes.last() {
-                    if l.get(&e.name.lexeme) == Some(&false) {
-                        self.error(&e.name, "Can't read local variable in its own initializer.");
+                    if l.get(&e.name.lexeme).is_some_and(|v| !v.defined) {
+                        self.error(
+                            &e.name,
+                            ErrorCode::OwnInitializer,
+                            "Can't read local variable in its own initializer.",
+                        );
                     }
                 }
-                self.resolve_local(expr, &e.name);
+                self.resolve_local(expr.id(), &e.name.lexeme, true);
             }
         }
     }
@@ -212,57 +379,378 @@ impl<'a> Resolver<'a> {
     fn resolve_function(&mut self, f: &FunctionStmt, current_function: FunctionType) {
         let enclosing_is_in_function =
             std::mem::replace(&mut self.current_function, current_function);
+        // Loops outside of the function can't be targeted by `break` or `continue` inside it
+        let enclosing_loops = std::mem::take(&mut self.loops);
 
         self.begin_scope();
         for p in f.params.iter() {
-            self.declare(p);
+            self.declare(p, Binding::Parameter);
             self.define(p);
         }
         for s in f.body.iter() {
             self.resolve_stmt(s)
         }
         self.end_scope();
+        self.loops = enclosing_loops;
         self.current_function = enclosing_is_in_function;
     }
 
+    /// Resolves the body of a loop or `if`. A `defer` there would be queued on the enclosing
+    /// block each time the body runs, so it has to be wrapped in a block of its own.
+    fn resolve_body(&mut self, body: &Stmt) {
+        match body {
+            Stmt::Defer(s) => {
+                self.error(
+                    &s.keyword,
+                    ErrorCode::DeferOutsideBlock,
+                    "Can't use 'defer' outside of a block.",
+                );
+                self.resolve_expr(&s.expression);
+            }
+            body => self.resolve_stmt(body),
+        }
+    }
+
+    fn begin_loop(&mut self, label: &Option<Token>) {
+        if let Some(label) = label {
+            if self.loops.iter().flatten().any(|l| l == &label.lexeme) {
+                self.error(
+                    label,
+                    ErrorCode::DuplicateLabel,
+                    "Label is already used by an enclosing loop.",
+                );
+            }
+        }
+        self.loops.push(label.as_ref().map(|l| l.lexeme.clone()));
+    }
+
+    fn resolve_jump(&mut self, keyword: &Token, label: &Option<Token>) {
+        if self.loops.is_empty() {
+            self.error(
+                keyword,
+                ErrorCode::OutsideLoop,
+                &format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+            );
+        } else if let Some(label) = label {
+            if !self.loops.iter().flatten().any(|l| l == &label.lexeme) {
+                self.error(
+                    label,
+                    ErrorCode::UndefinedLabel,
+                    "No enclosing loop has this label.",
+                );
+            }
+        }
+    }
+
+    /// Private members can only be accessed from inside a class body. Whether the object is an
+    /// instance of that class can only be checked at runtime, using the class scope's binding.
+    fn check_private_access(&mut self, expr: &Expr, name: &Token) {
+        if let TokenType::PrivateIdentifier(_) = name.token_type {
+            self.resolve_local(expr.id(), CLASS_BINDING, true);
+            if matches!(self.current_class, ClassType::None) {
+                self.error(
+                    name,
+                    ErrorCode::PrivateAccess,
+                    &format!(
+                        "Can't access private property '{}' outside of its class.",
+                        name.lexeme
+                    ),
+                );
+            }
+        }
+    }
+
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
 
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+        let mut unused: Vec<_> = scope
+            .into_iter()
+            .filter(|(name, v)| !v.read && v.binding != Binding::Hidden && !name.starts_with('_'))
+            .collect();
+        unused.sort_by_key(|(_, v)| v.slot);
+        for (name, v) in unused {
+            let lint = match v.binding {
+                Binding::Parameter => Lint::UnusedParameter,
+                _ => Lint::UnusedVariable,
+            };
+            let message = format!("Unused {} '{name}'.", v.binding.describe());
+            self.warn(lint, message, v.span);
+        }
     }
 
-    fn declare(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last() {
-            if scope.contains_key(&name.lexeme) {
-                self.error(name, "Already a variable with this name in this scope.");
+    fn declare(&mut self, name: &Token, binding: Binding) {
+        let shadowed = self.scopes.iter().rev().skip(1).find_map(|scope| {
+            let v = scope.get(&name.lexeme)?;
+            Some((v.binding, v.span)).filter(|_| v.binding != Binding::Hidden)
+        });
+        if let Some((shadowed, span)) = shadowed {
+            let message = format!(
+                "'{}' shadows a {} from an enclosing scope.",
+                name.lexeme,
+                shadowed.describe()
+            );
+            self.warn(Lint::Shadowing, message, name.span);
+            if let Some(warning) = self.warnings.last_mut() {
+                warning
+                    .labels
+                    .push(Label::new(span, "shadowed declaration"));
             }
         }
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), false);
+
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        if let Some(previous) = scope.get(&name.lexeme) {
+            let cause = ParseErrorCause::at(
+                name,
+                ErrorCode::DuplicateVariable,
+                "Already a variable with this name in this scope.",
+            )
+            .with_label(previous.span, "first declared here");
+            self.errors.push(cause);
+            return;
         }
+        let variable = Declared {
+            slot: scope.len(),
+            defined: false,
+            read: false,
+            binding,
+            span: name.span,
+        };
+        scope.insert(name.lexeme.clone(), variable);
     }
 
     fn define(&mut self, name: &Token) {
-        if let Some(scope) = self.scopes.last_mut() {
-            scope.insert(name.lexeme.clone(), true);
+        if let Some(variable) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.lexeme))
+        {
+            variable.defined = true;
+        }
+    }
+
+    /// Declares a variable the interpreter defines itself, such as `this`
+    fn declare_hidden(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        let slot = scope.len();
+        scope.insert(
+            name.to_string(),
+            Declared {
+                slot,
+                defined: true,
+                read: false,
+                binding: Binding::Hidden,
+                span: Span::default(),
+            },
+        );
+    }
+
+    /// Tells the interpreter where the node `id` finds `name`. Anything not found in a local
+    /// scope is a global, which may turn out not to exist, so a similar local is noted for the
+    /// error.
+    fn resolve_local(&mut self, id: ExprId, name: &str, read: bool) {
+        let local = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| {
+                let variable = scope.get_mut(name)?;
+                variable.read |= read;
+                Some(Local {
+                    depth,
+                    slot: variable.slot,
+                })
+            });
+        if local.is_none() {
+            let visible = self.scopes.iter().flat_map(|scope| scope.keys());
+            // Skip the hidden bindings, which can't be written as a variable
+            let visible = visible.filter(|n| !n.starts_with('#') && *n != "super");
+            if let Some(similar) = suggest::closest(name, visible.map(String::as_str)) {
+                self.interpreter.resolve_similar(id, similar);
+            }
+        }
+        self.interpreter.resolve(id, local);
+    }
+
+    fn warn(&mut self, lint: Lint, message: String, span: Span) {
+        if self.lints.is_enabled(lint) && span.is_known() {
+            self.warnings
+                .push(Diagnostic::warning(lint.code(), message, span));
         }
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
-        for (idx, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                self.interpreter.resolve(expr, idx);
+    fn error(&mut self, token: &Token, code: ErrorCode, message: &str) {
+        self.errors.push(ParseErrorCause::at(token, code, message));
+    }
+
+    /// An error at the second declaration of a name, pointing back at the first
+    fn duplicate(&mut self, token: &Token, first: &Token, code: ErrorCode, message: &str) {
+        let cause =
+            ParseErrorCause::at(token, code, message).with_label(first.span, "first declared here");
+        self.errors.push(cause);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        error_code::ErrorCode,
+        interpreter::Interpreter,
+        lint::{Lint, Lints},
+        lox_result::LoxResult,
+        parser::Parser,
+        scanner::Scanner,
+    };
+
+    use super::Resolver;
+
+    fn resolve(source: &str) -> Vec<String> {
+        let mut interpreter = Interpreter::new();
+        let mut scanner = Scanner::new(source);
+        let tokens = scanner.scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse().unwrap();
+        match Resolver::new(&mut interpreter).resolve_stmts(&statements) {
+            Ok(()) => vec![],
+            Err(LoxResult::ParseError { causes }) => {
+                causes.into_iter().map(|c| c.message).collect()
             }
+            Err(e) => panic!("Unexpected error {e:?}"),
         }
     }
 
-    fn error(&mut self, token: &Token, message: &str) {
-        self.errors.push(ParseErrorCause::new(
-            token.line,
-            Some(token.lexeme.clone()),
-            message,
-        ));
+    fn warnings(source: &str, lints: Lints) -> Vec<String> {
+        let mut interpreter = Interpreter::new();
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver.set_lints(lints);
+        resolver.resolve_stmts(&statements).unwrap();
+        let warnings = resolver.take_warnings();
+        warnings.into_iter().map(|w| w.message).collect()
+    }
+
+    #[test]
+    fn test_warnings() {
+        let source = "
+            var unused = 1;
+            fun f(a, _b, c) {
+                var x;
+                x = 1;
+                var _ignored;
+                fun g() { return c; }
+                { var c = 2; print c; }
+                class C {}
+                return g;
+            }
+            print f;
+        ";
+        let expected = [
+            "Unused parameter 'a'.",
+            "Unused variable 'x'.",
+            "'c' shadows a parameter from an enclosing scope.",
+            "Unused class 'C'.",
+        ];
+        assert_eq!(warnings(source, Lints::default()), expected);
+
+        let mut lints = Lints::default();
+        lints.set(Lint::Shadowing, false);
+        lints.set(Lint::UnusedParameter, false);
+        assert_eq!(
+            warnings(source, lints),
+            ["Unused variable 'x'.", "Unused class 'C'."]
+        );
+    }
+
+    #[test]
+    fn test_private_access_outside_class() {
+        assert_eq!(
+            resolve("class A { get() { return this.#x; } } var a = A(); a.#x = 1;"),
+            vec!["Can't access private property '#x' outside of its class."]
+        );
+        assert!(resolve("class A { get(other) { return other.#x; } }").is_empty());
+    }
+
+    #[test]
+    fn test_abstract_methods() {
+        assert_eq!(
+            resolve("class A { abstract init(); abstract get(); get() {} }"),
+            vec![
+                "An initializer can't be abstract.",
+                "A method can't be both abstract and implemented in the same class."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_enum_variants() {
+        assert_eq!(
+            resolve("enum E { A, A, count, B(name, x, x) }"),
+            vec![
+                "Already a variant with this name in this enum.",
+                "'count' is reserved for the number of variants.",
+                "'name' is reserved for every variant.",
+                "Already a field with this name in this variant.",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jumps_and_defer() {
+        assert_eq!(
+            resolve(
+                "break; defer 1; a: while (true) { a: loop { break b; } } \
+                 while (true) { fun f() { continue; } }"
+            ),
+            vec![
+                "Can't use 'break' outside of a loop.",
+                "Can't use 'defer' outside of a block.",
+                "Label is already used by an enclosing loop.",
+                "No enclosing loop has this label.",
+                "Can't use 'continue' outside of a loop.",
+            ]
+        );
+
+        // Loop and `if` bodies aren't blocks, even inside one
+        let defer = "Can't use 'defer' outside of a block.";
+        assert_eq!(
+            resolve(
+                "fun f(x) { \
+                 for (var i = 0; i < 3; i = i + 1) defer f(i); \
+                 while (x) defer f(1); do defer f(2); while (x); \
+                 if (x) defer f(3); else defer f(4); \
+                 if (x) { defer f(5); } }"
+            ),
+            vec![defer; 5]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_points_at_first_declaration() {
+        let source = "{\n  var a = 1;\n  var b;\n  var a = 2;\n  var a = 3;\n}";
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        let Err(LoxResult::ParseError { causes }) =
+            Resolver::new(&mut Interpreter::new()).resolve_stmts(&statements)
+        else {
+            panic!("Expected resolution errors");
+        };
+        let lines: Vec<_> = causes
+            .iter()
+            .map(|c| (c.line, c.labels[0].span.line, c.labels[0].message.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(4, 2, "first declared here"), (5, 2, "first declared here")]
+        );
+        assert!(causes
+            .iter()
+            .all(|c| c.code == ErrorCode::DuplicateVariable));
     }
 }
@@ -1,32 +1,55 @@
+use crate::error_code::ErrorCode;
 use crate::lox_result::{LoxResult, ParseErrorCause};
 
 use super::{Token, TokenType};
-use std::{collections::HashMap, iter::Peekable, str::Chars};
+use crate::token::Span;
+use std::{collections::HashMap, iter::Peekable, str::CharIndices};
 
 pub struct Scanner<'a> {
-    source: Peekable<Chars<'a>>,
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
     line: usize,
+    /// Byte offset of the first character of the current line
+    line_start: usize,
+    /// The last offset on the current line whose column was counted, and that column, so that
+    /// long lines aren't counted from their start for every token
+    counted: (usize, usize),
+    /// Where the token being scanned starts
+    start: usize,
+    start_line: usize,
+    start_column: usize,
     tokens: Vec<Token>,
     errors: Vec<ParseErrorCause>,
 }
 
 impl Scanner<'_> {
-    pub fn new(source: &str) -> Scanner {
+    pub fn new(source: &str) -> Scanner<'_> {
         Scanner {
-            source: source.chars().peekable(),
+            source,
+            chars: source.char_indices().peekable(),
             line: 1,
+            line_start: 0,
+            counted: (0, 1),
+            start: 0,
+            start_line: 1,
+            start_column: 1,
             tokens: Vec::new(),
             errors: Vec::new(),
         }
     }
 
     pub fn scan_tokens(&mut self) -> Result<&Vec<Token>, LoxResult> {
-        while let Some(ch) = self.source.next() {
+        while let Some((start, ch)) = self.chars.next() {
+            self.start = start;
+            self.start_line = self.line;
+            self.start_column = self.column(start);
             self.scan_token(ch);
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, "".to_owned(), self.line));
+        self.start = self.source.len();
+        self.start_line = self.line;
+        self.start_column = self.column(self.start);
+        self.add_token(TokenType::Eof, "".to_owned());
 
         if self.errors.is_empty() {
             Ok(&self.tokens)
@@ -39,13 +62,20 @@ impl Scanner<'_> {
     fn scan_token(&mut self, ch: char) {
         // TODO: Once cell this
         let keywords: HashMap<&'static str, TokenType> = HashMap::from([
+            ("abstract", TokenType::Abstract),
             ("and", TokenType::And),
+            ("break", TokenType::Break),
             ("class", TokenType::Class),
+            ("continue", TokenType::Continue),
+            ("defer", TokenType::Defer),
+            ("do", TokenType::Do),
             ("else", TokenType::Else),
+            ("enum", TokenType::Enum),
             ("false", TokenType::False),
             ("for", TokenType::For),
             ("fun", TokenType::Fun),
             ("if", TokenType::If),
+            ("loop", TokenType::Loop),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
@@ -58,171 +88,88 @@ impl Scanner<'_> {
         ]);
 
         match ch {
-            '(' => self
-                .tokens
-                .push(Token::new(TokenType::LeftParen, ch.to_string(), self.line)),
-            ')' => self
-                .tokens
-                .push(Token::new(TokenType::RightParen, ch.to_string(), self.line)),
-            '{' => self
-                .tokens
-                .push(Token::new(TokenType::LeftBrace, ch.to_string(), self.line)),
-            '}' => self
-                .tokens
-                .push(Token::new(TokenType::RightBrace, ch.to_string(), self.line)),
-            ',' => self
-                .tokens
-                .push(Token::new(TokenType::Comma, ch.to_string(), self.line)),
-            '.' => self
-                .tokens
-                .push(Token::new(TokenType::Dot, ch.to_string(), self.line)),
-            '-' => self
-                .tokens
-                .push(Token::new(TokenType::Minus, ch.to_string(), self.line)),
-            '+' => self
-                .tokens
-                .push(Token::new(TokenType::Plus, ch.to_string(), self.line)),
+            '(' => self.add_token(TokenType::LeftParen, ch.to_string()),
+            ')' => self.add_token(TokenType::RightParen, ch.to_string()),
+            '{' => self.add_token(TokenType::LeftBrace, ch.to_string()),
+            '}' => self.add_token(TokenType::RightBrace, ch.to_string()),
+            ',' => self.add_token(TokenType::Comma, ch.to_string()),
+            '.' => self.add_token(TokenType::Dot, ch.to_string()),
+            '-' => self.add_token(TokenType::Minus, ch.to_string()),
+            '+' => self.add_token(TokenType::Plus, ch.to_string()),
             // TODO: Colons are discarded, should they err if used without `?`
-            ':' => self
-                .tokens
-                .push(Token::new(TokenType::Colon, ch.to_string(), self.line)),
-            ';' => self
-                .tokens
-                .push(Token::new(TokenType::Semicolon, ch.to_string(), self.line)),
-            '*' => self
-                .tokens
-                .push(Token::new(TokenType::Star, ch.to_string(), self.line)),
-            '?' => self.tokens.push(Token::new(
-                TokenType::QuestionMark,
-                ch.to_string(),
-                self.line,
-            )),
-            '!' => {
-                if let Some(c) = self.source.next_if_eq(&'=') {
-                    self.tokens.push(Token::new(
-                        TokenType::BangEqual,
-                        String::from_iter([ch, c]),
-                        self.line,
-                    ))
-                } else {
-                    self.tokens
-                        .push(Token::new(TokenType::Bang, ch.to_string(), self.line))
-                }
-            }
-            '=' => {
-                if let Some(c) = self.source.next_if_eq(&'=') {
-                    self.tokens.push(Token::new(
-                        TokenType::EqualEqual,
-                        String::from_iter([ch, c]),
-                        self.line,
-                    ))
-                } else {
-                    self.tokens
-                        .push(Token::new(TokenType::Equal, ch.to_string(), self.line))
-                }
-            }
-            '<' => {
-                if let Some(c) = self.source.next_if_eq(&'=') {
-                    self.tokens.push(Token::new(
-                        TokenType::LessEqual,
-                        String::from_iter([ch, c]),
-                        self.line,
-                    ))
-                } else {
-                    self.tokens
-                        .push(Token::new(TokenType::Less, ch.to_string(), self.line))
-                }
-            }
-            '>' => {
-                if let Some(c) = self.source.next_if_eq(&'=') {
-                    self.tokens.push(Token::new(
-                        TokenType::GreaterEqual,
-                        String::from_iter([ch, c]),
-                        self.line,
-                    ))
-                } else {
-                    self.tokens
-                        .push(Token::new(TokenType::Greater, ch.to_string(), self.line))
-                }
-            }
+            ':' => self.add_token(TokenType::Colon, ch.to_string()),
+            ';' => self.add_token(TokenType::Semicolon, ch.to_string()),
+            '*' => self.add_token(TokenType::Star, ch.to_string()),
+            '?' => self.add_token(TokenType::QuestionMark, ch.to_string()),
+            '!' => self.one_or_two(ch, TokenType::Bang, TokenType::BangEqual),
+            '=' => self.one_or_two(ch, TokenType::Equal, TokenType::EqualEqual),
+            '<' => self.one_or_two(ch, TokenType::Less, TokenType::LessEqual),
+            '>' => self.one_or_two(ch, TokenType::Greater, TokenType::GreaterEqual),
             '/' => {
                 // Comment. ignore lexeme
-                if self.source.next_if_eq(&'/').is_some() {
-                    for next_ch in self.source.by_ref() {
-                        // Consume till newline
+                if self.chars.next_if(|&(_, c)| c == '/').is_some() {
+                    // Consume till newline
+                    while let Some((_, next_ch)) = self.chars.next() {
                         if next_ch == '\n' {
-                            self.line += 1;
+                            self.newline();
                             break;
                         }
                     }
-                } else if self.source.next_if_eq(&'*').is_some() {
+                } else if self.chars.next_if(|&(_, c)| c == '*').is_some() {
                     // Block comment. ignore lexeme
                     if self.scan_block_comment().is_err() {
-                        self.errors.push(ParseErrorCause::new(
-                            self.line,
-                            None,
+                        self.error(
+                            ErrorCode::UnterminatedComment,
                             "Unterminated block comment.",
-                        ))
+                        )
                     }
                 } else {
-                    self.tokens
-                        .push(Token::new(TokenType::Slash, ch.to_string(), self.line))
+                    self.add_token(TokenType::Slash, ch.to_string())
                 }
             }
             ' ' | '\r' | '\t' => {
                 // Skip whitespace
             }
-            '\n' => {
-                self.line += 1;
-            }
+            '\n' => self.newline(),
             '"' => {
                 // TODO: Handle escape sequences
                 let mut lexeme = Vec::new(); // reset text to trim first quote
                 let mut is_term = false;
-                for next_ch in self.source.by_ref() {
+                while let Some((_, next_ch)) = self.chars.next() {
                     if next_ch == '"' {
                         is_term = true;
                         break;
                     } else if next_ch == '\n' {
-                        self.line += 1;
+                        self.newline();
                     }
                     lexeme.push(next_ch);
                 }
                 if is_term {
                     let lexeme = String::from_iter(lexeme);
                     // TODO: Does this need to be in 2 places?
-                    self.tokens.push(Token::new(
-                        TokenType::String(lexeme.clone()),
-                        lexeme,
-                        self.line,
-                    ))
+                    self.add_token(TokenType::String(lexeme.clone()), lexeme)
                 } else {
-                    self.errors.push(ParseErrorCause::new(
-                        self.line,
-                        None,
-                        "Unterminated string.",
-                    ))
+                    self.error(ErrorCode::UnterminatedString, "Unterminated string.")
                 }
             }
             _ if ch.is_ascii_digit() => {
                 let mut char_num = vec![ch];
-                while let Some(next_ch) = self.source.next_if(|next_ch| next_ch.is_ascii_digit()) {
+                while let Some((_, next_ch)) = self.chars.next_if(|(_, c)| c.is_ascii_digit()) {
                     // Keep consuming while next is number
                     char_num.push(next_ch);
                 }
 
                 // No longer a number char
                 // Check if next is dot (for decimals)
-                if self.source.peek() == Some(&'.') {
+                if let Some((dot, _)) = self.chars.next_if(|&(_, c)| c == '.') {
                     // Append dot and consume
                     char_num.push('.');
-                    self.source.next();
 
                     // Peek next
-                    match self.source.peek() {
-                        Some(c) if c.is_ascii_digit() => {
-                            while let Some(next_ch) =
-                                self.source.next_if(|next_ch| next_ch.is_ascii_digit())
+                    match self.chars.peek() {
+                        Some((_, c)) if c.is_ascii_digit() => {
+                            while let Some((_, next_ch)) =
+                                self.chars.next_if(|(_, c)| c.is_ascii_digit())
                             {
                                 // Keep consuming while next is number
                                 char_num.push(next_ch);
@@ -230,71 +177,132 @@ impl Scanner<'_> {
                         }
                         _ => {
                             // Add the number, add the consumed dot as token
-                            self.push_num(&char_num);
-                            return self.tokens.push(Token::new(
-                                TokenType::Dot,
-                                ".".to_string(),
-                                self.line,
-                            ));
+                            char_num.pop();
+                            self.push_num(&char_num, dot);
+                            self.start = dot;
+                            self.start_column = self.column(dot);
+                            return self.add_token(TokenType::Dot, ".".to_string());
                         }
                     }
                 }
-                self.push_num(&char_num);
+                let end = self.offset();
+                self.push_num(&char_num, end);
             }
 
-            // TODO: allow unicode?
-            _ if ch.is_ascii_alphabetic() || ch == '_' => {
-                let mut lexeme = vec![ch]; // TODO: Capacity
-                while let Some(next_ch) = self
-                    .source
-                    .next_if(|ch| ch.is_ascii_alphanumeric() || ch == &'_')
-                {
-                    lexeme.push(next_ch);
+            // Private property or method name, e.g. `this.#secret`
+            '#' => match self
+                .chars
+                .next_if(|(_, ch)| ch.is_ascii_alphabetic() || ch == &'_')
+            {
+                Some((_, next_ch)) => {
+                    let lexeme = format!("#{}", self.identifier(next_ch));
+                    self.add_token(TokenType::PrivateIdentifier(lexeme.clone()), lexeme)
                 }
+                None => self.error(
+                    ErrorCode::MissingPrivateName,
+                    "Expect private name after '#'.",
+                ),
+            },
 
-                let lexeme = String::from_iter(lexeme);
+            // TODO: allow unicode?
+            _ if ch.is_ascii_alphabetic() || ch == '_' => {
+                let lexeme = self.identifier(ch);
                 let t_type = keywords.get(&lexeme.as_str());
                 match t_type {
-                    Some(t) => self.tokens.push(Token::new(t.clone(), lexeme, self.line)),
-                    None => self.tokens.push(Token::new(
-                        TokenType::Identifier(lexeme.clone()),
-                        lexeme,
-                        self.line,
-                    )),
+                    Some(t) => self.add_token(t.clone(), lexeme),
+                    None => self.add_token(TokenType::Identifier(lexeme.clone()), lexeme),
                 }
             }
-            _ => self.errors.push(ParseErrorCause::new(
-                self.line,
-                None,
-                "Unexpected character.",
-            )),
+            _ => self.error(ErrorCode::UnexpectedCharacter, "Unexpected character."),
+        }
+    }
+
+    /// Adds `two` if `first` is followed by '=', otherwise `one`
+    fn one_or_two(&mut self, first: char, one: TokenType, two: TokenType) {
+        match self.chars.next_if(|&(_, c)| c == '=') {
+            Some((_, c)) => self.add_token(two, String::from_iter([first, c])),
+            None => self.add_token(one, first.to_string()),
+        }
+    }
+
+    /// Adds a token from the start of the current one up to what has been consumed so far
+    fn add_token(&mut self, token_type: TokenType, lexeme: String) {
+        let end = self.offset();
+        let span = self.span_to(end);
+        self.tokens.push(Token::with_span(token_type, lexeme, span));
+    }
+
+    fn error(&mut self, code: ErrorCode, message: &str) {
+        let mut cause = ParseErrorCause::new(self.start_line, None, code, message);
+        let end = self.offset();
+        cause.span = Some(self.span_to(end));
+        self.errors.push(cause);
+    }
+
+    /// From the start of the current token to `end`
+    fn span_to(&self, end: usize) -> Span {
+        Span {
+            start: self.start,
+            end,
+            line: self.start_line,
+            column: self.start_column,
+        }
+    }
+
+    /// Byte offset of the next character
+    fn offset(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map_or(self.source.len(), |&(offset, _)| offset)
+    }
+
+    fn column(&mut self, offset: usize) -> usize {
+        let (from, column) = match self.counted {
+            (counted, column) if counted <= offset => (counted, column),
+            _ => (self.line_start, 1),
+        };
+        let column = column + self.source[from..offset].chars().count();
+        self.counted = (offset, column);
+        column
+    }
+
+    /// Called after consuming a '\n'
+    fn newline(&mut self) {
+        self.line += 1;
+        self.line_start = self.offset();
+        self.counted = (self.line_start, 1);
+    }
+
+    fn identifier(&mut self, first: char) -> String {
+        let mut lexeme = vec![first]; // TODO: Capacity
+        while let Some((_, next_ch)) = self
+            .chars
+            .next_if(|(_, ch)| ch.is_ascii_alphanumeric() || ch == &'_')
+        {
+            lexeme.push(next_ch);
         }
+        String::from_iter(lexeme)
     }
 
-    fn push_num(&mut self, char_num: &[char]) {
+    fn push_num(&mut self, char_num: &[char], end: usize) {
         let str_num = String::from_iter(char_num);
         let value = str_num.parse::<f64>().unwrap();
+        let span = self.span_to(end);
         self.tokens
-            .push(Token::new(TokenType::Number(value), str_num, self.line));
+            .push(Token::with_span(TokenType::Number(value), str_num, span));
     }
 
     fn scan_block_comment(&mut self) -> Result<(), ()> {
         // Consume till loop broken or EOF
-        while let Some(next_ch) = self.source.next() {
+        while let Some((_, next_ch)) = self.chars.next() {
             if next_ch == '\n' {
-                self.line += 1;
+                self.newline();
             } else if next_ch == '/' {
-                if let Some(next_next_ch) = self.source.next() {
-                    if next_next_ch == '*' {
-                        self.scan_block_comment()?;
-                    }
-                }
-            } else if next_ch == '*' {
-                if let Some(next_next_ch) = self.source.next() {
-                    if next_next_ch == '/' {
-                        return Ok(());
-                    }
+                if self.chars.next_if(|&(_, c)| c == '*').is_some() {
+                    self.scan_block_comment()?;
                 }
+            } else if next_ch == '*' && self.chars.next_if(|&(_, c)| c == '/').is_some() {
+                return Ok(());
             }
         }
         Err(())
@@ -372,3 +380,62 @@ fn test_comment() {
     assert_eq!(ttypes.len(), 1);
     assert_eq!(ttypes[0], &TokenType::Eof);
 }
+
+#[test]
+fn test_private_identifier() {
+    let source = "this.#secret # x".to_owned();
+    let mut scanner = Scanner::new(&source);
+    let errors = match scanner.scan_tokens() {
+        Err(LoxResult::ParseError { causes }) => causes,
+        _ => unreachable!("A lone '#' is an error"),
+    };
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Expect private name after '#'.");
+
+    let source = "this.#secret".to_owned();
+    let mut scanner = Scanner::new(&source);
+    let tokens = scanner.scan_tokens().unwrap();
+    assert_eq!(
+        tokens[2].token_type,
+        TokenType::PrivateIdentifier("#secret".to_owned())
+    );
+    assert_eq!(tokens[2].lexeme, "#secret");
+}
+
+#[test]
+fn test_spans() {
+    let source = "var s = \"é\nlines\" + \"é\";\n  x.y >= 1.5;";
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan_tokens().unwrap();
+    let spans: Vec<_> = tokens
+        .iter()
+        .map(|t| (&source[t.span.start..t.span.end], t.line, t.span.column))
+        .collect();
+    assert_eq!(
+        spans,
+        vec![
+            ("var", 1, 1),
+            ("s", 1, 5),
+            ("=", 1, 7),
+            // A string spanning lines is reported on the line it starts
+            ("\"é\nlines\"", 1, 9),
+            ("+", 2, 8),
+            ("\"é\"", 2, 10),
+            (";", 2, 13),
+            ("x", 3, 3),
+            (".", 3, 4),
+            ("y", 3, 5),
+            (">=", 3, 7),
+            ("1.5", 3, 10),
+            (";", 3, 13),
+            ("", 3, 14),
+        ]
+    );
+
+    let mut scanner = Scanner::new("1 +\n  @ 2;");
+    let Err(LoxResult::ParseError { causes }) = scanner.scan_tokens() else {
+        unreachable!("'@' is an unexpected character");
+    };
+    assert_eq!(causes[0].line, 2);
+    assert_eq!(causes[0].span.map(|s| (s.start, s.column)), Some((6, 3)));
+}
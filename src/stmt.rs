@@ -1,13 +1,18 @@
 use crate::{
     expr::{Expr, VariableExpr},
-    token::Token,
+    token::{Span, Token},
 };
 use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Stmt {
     Block(Box<BlockStmt>),
+    Break(Box<BreakStmt>),
     Class(Box<ClassStmt>),
+    Continue(Box<ContinueStmt>),
+    Defer(Rc<DeferStmt>),
+    DoWhile(Box<DoWhileStmt>),
+    Enum(Box<EnumStmt>),
     Expression(Box<ExpressionStmt>),
     Function(Rc<FunctionStmt>),
     If(Box<IfStmt>),
@@ -17,6 +22,30 @@ pub enum Stmt {
     While(Box<WhileStmt>),
 }
 
+impl Stmt {
+    /// Where the statement starts, as far as the syntax tree knows: the keyword or name for most
+    /// statements and the expression for the others. `None` for an empty block.
+    pub fn span(&self) -> Option<Span> {
+        let span = match self {
+            Stmt::Block(b) => return b.statements.first().and_then(Stmt::span),
+            Stmt::Break(b) => b.keyword.span,
+            Stmt::Class(c) => c.name.span,
+            Stmt::Continue(c) => c.keyword.span,
+            Stmt::Defer(d) => d.keyword.span,
+            Stmt::DoWhile(d) => return d.body.span(),
+            Stmt::Enum(e) => e.name.span,
+            Stmt::Expression(e) => e.expression.span(),
+            Stmt::Function(f) => f.name.span,
+            Stmt::If(i) => i.condition.span(),
+            Stmt::Print(p) => p.expression.span(),
+            Stmt::Return(r) => r.keyword.span,
+            Stmt::Var(v) => v.name.span,
+            Stmt::While(w) => w.condition.span(),
+        };
+        Some(span)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BlockStmt {
     pub statements: Vec<Stmt>,
@@ -28,23 +57,112 @@ impl BlockStmt {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BreakStmt {
+    pub keyword: Token,
+    pub label: Option<Token>,
+}
+
+impl BreakStmt {
+    pub fn new(keyword: Token, label: Option<Token>) -> Self {
+        Self { keyword, label }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ClassStmt {
     pub name: Token,
     pub superclass: Option<VariableExpr>,
     pub methods: Vec<Stmt>,
+    /// Methods without a body, which have to be implemented by a subclass before instantiation.
+    pub abstract_methods: Vec<Rc<FunctionStmt>>,
 }
 
 impl ClassStmt {
-    pub fn new(name: Token, methods: Vec<Stmt>, superclass: Option<VariableExpr>) -> Self {
+    pub fn new(
+        name: Token,
+        methods: Vec<Stmt>,
+        abstract_methods: Vec<Rc<FunctionStmt>>,
+        superclass: Option<VariableExpr>,
+    ) -> Self {
         Self {
             name,
             methods,
+            abstract_methods,
             superclass,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContinueStmt {
+    pub keyword: Token,
+    pub label: Option<Token>,
+}
+
+impl ContinueStmt {
+    pub fn new(keyword: Token, label: Option<Token>) -> Self {
+        Self { keyword, label }
+    }
+}
+
+/// Expression evaluated when the enclosing block exits, however it exits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeferStmt {
+    pub keyword: Token,
+    pub expression: Expr,
+}
+
+impl DeferStmt {
+    pub fn new(keyword: Token, expression: Expr) -> Self {
+        Self {
+            keyword,
+            expression,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DoWhileStmt {
+    pub body: Stmt,
+    pub condition: Expr,
+    pub label: Option<Token>,
+}
+
+impl DoWhileStmt {
+    pub fn new(body: Stmt, condition: Expr, label: Option<Token>) -> Self {
+        Self {
+            body,
+            condition,
+            label,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnumStmt {
+    pub name: Token,
+    pub variants: Vec<EnumVariant>,
+}
+
+impl EnumStmt {
+    pub fn new(name: Token, variants: Vec<EnumVariant>) -> Self {
+        Self { name, variants }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnumVariant {
+    pub name: Token,
+    pub fields: Vec<Token>,
+}
+
+impl EnumVariant {
+    pub fn new(name: Token, fields: Vec<Token>) -> Self {
+        Self { name, fields }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ExpressionStmt {
     pub expression: Expr,
@@ -101,11 +219,17 @@ impl PrintStmt {
 pub struct ReturnStmt {
     pub keyword: Token,
     pub value: Option<Expr>,
+    /// Set by the parser when the value is a call that can reuse the caller's frame
+    pub tail_call: bool,
 }
 
 impl ReturnStmt {
     pub fn new(keyword: Token, value: Option<Expr>) -> Self {
-        Self { keyword, value }
+        Self {
+            keyword,
+            value,
+            tail_call: false,
+        }
     }
 }
 
@@ -125,10 +249,18 @@ impl VarStmt {
 pub struct WhileStmt {
     pub condition: Expr,
     pub body: Stmt,
+    /// Increment clause of a desugared `for` loop, evaluated after every iteration
+    pub increment: Option<Expr>,
+    pub label: Option<Token>,
 }
 
 impl WhileStmt {
-    pub fn new(condition: Expr, body: Stmt) -> Self {
-        Self { condition, body }
+    pub fn new(condition: Expr, body: Stmt, increment: Option<Expr>, label: Option<Token>) -> Self {
+        Self {
+            condition,
+            body,
+            increment,
+            label,
+        }
     }
 }
@@ -0,0 +1,75 @@
+/// The candidate closest to `name` by edit distance, if any is close enough to be a likely typo:
+/// at most one edit for names of up to five characters, and a third of the length for longer
+/// ones. Ties go to the alphabetically first candidate, so suggestions don't depend on the
+/// iteration order of a `HashMap`.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// `message` followed by a suggestion of the candidate closest to `name`, if there is one
+pub fn did_you_mean<'a>(
+    message: String,
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> String {
+    match closest(name, candidates) {
+        Some(suggestion) => format!("{message} Did you mean '{suggestion}'?"),
+        None => message,
+    }
+}
+
+/// Optimal string alignment distance: insertions, deletions, substitutions and swaps of two
+/// adjacent characters each count as one edit, so `lenght` is one edit from `length`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // Rows of the distance matrix for the prefixes of `a` two, one and zero characters shorter
+    let mut before: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (previous[j] + 1)
+                .min(row[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        before = std::mem::replace(&mut previous, row);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{closest, edit_distance};
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("lenght", "length"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+    }
+
+    #[test]
+    fn test_closest() {
+        let names = ["length", "height", "width", "lengths"];
+        assert_eq!(closest("lenght", names), Some("length"));
+        assert_eq!(closest("widht", names), Some("width"));
+        assert_eq!(closest("depth", names), None);
+        assert_eq!(closest("length", names), Some("lengths"));
+        // One edit is all a short name allows
+        assert_eq!(closest("ab", ["ax", "xy"]), Some("ax"));
+        assert_eq!(closest("ab", ["xy"]), None);
+    }
+}
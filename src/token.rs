@@ -4,15 +4,63 @@ use std::fmt::Display;
 pub struct Token {
     pub token_type: TokenType,
     pub lexeme: String,
+    /// Line the token starts on
     pub line: usize,
+    pub span: Span,
 }
 
 impl Token {
+    /// A token that isn't from the source, e.g. one made up by desugaring. Only its line is known.
     pub fn new(token_type: TokenType, lexeme: String, line: usize) -> Token {
+        Token::with_span(token_type, lexeme, Span::line(line))
+    }
+
+    pub fn with_span(token_type: TokenType, lexeme: String, span: Span) -> Token {
         Token {
             token_type,
             lexeme,
+            line: span.line,
+            span,
+        }
+    }
+}
+
+/// Where a token or expression is in the source: byte offsets of its start and (exclusive) end,
+/// and the line and column it starts at. Lines and columns count from 1; columns are in
+/// characters.
+#[derive(Hash, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Somewhere on `line`, for code that wasn't scanned. Its column is 0.
+    pub fn line(line: usize) -> Span {
+        Span {
             line,
+            ..Span::default()
+        }
+    }
+
+    /// Whether the span points at actual source text rather than just a line
+    pub fn is_known(&self) -> bool {
+        self.column > 0
+    }
+
+    /// From the start of `self` to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        if !self.is_known() {
+            return other;
+        }
+        if !other.is_known() {
+            return self;
+        }
+        Span {
+            end: other.end,
+            ..self
         }
     }
 }
@@ -54,17 +102,25 @@ pub enum TokenType {
     Less,
     LessEqual,
     Identifier(String),
+    PrivateIdentifier(String),
     // --- Literals. ---
     String(String),
     Number(f64),
     // --- Keywords. ---
+    Abstract,
     And,
+    Break,
     Class,
+    Continue,
+    Defer,
+    Do,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    Loop,
     Nil,
     Or,
     Print,
@@ -78,9 +134,7 @@ pub enum TokenType {
 }
 
 // TODO: Verify
-impl Eq for TokenType {
-    fn assert_receiver_is_total_eq(&self) {}
-}
+impl Eq for TokenType {}
 
 // TODO: Verify
 impl std::hash::Hash for TokenType {
@@ -0,0 +1,323 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Formatter},
+    rc::Rc,
+};
+
+use crate::{chunk::Chunk, lox_class::next_class_id};
+
+/// Values of the bytecode VM. They mirror `Literal`, but functions hold compiled code and
+/// upvalues instead of a declaration and an environment.
+#[derive(Clone)]
+pub enum Value {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    /// Compiled code, only found in constant pools until wrapped in a closure
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Native(Rc<Native>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+    Enum(Rc<Enum>),
+    EnumVariant(Rc<EnumValue>),
+}
+
+impl Value {
+    /// Only `false` and `nil` are falsey, as in the tree-walker
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(l), Value::Boolean(r)) => l == r,
+            (Value::Number(l), Value::Number(r)) => l == r,
+            (Value::String(l), Value::String(r)) => l == r,
+            (Value::Function(l), Value::Function(r)) => Rc::ptr_eq(l, r),
+            (Value::Closure(l), Value::Closure(r)) => Rc::ptr_eq(l, r),
+            (Value::Native(l), Value::Native(r)) => Rc::ptr_eq(l, r),
+            (Value::Class(l), Value::Class(r)) => Rc::ptr_eq(l, r),
+            (Value::Instance(l), Value::Instance(r)) => Rc::ptr_eq(l, r),
+            (Value::BoundMethod(l), Value::BoundMethod(r)) => Rc::ptr_eq(l, r),
+            (Value::Enum(l), Value::Enum(r)) => l.name == r.name,
+            (Value::EnumVariant(l), Value::EnumVariant(r)) => l == r,
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{b}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Function(function) => write!(f, "{function}"),
+            Value::Closure(closure) => write!(f, "{}", closure.function),
+            Value::Native(_) => write!(f, "<native fn>"),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Value::BoundMethod(bound) => write!(f, "{}", bound.method.function),
+            Value::Enum(enumeration) => write!(f, "{}", enumeration.name),
+            Value::EnumVariant(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{s:?}"),
+            v => write!(f, "{v}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    /// Empty for the top-level script
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl Display for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+/// A variable captured by a closure. It points into the VM stack while the variable's scope is
+/// alive and holds the value itself once the scope has ended.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&[Value]) -> Value,
+}
+
+pub struct Class {
+    pub name: String,
+    /// Same as `LoxClass::id`
+    pub id: usize,
+    pub superclass: RefCell<Option<Rc<Class>>>,
+    pub methods: RefCell<HashMap<Rc<str>, Rc<Closure>>>,
+    pub abstract_methods: RefCell<Vec<Rc<str>>>,
+}
+
+impl Class {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            id: next_class_id(),
+            superclass: RefCell::new(None),
+            methods: RefCell::new(HashMap::new()),
+            abstract_methods: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<Closure>> {
+        if let Some(method) = self.methods.borrow().get(name) {
+            return Some(Rc::clone(method));
+        }
+        self.superclass.borrow().as_ref()?.find_method(name)
+    }
+
+    /// Names of the public methods of the class and its superclasses
+    pub fn method_names(&self) -> Vec<Rc<str>> {
+        let methods = self.methods.borrow();
+        let mut names: Vec<_> = methods
+            .keys()
+            .filter(|n| !n.starts_with('#'))
+            .cloned()
+            .collect();
+        if let Some(superclass) = self.superclass.borrow().as_ref() {
+            names.extend(superclass.method_names());
+        }
+        names
+    }
+
+    /// Private methods are only looked up in the class that declared them, never inherited.
+    pub fn find_private_method(&self, owner: &Rc<Class>, name: &str) -> Option<Rc<Closure>> {
+        if std::ptr::eq(self, owner.as_ref()) {
+            self.methods.borrow().get(name).cloned()
+        } else {
+            self.superclass
+                .borrow()
+                .as_ref()?
+                .find_private_method(owner, name)
+        }
+    }
+
+    pub fn inherits_from(&self, class: &Rc<Class>) -> bool {
+        std::ptr::eq(self, class.as_ref())
+            || self
+                .superclass
+                .borrow()
+                .as_ref()
+                .is_some_and(|superclass| superclass.inherits_from(class))
+    }
+
+    /// Same as `LoxClass::unimplemented_methods`
+    pub fn unimplemented_methods(&self) -> Vec<Rc<str>> {
+        let mut missing = Vec::new();
+        self.collect_unimplemented(&mut missing, &mut HashSet::new());
+        missing
+    }
+
+    fn collect_unimplemented(&self, missing: &mut Vec<Rc<str>>, seen: &mut HashSet<Rc<str>>) {
+        for name in self.abstract_methods.borrow().iter() {
+            if seen.insert(Rc::clone(name)) {
+                missing.push(Rc::clone(name));
+            }
+        }
+        seen.extend(self.methods.borrow().keys().cloned());
+        if let Some(superclass) = self.superclass.borrow().as_ref() {
+            superclass.collect_unimplemented(missing, seen);
+        }
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    pub fields: HashMap<Rc<str>, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+/// Same as `LoxEnum`
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<Rc<EnumValue>>,
+}
+
+impl Enum {
+    /// Each variant is given as its name and the names of its payload fields, if any.
+    pub fn new(name: &str, variants: Vec<(String, Vec<Rc<str>>)>) -> Self {
+        let variants = variants
+            .into_iter()
+            .enumerate()
+            .map(|(ordinal, (variant, fields))| {
+                Rc::new(EnumValue {
+                    variant: Rc::new(Variant {
+                        enum_name: name.to_string(),
+                        name: variant,
+                        ordinal,
+                        fields,
+                    }),
+                    payload: None,
+                })
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            variants,
+        }
+    }
+
+    pub fn find_variant(&self, name: &str) -> Option<Rc<EnumValue>> {
+        self.variants
+            .iter()
+            .find(|v| v.variant.name == name)
+            .cloned()
+    }
+
+    /// Same as `LoxEnum::variant_at`
+    pub fn variant_at(&self, ordinal: &Value) -> Option<Rc<EnumValue>> {
+        match ordinal {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 => {
+                self.variants.get(*n as usize).cloned()
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct Variant {
+    pub enum_name: String,
+    pub name: String,
+    pub ordinal: usize,
+    pub fields: Vec<Rc<str>>,
+}
+
+/// Same as `LoxEnumValue`
+pub struct EnumValue {
+    pub variant: Rc<Variant>,
+    pub payload: Option<Vec<Value>>,
+}
+
+impl EnumValue {
+    pub fn is_constructor(&self) -> bool {
+        !self.variant.fields.is_empty() && self.payload.is_none()
+    }
+
+    pub fn construct(&self, payload: Vec<Value>) -> Value {
+        Value::EnumVariant(Rc::new(EnumValue {
+            variant: Rc::clone(&self.variant),
+            payload: Some(payload),
+        }))
+    }
+
+    /// The payload field called `name`, if the variant has been constructed
+    pub fn field(&self, name: &str) -> Option<Value> {
+        let i = self
+            .variant
+            .fields
+            .iter()
+            .position(|f| f.as_ref() == name)?;
+        self.payload.as_ref()?.get(i).cloned()
+    }
+}
+
+impl PartialEq for EnumValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.variant.enum_name == other.variant.enum_name
+            && self.variant.ordinal == other.variant.ordinal
+            && self.payload == other.payload
+    }
+}
+
+impl Display for EnumValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.variant.enum_name, self.variant.name)?;
+        if let Some(payload) = &self.payload {
+            let payload: Vec<_> = payload.iter().map(|v| v.to_string()).collect();
+            write!(f, "({})", payload.join(", "))?;
+        }
+        Ok(())
+    }
+}
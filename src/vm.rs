@@ -0,0 +1,1150 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::SystemTime};
+
+use crate::{
+    chunk::OpCode,
+    error_code::ErrorCode,
+    interpreter::DEFAULT_MAX_CALL_DEPTH,
+    lox_result::{LoxResult, TraceFrame},
+    suggest,
+    token::{Span, Token, TokenType},
+    value::{BoundMethod, Class, Closure, Enum, Function, Instance, Native, Upvalue, Value},
+};
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Stack index of slot 0, which holds the function or the receiver
+    base: usize,
+    /// Functions that ran in this frame before `closure` replaced them with a tail call
+    tail_calls: usize,
+}
+
+impl CallFrame {
+    fn read_byte(&mut self) -> u8 {
+        let byte = self.closure.function.chunk.code[self.ip];
+        self.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let value = self.closure.function.chunk.read_u16(self.ip);
+        self.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16() as usize;
+        self.closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(name) => name,
+            value => unreachable!("Names are string constants, not {value:?}"),
+        }
+    }
+
+    /// Span of the code the instruction being executed was compiled from
+    fn span(&self) -> Span {
+        self.closure
+            .function
+            .chunk
+            .span_at(self.ip.saturating_sub(1))
+    }
+}
+
+/// Stack-based virtual machine running code from the `Compiler`. Behaves like the `Interpreter`,
+/// except that objects are only reference counted, so cycles between them aren't freed until the
+/// VM is dropped.
+pub struct Vm {
+    stack: Vec<Value>,
+    /// Frames of the calls waiting for the current one to return
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    /// Upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// Closures registered by `defer` in the blocks being executed, run last first
+    deferred: Vec<Rc<Closure>>,
+    max_call_depth: usize,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            deferred: Vec::new(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        };
+        vm.define_native(Native {
+            name: "clock",
+            arity: 0,
+            function: clock,
+        });
+        vm.define_native(Native {
+            name: "gc",
+            arity: 0,
+            function: gc,
+        });
+        vm
+    }
+
+    /// Limits how many Lox calls can be nested before "Stack overflow." is raised.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    fn define_native(&mut self, native: Native) {
+        self.globals
+            .insert(native.name.into(), Value::Native(Rc::new(native)));
+    }
+
+    pub fn interpret(&mut self, function: Rc<Function>) -> Result<(), LoxResult> {
+        let closure = Rc::new(Closure {
+            function,
+            upvalues: Vec::new(),
+        });
+        self.stack.push(Value::Closure(Rc::clone(&closure)));
+        let frame = CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+            tail_calls: 0,
+        };
+        let result = self.run(frame);
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+            self.deferred.clear();
+        }
+        result
+    }
+
+    fn run(&mut self, mut frame: CallFrame) -> Result<(), LoxResult> {
+        let mut result = self.execute(&mut frame);
+        if let Err(LoxResult::RuntimeError { trace, .. }) = &mut result {
+            // Deferred expressions are reported as part of the function that deferred them
+            let mut deferred_line = None;
+            for frame in std::iter::once(&frame).chain(self.frames.iter().rev()) {
+                let line = deferred_line.take().unwrap_or(frame.span().line);
+                let function = match frame.closure.function.name.as_str() {
+                    "" => "<script>",
+                    "defer" => {
+                        deferred_line = Some(line);
+                        continue;
+                    }
+                    name => name,
+                };
+                trace.push(TraceFrame {
+                    function: function.into(),
+                    line: Some(line),
+                    tail_calls: frame.tail_calls,
+                });
+            }
+        }
+        if result.is_err() {
+            self.run_remaining_deferred();
+        }
+        result
+    }
+
+    /// Runs what is still deferred once an error has stopped the program, last first. Errors
+    /// raised by those expressions are ignored, as in the tree-walker.
+    fn run_remaining_deferred(&mut self) {
+        self.frames.clear();
+        while let Some(closure) = self.deferred.pop() {
+            self.stack.push(Value::Closure(Rc::clone(&closure)));
+            let mut frame = CallFrame {
+                closure,
+                ip: 0,
+                base: self.stack.len() - 1,
+                tail_calls: 0,
+            };
+            if self.execute(&mut frame).is_err() {
+                self.frames.clear();
+            }
+        }
+    }
+
+    fn execute(&mut self, frame: &mut CallFrame) -> Result<(), LoxResult> {
+        loop {
+            let op = OpCode::from_byte(frame.read_byte()).expect("Invalid opcode");
+            match op {
+                OpCode::Constant => {
+                    let constant = frame.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Boolean(true)),
+                OpCode::False => self.stack.push(Value::Boolean(false)),
+                OpCode::Pop => {
+                    self.stack.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = frame.read_byte() as usize;
+                    let value = self.stack[frame.base + slot].clone();
+                    self.stack.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = frame.read_byte() as usize;
+                    self.stack[frame.base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = frame.read_name();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => {
+                            let message = self.undefined_variable(&name);
+                            return Err(error(frame, ErrorCode::UndefinedVariable, &message));
+                        }
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = frame.read_name();
+                    let value = self.stack.pop().unwrap();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = frame.read_name();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            let message = self.undefined_variable(&name);
+                            return Err(error(frame, ErrorCode::UndefinedVariable, &message));
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = frame.read_byte() as usize;
+                    let value = match &*frame.closure.upvalues[index].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = frame.read_byte() as usize;
+                    let value = self.peek(0).clone();
+                    match &mut *frame.closure.upvalues[index].borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = frame.read_name();
+                    let object = self.stack.pop().unwrap();
+                    let value = get_property(frame, object, &name)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetProperty => {
+                    let name = frame.read_name();
+                    let value = self.stack.pop().unwrap();
+                    let Value::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(error(
+                            frame,
+                            ErrorCode::NotAnInstance,
+                            "Only instances have fields.",
+                        ));
+                    };
+                    instance.borrow_mut().fields.insert(name, value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetPrivate => {
+                    let name = frame.read_name();
+                    let owner = self.pop_class();
+                    let Value::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(error(
+                            frame,
+                            ErrorCode::NotAnInstance,
+                            "Only instances have properties.",
+                        ));
+                    };
+                    check_private_access(frame, &instance.borrow(), &name, &owner)?;
+                    let field = instance
+                        .borrow()
+                        .fields
+                        .get(private_key(&owner, &name).as_str())
+                        .cloned();
+                    let value = match field {
+                        Some(value) => value,
+                        None => {
+                            let method = instance.borrow().class.find_private_method(&owner, &name);
+                            match method {
+                                Some(method) => bind(Value::Instance(instance), method),
+                                None => return Err(undefined_property(frame, &name, Vec::new())),
+                            }
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetPrivate => {
+                    let name = frame.read_name();
+                    let owner = self.pop_class();
+                    let value = self.stack.pop().unwrap();
+                    let Value::Instance(instance) = self.stack.pop().unwrap() else {
+                        return Err(error(
+                            frame,
+                            ErrorCode::NotAnInstance,
+                            "Only instances have fields.",
+                        ));
+                    };
+                    check_private_access(frame, &instance.borrow(), &name, &owner)?;
+                    let key = private_key(&owner, &name).into();
+                    instance.borrow_mut().fields.insert(key, value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = frame.read_name();
+                    let Some(Value::Class(superclass)) = self.stack.pop() else {
+                        unreachable!("'super' always holds a class");
+                    };
+                    let receiver = self.stack.pop().unwrap();
+                    match superclass.find_method(&name) {
+                        Some(method) => self.stack.push(bind(receiver, method)),
+                        None => {
+                            let names = superclass.method_names();
+                            return Err(undefined_property(frame, &name, names));
+                        }
+                    }
+                }
+                OpCode::Equal => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+                    self.stack.push(Value::Boolean(left == right));
+                }
+                OpCode::NotEqual => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+                    self.stack.push(Value::Boolean(left != right));
+                }
+                OpCode::Greater => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Boolean(left > right));
+                }
+                OpCode::GreaterEqual => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Boolean(left >= right));
+                }
+                OpCode::Less => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Boolean(left < right));
+                }
+                OpCode::LessEqual => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Boolean(left <= right));
+                }
+                OpCode::Add => {
+                    let right = self.stack.pop().unwrap();
+                    let left = self.stack.pop().unwrap();
+                    let value = match (left, right) {
+                        (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                        (Value::String(left), Value::String(right)) => {
+                            Value::String(format!("{left}{right}").into())
+                        }
+                        (Value::String(s), Value::Number(n)) => {
+                            Value::String(format!("{s}{n}").into())
+                        }
+                        (Value::Number(n), Value::String(s)) => {
+                            Value::String(format!("{n}{s}").into())
+                        }
+                        _ => {
+                            return Err(error(
+                                frame,
+                                ErrorCode::InvalidOperand,
+                                "Operands must be two numbers or two strings.",
+                            ))
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Subtract => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Number(left - right));
+                }
+                OpCode::Multiply => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    self.stack.push(Value::Number(left * right));
+                }
+                OpCode::Divide => {
+                    let (left, right) = self.pop_numbers(frame)?;
+                    if right == 0.0 {
+                        return Err(error(frame, ErrorCode::DivisionByZero, "Division by zero"));
+                    }
+                    self.stack.push(Value::Number(left / right));
+                }
+                OpCode::Not => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(Value::Boolean(!value.is_truthy()));
+                }
+                OpCode::Negate => match self.stack.pop().unwrap() {
+                    Value::Number(n) => self.stack.push(Value::Number(-n)),
+                    _ => {
+                        return Err(error(
+                            frame,
+                            ErrorCode::InvalidOperand,
+                            "Operand must be a number.",
+                        ))
+                    }
+                },
+                OpCode::Print => {
+                    let value = self.stack.pop().unwrap();
+                    println!("{value}");
+                }
+                OpCode::Jump => {
+                    let offset = frame.read_u16() as usize;
+                    frame.ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = frame.read_u16() as usize;
+                    if !self.peek(0).is_truthy() {
+                        frame.ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = frame.read_u16() as usize;
+                    frame.ip -= offset;
+                }
+                OpCode::Call => {
+                    let count = frame.read_byte() as usize;
+                    let callee = self.peek(count).clone();
+                    self.call_value(frame, callee, count)?;
+                }
+                OpCode::TailCall => {
+                    let count = frame.read_byte() as usize;
+                    let callee = self.peek(count).clone();
+                    let (method, receiver) = match callee {
+                        Value::Closure(closure) if closure.function.arity == count => {
+                            (closure, None)
+                        }
+                        Value::BoundMethod(bound) if bound.method.function.arity == count => {
+                            (Rc::clone(&bound.method), Some(bound.receiver.clone()))
+                        }
+                        // Called as usual, and returned by the instruction that follows
+                        callee => {
+                            self.call_value(frame, callee, count)?;
+                            continue;
+                        }
+                    };
+                    let start = self.stack.len() - count - 1;
+                    if let Some(receiver) = receiver {
+                        self.stack[start] = receiver;
+                    }
+                    self.close_upvalues(frame.base);
+                    self.stack.drain(frame.base..start);
+                    frame.closure = method;
+                    frame.ip = 0;
+                    frame.tail_calls += 1;
+                }
+                OpCode::Invoke => {
+                    let name = frame.read_name();
+                    let count = frame.read_byte() as usize;
+                    let instance = match self.peek(count).clone() {
+                        Value::Instance(instance) => instance,
+                        // Variants and payload fields are looked up like any property first
+                        object => {
+                            let callee = get_property(frame, object, &name)?;
+                            let slot = self.stack.len() - count - 1;
+                            self.stack[slot] = callee.clone();
+                            self.call_value(frame, callee, count)?;
+                            continue;
+                        }
+                    };
+                    let field = instance.borrow().fields.get(&name).cloned();
+                    if let Some(field) = field {
+                        let callee = self.stack.len() - count - 1;
+                        self.stack[callee] = field.clone();
+                        self.call_value(frame, field, count)?;
+                        continue;
+                    }
+                    let method = instance.borrow().class.find_method(&name);
+                    match method {
+                        Some(method) => self.call_closure(frame, method, count)?,
+                        None => {
+                            let names = property_names(&instance.borrow());
+                            return Err(undefined_property(frame, &name, names));
+                        }
+                    }
+                }
+                OpCode::Closure => {
+                    let Value::Function(function) = frame.read_constant() else {
+                        unreachable!("Closures are made of function constants");
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = frame.read_byte() == 1;
+                        let index = frame.read_byte() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(frame.base + index)
+                        } else {
+                            Rc::clone(&frame.closure.upvalues[index])
+                        });
+                    }
+                    let closure = Closure { function, upvalues };
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop();
+                }
+                OpCode::Return => {
+                    let result = self.stack.pop().unwrap();
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    match self.frames.pop() {
+                        Some(caller) => *frame = caller,
+                        None => return Ok(()),
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = frame.read_name();
+                    self.stack.push(Value::Class(Rc::new(Class::new(&name))));
+                }
+                OpCode::Inherit => {
+                    let Some(Value::Class(class)) = self.stack.pop() else {
+                        unreachable!("Only classes inherit");
+                    };
+                    let Value::Class(superclass) = self.peek(0) else {
+                        return Err(error(
+                            frame,
+                            ErrorCode::SuperclassNotClass,
+                            "Superclass must be a class.",
+                        ));
+                    };
+                    *class.superclass.borrow_mut() = Some(Rc::clone(superclass));
+                }
+                OpCode::Method => {
+                    let name = frame.read_name();
+                    let Some(Value::Closure(method)) = self.stack.pop() else {
+                        unreachable!("Methods are closures");
+                    };
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("Methods are added to classes");
+                    };
+                    class.methods.borrow_mut().insert(name, method);
+                }
+                OpCode::AbstractMethod => {
+                    let name = frame.read_name();
+                    let Value::Class(class) = self.peek(0) else {
+                        unreachable!("Methods are added to classes");
+                    };
+                    class.abstract_methods.borrow_mut().push(name);
+                }
+                OpCode::Defer => {
+                    let Some(Value::Closure(closure)) = self.stack.pop() else {
+                        unreachable!("Deferred expressions are compiled to closures");
+                    };
+                    self.deferred.push(closure);
+                }
+                OpCode::RunDeferred => {
+                    let closure = self.deferred.pop().expect("Nothing deferred to run");
+                    self.stack.push(Value::Closure(Rc::clone(&closure)));
+                    self.call_closure(frame, closure, 0)?;
+                }
+                OpCode::Enum => {
+                    let name = frame.read_name();
+                    let count = frame.read_byte();
+                    let variants = (0..count)
+                        .map(|_| {
+                            let variant = frame.read_name().to_string();
+                            let fields = frame.read_byte();
+                            (variant, (0..fields).map(|_| frame.read_name()).collect())
+                        })
+                        .collect();
+                    let enumeration = Enum::new(&name, variants);
+                    self.stack.push(Value::Enum(Rc::new(enumeration)));
+                }
+            }
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    /// Pops the class a private access is made from, which the compiler pushes last
+    fn pop_class(&mut self) -> Rc<Class> {
+        match self.stack.pop() {
+            Some(Value::Class(class)) => class,
+            _ => unreachable!("Private accesses push the class they are made from"),
+        }
+    }
+
+    fn pop_numbers(&mut self, frame: &CallFrame) -> Result<(f64, f64), LoxResult> {
+        let right = self.stack.pop().unwrap();
+        let left = self.stack.pop().unwrap();
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok((left, right)),
+            _ => Err(error(
+                frame,
+                ErrorCode::InvalidOperand,
+                "Operands must be numbers.",
+            )),
+        }
+    }
+
+    /// Calls the value below the `count` arguments on top of the stack. Lox functions get a frame
+    /// of their own, which becomes the current one.
+    fn call_value(
+        &mut self,
+        frame: &mut CallFrame,
+        callee: Value,
+        count: usize,
+    ) -> Result<(), LoxResult> {
+        let start = self.stack.len() - count - 1;
+        match callee {
+            Value::Closure(closure) => self.call_closure(frame, closure, count),
+            Value::BoundMethod(bound) => {
+                self.stack[start] = bound.receiver.clone();
+                self.call_closure(frame, Rc::clone(&bound.method), count)
+            }
+            Value::Class(class) => {
+                let missing = class.unimplemented_methods();
+                if !missing.is_empty() {
+                    let missing: Vec<_> = missing.iter().map(|m| format!("'{m}'")).collect();
+                    let message = format!(
+                        "Can't instantiate abstract class {} without an implementation of {}.",
+                        class.name,
+                        missing.join(", ")
+                    );
+                    return Err(error(frame, ErrorCode::AbstractInstantiation, &message));
+                }
+
+                let initializer = class.find_method("init");
+                let instance = Instance::new(class);
+                self.stack[start] = Value::Instance(Rc::new(RefCell::new(instance)));
+                match initializer {
+                    Some(initializer) => self.call_closure(frame, initializer, count),
+                    None => self.check_call(frame, 0, count),
+                }
+            }
+            Value::Native(native) => {
+                self.check_call(frame, native.arity, count)?;
+                let result = (native.function)(&self.stack[start + 1..]);
+                self.stack.truncate(start);
+                self.stack.push(result);
+                Ok(())
+            }
+            // Enums are called with an ordinal to look up a variant, e.g. `Color(0)`
+            Value::Enum(enumeration) => {
+                check_arity(frame, 1, count)?;
+                let ordinal = self.stack.pop().unwrap();
+                let Some(variant) = enumeration.variant_at(&ordinal) else {
+                    let message = format!(
+                        "Enum {} has no variant with ordinal {ordinal}.",
+                        enumeration.name
+                    );
+                    return Err(error(frame, ErrorCode::InvalidOrdinal, &message));
+                };
+                self.stack[start] = Value::EnumVariant(variant);
+                Ok(())
+            }
+            Value::EnumVariant(variant) if variant.is_constructor() => {
+                check_arity(frame, variant.variant.fields.len(), count)?;
+                let payload = self.stack.split_off(start + 1);
+                self.stack[start] = variant.construct(payload);
+                Ok(())
+            }
+            Value::Nil
+            | Value::Boolean(_)
+            | Value::Number(_)
+            | Value::String(_)
+            | Value::Function(_)
+            | Value::Instance(_)
+            | Value::EnumVariant(_) => Err(error(
+                frame,
+                ErrorCode::NotCallable,
+                "Can only call functions and classes.",
+            )),
+        }
+    }
+
+    fn call_closure(
+        &mut self,
+        frame: &mut CallFrame,
+        closure: Rc<Closure>,
+        count: usize,
+    ) -> Result<(), LoxResult> {
+        self.check_call(frame, closure.function.arity, count)?;
+        let callee = CallFrame {
+            closure,
+            ip: 0,
+            base: self.stack.len() - count - 1,
+            tail_calls: 0,
+        };
+        self.frames.push(std::mem::replace(frame, callee));
+        Ok(())
+    }
+
+    fn undefined_variable(&self, name: &str) -> String {
+        let message = format!("Undefined variable '{name}'.");
+        suggest::did_you_mean(message, name, self.globals.keys().map(|name| name.as_ref()))
+    }
+
+    /// Checks the arity, and that another call fits within the call depth limit
+    fn check_call(&self, frame: &CallFrame, arity: usize, count: usize) -> Result<(), LoxResult> {
+        check_arity(frame, arity, count)?;
+        if self.frames.len() >= self.max_call_depth {
+            return Err(error(frame, ErrorCode::StackOverflow, "Stack overflow."));
+        }
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .partition_point(|upvalue| open_slot(upvalue) < slot);
+        if let Some(upvalue) = self.open_upvalues.get(position) {
+            if open_slot(upvalue) == slot {
+                return Rc::clone(upvalue);
+            }
+        }
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.insert(position, Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Moves the variables at and above `slot` into the upvalues capturing them
+    fn close_upvalues(&mut self, slot: usize) {
+        let first = self
+            .open_upvalues
+            .partition_point(|upvalue| open_slot(upvalue) < slot);
+        for upvalue in self.open_upvalues.drain(first..) {
+            let value = self.stack[open_slot(&upvalue)].clone();
+            *upvalue.borrow_mut() = Upvalue::Closed(value);
+        }
+    }
+}
+
+fn open_slot(upvalue: &Rc<RefCell<Upvalue>>) -> usize {
+    match &*upvalue.borrow() {
+        Upvalue::Open(slot) => *slot,
+        Upvalue::Closed(_) => unreachable!("Closed upvalues are no longer tracked"),
+    }
+}
+
+fn check_arity(frame: &CallFrame, arity: usize, count: usize) -> Result<(), LoxResult> {
+    if count == arity {
+        Ok(())
+    } else {
+        let message = format!("Expected {arity} arguments but got {count}.");
+        Err(error(frame, ErrorCode::WrongArity, &message))
+    }
+}
+
+/// Reads a public property of an instance, a variant of an enum, or a field of a variant
+fn get_property(frame: &CallFrame, object: Value, name: &Rc<str>) -> Result<Value, LoxResult> {
+    match object {
+        Value::Instance(instance) => {
+            let field = instance.borrow().fields.get(name).cloned();
+            if let Some(value) = field {
+                return Ok(value);
+            }
+            let method = instance.borrow().class.find_method(name);
+            match method {
+                Some(method) => Ok(bind(Value::Instance(instance), method)),
+                None => {
+                    let names = property_names(&instance.borrow());
+                    Err(undefined_property(frame, name, names))
+                }
+            }
+        }
+        Value::Enum(enumeration) => enum_variant(frame, &enumeration, name),
+        Value::EnumVariant(value) => match name.as_ref() {
+            "name" => Ok(Value::String(value.variant.name.as_str().into())),
+            "ordinal" => Ok(Value::Number(value.variant.ordinal as f64)),
+            field => value.field(field).ok_or_else(|| {
+                let mut names = value.variant.fields.clone();
+                names.extend(["name".into(), "ordinal".into()]);
+                undefined_property(frame, field, names)
+            }),
+        },
+        _ => Err(error(
+            frame,
+            ErrorCode::NotAnInstance,
+            "Only instances have properties.",
+        )),
+    }
+}
+
+fn enum_variant(frame: &CallFrame, enumeration: &Enum, name: &str) -> Result<Value, LoxResult> {
+    if name == "count" {
+        return Ok(Value::Number(enumeration.variants.len() as f64));
+    }
+    match enumeration.find_variant(name) {
+        Some(variant) => Ok(Value::EnumVariant(variant)),
+        None => {
+            let names = enumeration.variants.iter().map(|v| v.variant.name.as_str());
+            let message = format!("Undefined variant '{name}'.");
+            let message = suggest::did_you_mean(message, name, names.chain(["count"]));
+            Err(error(frame, ErrorCode::UndefinedVariant, &message))
+        }
+    }
+}
+
+fn bind(receiver: Value, method: Rc<Closure>) -> Value {
+    Value::BoundMethod(Rc::new(BoundMethod { receiver, method }))
+}
+
+fn private_key(owner: &Class, name: &str) -> String {
+    format!("{}.{name}", owner.id)
+}
+
+fn check_private_access(
+    frame: &CallFrame,
+    instance: &Instance,
+    name: &str,
+    owner: &Rc<Class>,
+) -> Result<(), LoxResult> {
+    if instance.class.inherits_from(owner) {
+        Ok(())
+    } else {
+        let message = format!(
+            "Can't access private property '{name}' of a {} instance from class {}.",
+            instance.class.name, owner.name
+        );
+        Err(error(frame, ErrorCode::PrivateAccess, &message))
+    }
+}
+
+/// Public fields and methods of `instance`, to suggest in place of a missing one
+fn property_names(instance: &Instance) -> Vec<Rc<str>> {
+    let fields = instance.fields.keys().filter(|key| !key.contains('#'));
+    let mut names: Vec<_> = fields.cloned().collect();
+    names.extend(instance.class.method_names());
+    names
+}
+
+/// The error for a missing property, suggesting the closest of `names`
+fn undefined_property(frame: &CallFrame, name: &str, names: Vec<Rc<str>>) -> LoxResult {
+    let message = format!("Undefined property '{name}'.");
+    let names = names.iter().map(|name| name.as_ref());
+    error(
+        frame,
+        ErrorCode::UndefinedProperty,
+        &suggest::did_you_mean(message, name, names),
+    )
+}
+
+/// Errors are reported at the span the failing instruction was compiled from
+fn error(frame: &CallFrame, code: ErrorCode, message: &str) -> LoxResult {
+    let span = frame.span();
+    let token = Token::with_span(TokenType::Identifier(String::new()), String::new(), span);
+    LoxResult::runtime_error(&token, code, message)
+}
+
+/// Returns time in seconds from UNIX_EPOCH
+fn clock(_arguments: &[Value]) -> Value {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
+    Value::Number(now.map_or(0.0, |n| n.as_secs_f64()))
+}
+
+/// Objects are only reference counted, so there are never cycles for a collector to free
+fn gc(_arguments: &[Value]) -> Value {
+    Value::Number(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compiler::Compiler, interpreter::Interpreter, lox_result::LoxResult, parser::Parser,
+        resolver::Resolver, scanner::Scanner, token::Token, token::TokenType,
+    };
+
+    use super::Vm;
+
+    fn run_in(vm: &mut Vm, source: &str) -> Result<(), LoxResult> {
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        Resolver::new(&mut Interpreter::new()).resolve_stmts(&statements)?;
+        let function = Compiler::new().compile(&statements)?;
+        vm.interpret(function)
+    }
+
+    fn runtime_error(result: Result<(), LoxResult>) -> String {
+        match result {
+            Err(LoxResult::RuntimeError { message, .. }) => message,
+            r => panic!("Expected a runtime error, got {r:?}"),
+        }
+    }
+
+    /// Runs `source` on both backends and returns the global `result` as each one prints it
+    fn on_both(source: &str) -> (String, String) {
+        let mut vm = Vm::new();
+        run_in(&mut vm, source).unwrap();
+        let from_vm = vm.globals["result"].to_string();
+
+        let mut interpreter = Interpreter::new();
+        let tokens = Scanner::new(source).scan_tokens().unwrap().to_vec();
+        let statements = Parser::new(&tokens).parse().unwrap();
+        Resolver::new(&mut interpreter)
+            .resolve_stmts(&statements)
+            .unwrap();
+        interpreter.interpret(&statements).unwrap();
+        let name = Token::new(TokenType::Identifier("result".into()), "result".into(), 1);
+        let from_tree = interpreter.environment.borrow().get(&name).unwrap();
+        (from_vm, from_tree.to_string())
+    }
+
+    #[test]
+    fn test_stack_trace() {
+        let result = run_in(
+            &mut Vm::new(),
+            "fun f() {\n  return -nil;\n}\nfun g() {\n  var x = f();\n  return x;\n}\ng();",
+        );
+        let Err(LoxResult::RuntimeError { trace, .. }) = result else {
+            panic!("Expected a runtime error, got {result:?}");
+        };
+        let trace: Vec<_> = trace.iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(
+            trace,
+            ["at f (line 2)", "at g (line 5)", "at <script> (line 8)"]
+        );
+
+        let result = run_in(
+            &mut Vm::new(),
+            "fun f() {\n  return -nil;\n}\nfun g() { return f(); }\nfun h() { var x = g(); return x; }\nh();",
+        );
+        let Err(LoxResult::RuntimeError { trace, .. }) = result else {
+            panic!("Expected a runtime error, got {result:?}");
+        };
+        let trace: Vec<_> = trace.iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(
+            trace,
+            [
+                "at f (line 2) [1 tail call elided]",
+                "at h (line 5)",
+                "at <script> (line 6)"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let programs = [
+            "var result = 1 + 2 * 3 - 4 / 2;",
+            r#"var result = "n" + 1 + (2 > 1 ? "yes" : "no") + (nil or "x") + (1 and 2);"#,
+            r#"
+            fun counter() { var i = 0; fun inc() { i = i + 1; return i; } return inc; }
+            var c = counter(); c(); c();
+            var result = c();
+            "#,
+            r#"
+            var result = "";
+            outer: for (var i = 0; i < 4; i = i + 1) {
+                var j = 0;
+                do {
+                    j = j + 1;
+                    var k = i * 10 + j;
+                    fun f() { return k; }
+                    if (j == 2) continue;
+                    if (i == 3) break outer;
+                    result = result + f() + ",";
+                } while (j < 3);
+            }
+            "#,
+            r#"
+            class A {
+                init(name) { this.name = name; this.#secret = 1; }
+                greet() { return "I am " + this.name + this.#secret; }
+            }
+            class B < A {
+                init(name) { super.init(name + "!"); }
+                greet() { return super.greet() + " and B"; }
+            }
+            var result = B("b").greet();
+            "#,
+            r#"
+            class Shape { abstract area(); describe() { return "area " + this.area(); } }
+            class Square < Shape { init(side) { this.side = side; } area() { return this.side * this.side; } }
+            var square = Square(3);
+            var result = square.describe();
+            if (square.init(2) == square) result = result + " init returns this";
+            "#,
+        ];
+        for program in programs {
+            let (vm, tree) = on_both(program);
+            assert_eq!(vm, tree, "{program}");
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let cases = [
+            ("1 + nil;", "Operands must be two numbers or two strings."),
+            ("1 / 0;", "Division by zero"),
+            ("fun f(a) {} f();", "Expected 1 arguments but got 0."),
+            ("nil();", "Can only call functions and classes."),
+            ("print missing;", "Undefined variable 'missing'."),
+            (
+                "var missing = 1; print misisng;",
+                "Undefined variable 'misisng'. Did you mean 'missing'?",
+            ),
+            (
+                "class A { area() {} } A().aera();",
+                "Undefined property 'aera'. Did you mean 'area'?",
+            ),
+            ("class A {} A().missing();", "Undefined property 'missing'."),
+            ("var a = 1; class B < a {}", "Superclass must be a class."),
+            (
+                "class A { abstract f(); } A();",
+                "Can't instantiate abstract class A without an implementation of 'f'.",
+            ),
+            (
+                "class A { get(o) { return o.#x; } } class B {} A().get(B());",
+                "Can't access private property '#x' of a B instance from class A.",
+            ),
+            (
+                "fun make() { class A { init() { this.#x = 1; } get(o) { return o.#x; } } return A; }
+                make()().get(make()());",
+                "Can't access private property '#x' of a A instance from class A.",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(runtime_error(run_in(&mut Vm::new(), source)), message);
+        }
+    }
+
+    #[test]
+    fn test_stack_overflow_and_tail_calls() {
+        let mut vm = Vm::new();
+        vm.set_max_call_depth(50);
+        let result = run_in(&mut vm, "fun f(n) { if (n > 0) f(n - 1); } f(100);");
+        assert_eq!(runtime_error(result), "Stack overflow.");
+
+        // A frame is reused by calls in tail position, however deep the recursion
+        run_in(
+            &mut vm,
+            "fun count(n, acc) { if (n == 0) return acc; return count(n - 1, acc + 1); }
+             var result = count(10000, 0);",
+        )
+        .unwrap();
+        assert_eq!(vm.globals["result"].to_string(), "10000");
+        assert!(vm.frames.is_empty());
+    }
+
+    #[test]
+    fn test_defer() {
+        let program = r#"
+            var result = "";
+            fun work(fail) {
+                defer result = result + "1";
+                defer result = result + "2";
+                {
+                    var inner = "b";
+                    defer result = result + inner;
+                }
+                if (fail) return result + "early";
+                result = result + "e";
+            }
+            result = work(true) + "|";
+            work(false);
+            result = result + "|";
+            outer: for (var i = 0; i < 3; i = i + 1) {
+                defer result = result + i;
+                while (true) {
+                    defer result = result + "w";
+                    if (i == 0) continue outer;
+                    if (i == 1) break;
+                    break outer;
+                }
+            }
+            class File {
+                init(name) { defer this.open = true; this.name = name; }
+                read() { defer this.#reads = 1; return this.name; }
+            }
+            result = result + "|" + File("f").read() + (File("g").open ? " open" : "");
+        "#;
+        let (vm, tree) = on_both(program);
+        assert_eq!(vm, tree);
+        assert_eq!(vm, "bearly|be21|w0w1w2|f open");
+
+        // Whatever is still deferred runs after an error, and the trace leaves out the closures
+        let mut vm = Vm::new();
+        let result = run_in(
+            &mut vm,
+            "var log = \"\";\nfun f() {\n  defer log = log + \"f\";\n  {\n    defer log = log + \"b\";\n    defer -nil;\n  }\n}\nf();",
+        );
+        let Err(LoxResult::RuntimeError { trace, .. }) = result else {
+            panic!("Expected a runtime error, got {result:?}");
+        };
+        let trace: Vec<_> = trace.iter().map(|frame| frame.to_string()).collect();
+        assert_eq!(trace, ["at f (line 6)", "at <script> (line 9)"]);
+        assert_eq!(vm.globals["log"].to_string(), "bf");
+    }
+
+    #[test]
+    fn test_enums() {
+        let programs = [
+            r#"
+            enum Color { Red, Green, Blue }
+            var result = "";
+            for (var i = 0; i < Color.count; i = i + 1) {
+                result = result + Color(i).name + Color(i).ordinal;
+            }
+            "#,
+            "enum Color { Red, Green } var result = Color.Green == Color(1) and Color.Green != Color.Red;",
+            r#"
+            var result;
+            {
+                enum Shape { Circle(radius), Rect(width, height) }
+                var rect = Shape.Rect(2, 3);
+                result = rect.width * rect.height == 6 and Shape.Circle(1) == Shape.Circle(1);
+                if (result) result = Shape.Rect(rect.width, "x");
+            }
+            "#,
+            "enum Shape { Circle(radius) } var result = Shape.Circle;",
+            "enum Shape { Circle(radius) } var result = Shape;",
+        ];
+        for program in programs {
+            let (vm, tree) = on_both(program);
+            assert_eq!(vm, tree, "{program}");
+        }
+
+        let cases = [
+            (
+                "enum Color { Red } Color.Rde;",
+                "Undefined variant 'Rde'. Did you mean 'Red'?",
+            ),
+            (
+                "enum Color { Red } Color(1);",
+                "Enum Color has no variant with ordinal 1.",
+            ),
+            (
+                "enum Color { Red } Color();",
+                "Expected 1 arguments but got 0.",
+            ),
+            (
+                "enum Shape { Circle(radius) } Shape.Circle(1).raduis;",
+                "Undefined property 'raduis'. Did you mean 'radius'?",
+            ),
+            (
+                "enum Shape { Circle(radius) } Shape.Circle(1, 2);",
+                "Expected 1 arguments but got 2.",
+            ),
+            (
+                "enum Color { Red } Color.Red();",
+                "Can only call functions and classes.",
+            ),
+            (
+                "enum Color { Red } Color.Red.x = 1;",
+                "Only instances have fields.",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(runtime_error(run_in(&mut Vm::new(), source)), message);
+        }
+    }
+
+    #[test]
+    fn test_gc() {
+        // Nothing is ever left for a collector, since the VM only counts references
+        let mut vm = Vm::new();
+        run_in(&mut vm, "var result = gc();").unwrap();
+        assert_eq!(vm.globals["result"].to_string(), "0");
+    }
+}
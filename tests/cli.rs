@@ -0,0 +1,79 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+/// Where `test` saves its scripts, so tests running in parallel don't share files
+fn scratch_dir(test: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("loxer-{test}-{}", std::process::id()))
+}
+
+/// Runs the binary on `source` saved as `name` in the scratch directory of `test`, with `args`
+/// before the script
+fn run(test: &str, name: &str, source: &str, args: &[&str]) -> Output {
+    let dir = scratch_dir(test);
+    fs::create_dir_all(&dir).unwrap();
+    let script = dir.join(name);
+    fs::write(&script, source).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_loxer"))
+        .args(args)
+        .arg(&script)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_optimize_keeps_diagnostics() {
+    let run = |name, source, args: &[&str]| run("optimize", name, source, args);
+    let scripts = [
+        ("return.lox", "if (false) { return 1; }\nprint \"ok\";\n"),
+        ("unused.lox", "while (false) { var x = 1; }\nprint 1 + 2;\n"),
+        ("runtime.lox", "if (true) print 1 / 0;\n"),
+    ];
+    for (name, source) in scripts {
+        for backend in [&[][..], &["--vm"]] {
+            let args = [backend, &["--error-format=json"]].concat();
+            let plain = run(name, source, &args);
+            let optimized = run(name, source, &[&args[..], &["--optimize"]].concat());
+            assert_eq!(
+                String::from_utf8_lossy(&optimized.stderr),
+                String::from_utf8_lossy(&plain.stderr),
+                "{name} {backend:?}"
+            );
+            assert_eq!(optimized.status.code(), plain.status.code(), "{name}");
+            assert_eq!(optimized.stdout, plain.stdout, "{name}");
+        }
+    }
+
+    let returned = run("return.lox", scripts[0].1, &["--optimize"]);
+    assert!(String::from_utf8_lossy(&returned.stderr).contains("error[L0205]"));
+    assert_eq!(returned.status.code(), Some(65));
+    fs::remove_dir_all(scratch_dir("optimize")).unwrap();
+}
+
+#[test]
+fn test_backends_report_the_same_runtime_errors() {
+    let run = |name, source, args: &[&str]| run("backends", name, source, args);
+    let scripts = [
+        ("operands.lox", "var a = 1;\nprint a + nil;\n"),
+        (
+            "property.lox",
+            "fun f(o) {\n  return o.x;\n}\nprint f(nil);\n",
+        ),
+        ("call.lox", "var s = \"text\";\n  s(1, 2);\n"),
+    ];
+    for (name, source) in scripts {
+        let tree_walker = run(name, source, &[]);
+        let vm = run(name, source, &["--vm"]);
+        let stderr = String::from_utf8_lossy(&vm.stderr);
+        assert_eq!(
+            stderr,
+            String::from_utf8_lossy(&tree_walker.stderr),
+            "{name}"
+        );
+        assert!(stderr.contains(&format!("{name}:2:")), "{name}: {stderr}");
+        assert_eq!(vm.status.code(), Some(70), "{name}");
+    }
+    fs::remove_dir_all(scratch_dir("backends")).unwrap();
+}